@@ -0,0 +1,406 @@
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{mpsc, watch};
+use tokio::time::{sleep_until, Instant};
+use tokio_util::codec::{Decoder, Encoder, Framed};
+
+use crate::{
+  types::*,
+  error::{DecodeError, EncodeError},
+  session::{PacketIdAllocator, Inflight, OutgoingState, FlowControl, KeepAlive, KeepAliveAction, StoredSession, ExpiringMessage, TokioClock},
+  websocket::WebsocketMessage,
+  topic
+};
+use super::{Outgoing, SharedState};
+
+pub(crate) struct Connection<T, C> {
+  framed: Framed<T, C>,
+  state: SharedState,
+  shutdown: watch::Receiver<bool>,
+  client_id: String,
+  connection_id: u64,
+  session_expiry_interval: u32,
+  allocator: PacketIdAllocator,
+  inflight: Inflight,
+  flow_control: FlowControl,
+  keep_alive: KeepAlive<TokioClock>
+}
+
+// the reason the connection loop stopped
+enum Close {
+  // the network connection was lost
+  Lost,
+  // the client sent DISCONNECT
+  Disconnected(DisconnectPacket),
+  // the server closes the connection with this reason code
+  Disconnect(ReasonCode)
+}
+
+// what a transport carries: MQTT packets, and over websockets also control frames the connection answers
+pub(crate) trait Message: From<DecodedPacket> {
+  // the packet, or the message itself if it is a control frame
+  fn into_packet(self) -> Result<DecodedPacket, Self>;
+  fn reply(&self) -> Option<Self>;
+  fn is_close(&self) -> bool;
+}
+
+impl Message for DecodedPacket {
+  fn into_packet(self) -> Result<DecodedPacket, Self> {
+    Ok(self)
+  }
+
+  fn reply(&self) -> Option<Self> {
+    None
+  }
+
+  fn is_close(&self) -> bool {
+    false
+  }
+}
+
+impl Message for WebsocketMessage {
+  fn into_packet(self) -> Result<DecodedPacket, Self> {
+    match self {
+      WebsocketMessage::Packet(packet) => Ok(packet),
+      message => Err(message)
+    }
+  }
+
+  fn reply(&self) -> Option<Self> {
+    WebsocketMessage::reply(self)
+  }
+
+  fn is_close(&self) -> bool {
+    matches!(self, WebsocketMessage::Close(_))
+  }
+}
+
+// the next packet, answering control frames on the way; None once the transport is closed
+async fn next_packet<T, C>(framed: &mut Framed<T, C>) -> Option<Result<DecodedPacket, DecodeError>>
+where
+  T: AsyncRead + AsyncWrite + Unpin,
+  C: Decoder<Error = DecodeError> + Encoder<C::Item, Error = EncodeError>,
+  C::Item: Message
+{
+  loop {
+    let control = match framed.next().await? {
+      Ok(message) => match message.into_packet() {
+        Ok(packet) => return Some(Ok(packet)),
+        Err(control) => control
+      },
+      Err(error) => return Some(Err(error))
+    };
+
+    if let Some(reply) = control.reply() {
+      if framed.send(reply).await.is_err() {
+        return None;
+      }
+    }
+    if control.is_close() {
+      return None;
+    }
+  }
+}
+
+impl<T, C> Connection<T, C>
+where
+  T: AsyncRead + AsyncWrite + Unpin,
+  C: Decoder<Error = DecodeError> + Encoder<C::Item, Error = EncodeError>,
+  C::Item: Message
+{
+  pub(crate) fn new(framed: Framed<T, C>, state: SharedState, shutdown: watch::Receiver<bool>) -> Self {
+    Self {
+      framed,
+      state,
+      shutdown,
+      client_id: String::new(),
+      connection_id: 0,
+      session_expiry_interval: 0,
+      allocator: PacketIdAllocator::new(),
+      inflight: Inflight::new(),
+      flow_control: FlowControl::default(),
+      keep_alive: KeepAlive::with_clock(Role::Server, 0, TokioClock)
+    }
+  }
+
+  pub(crate) async fn run(mut self) {
+    let connect = match next_packet(&mut self.framed).await {
+      Some(Ok(DecodedPacket::Connect(packet))) => packet,
+      Some(Err(error)) => {
+        let _ = self.write(DecodedPacket::Connack(ConnackPacket {
+          session_present: false,
+          reason_code: error.reason_code(),
+          properties: Default::default()
+        })).await;
+        return;
+      },
+      _ => return
+    };
+
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let (connack, queued) = self.accept(connect, sender);
+    let close = match self.write(DecodedPacket::Connack(connack)).await {
+      Ok(_) => match self.resume(queued).await {
+        Ok(_) => self.drive(receiver).await,
+        Err(close) => close
+      },
+      Err(close) => close
+    };
+
+    let disconnect = match close {
+      Close::Disconnected(packet) => {
+        if let Some(interval) = packet.properties.session_expiry_interval {
+          self.session_expiry_interval = interval;
+        }
+        Some(packet)
+      },
+      Close::Disconnect(reason_code) => {
+        let _ = self.write(DecodedPacket::Disconnect(DisconnectPacket {
+          reason_code,
+          properties: Default::default()
+        })).await;
+        None
+      },
+      Close::Lost => None
+    };
+
+    let stored = StoredSession {
+      expiry_interval: self.session_expiry_interval,
+      inflight: self.inflight.outgoing().cloned().collect(),
+      received: self.inflight.received().collect(),
+      queued: self.flow_control.take_pending(),
+      ..StoredSession::default()
+    };
+    self.state.lock().unwrap().disconnect(&self.client_id, self.connection_id, disconnect.as_ref(), stored);
+  }
+
+  // returns the CONNACK and the messages queued while the client was offline
+  fn accept(&mut self, connect: ConnectPacket, sender: mpsc::UnboundedSender<Outgoing>) -> (ConnackPacket, Vec<PublishPacket>) {
+    let mut state = self.state.lock().unwrap();
+    let mut properties = ConnackProperties::default();
+
+    self.client_id = match connect.client_id.is_empty() {
+      true => {
+        let client_id = state.assign_client_id();
+        properties.assigned_client_identifier = Some(client_id.clone());
+        client_id
+      },
+      false => connect.client_id.clone()
+    };
+    if connect.properties.request_response_information == Some(1) {
+      properties.response_information = Some(format!("responses/{}", self.client_id));
+    }
+    let (connection_id, stored) = state.connect(&self.client_id, &connect, sender);
+    self.connection_id = connection_id;
+    self.session_expiry_interval = connect.properties.session_expiry_interval.unwrap_or(0);
+
+    self.flow_control.apply_connect(&connect);
+    self.keep_alive.set_keep_alive(connect.keep_alive);
+
+    let session_present = stored.is_some();
+    let mut queued = Vec::new();
+    if let Some(stored) = stored {
+      for outgoing in stored.inflight {
+        let packet_id = match &outgoing {
+          OutgoingState::Publish(packet) => packet.packet_id.unwrap_or(0),
+          OutgoingState::Release(packet_id) => *packet_id
+        };
+        if self.allocator.reserve(packet_id).is_ok() {
+          let _ = self.inflight.restore(outgoing);
+        }
+      }
+      for packet_id in stored.received {
+        self.inflight.restore_received(packet_id);
+      }
+      // messages that expired while waiting for the client are dropped
+      queued = stored.queued.iter().filter_map(|message| message.deliver(&TokioClock)).collect();
+    }
+
+    (ConnackPacket {
+      session_present,
+      reason_code: ReasonCode::Success,
+      properties
+    }, queued)
+  }
+
+  // resends unacknowledged messages of a resumed session, then the ones queued while offline
+  async fn resume(&mut self, queued: Vec<PublishPacket>) -> Result<(), Close> {
+    for packet in self.inflight.retransmit() {
+      self.write(packet).await?;
+    }
+    for packet in queued {
+      self.deliver(packet).await?;
+    }
+    Ok(())
+  }
+
+  async fn drive(&mut self, mut receiver: mpsc::UnboundedReceiver<Outgoing>) -> Close {
+    loop {
+      let deadline = self.keep_alive.next_deadline().map(Instant::from_std);
+
+      let result = tokio::select! {
+        biased;
+        _ = self.shutdown.changed() => Err(Close::Disconnect(ReasonCode::ServerShuttingDown)),
+        outgoing = receiver.recv() => match outgoing {
+          Some(Outgoing::Publish(packet)) => self.deliver(packet).await,
+          Some(Outgoing::Disconnect(reason_code)) => Err(Close::Disconnect(reason_code)),
+          None => Err(Close::Lost)
+        },
+        packet = next_packet(&mut self.framed) => match packet {
+          Some(Ok(DecodedPacket::Disconnect(packet))) => Err(Close::Disconnected(packet)),
+          Some(Ok(packet)) => self.handle_packet(packet).await,
+          Some(Err(error)) => Err(Close::Disconnect(error.reason_code())),
+          None => Err(Close::Lost)
+        },
+        _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+          match self.keep_alive.poll() {
+            Some(KeepAliveAction::Close) => Err(Close::Disconnect(ReasonCode::KeepAliveMethod)),
+            _ => Ok(())
+          }
+        }
+      };
+
+      if let Err(close) = result {
+        return close;
+      }
+    }
+  }
+
+  async fn handle_packet(&mut self, packet: DecodedPacket) -> Result<(), Close> {
+    self.keep_alive.received(&packet);
+
+    match packet {
+      DecodedPacket::Publish(packet) => self.handle_publish(packet).await,
+      DecodedPacket::Pubrel(packet) => {
+        let response = DecodedPacket::Pubcomp(self.inflight.handle_pubrel(&packet));
+        self.flow_control.respond(&response);
+        self.write(response).await
+      },
+      DecodedPacket::Puback(packet) => {
+        self.inflight.handle_puback(&packet).map_err(Close::Disconnect)?;
+        self.complete(DecodedPacket::Puback(packet)).await
+      },
+      DecodedPacket::Pubrec(packet) => {
+        match self.inflight.handle_pubrec(&packet).map_err(Close::Disconnect)? {
+          Some(pubrel) => self.write(DecodedPacket::Pubrel(pubrel)).await,
+          None => self.complete(DecodedPacket::Pubrec(packet)).await
+        }
+      },
+      DecodedPacket::Pubcomp(packet) => {
+        self.inflight.handle_pubcomp(&packet).map_err(Close::Disconnect)?;
+        self.complete(DecodedPacket::Pubcomp(packet)).await
+      },
+      DecodedPacket::Subscribe(packet) => self.handle_subscribe(packet).await,
+      DecodedPacket::Unsubscribe(packet) => {
+        let reason_codes = {
+          let mut state = self.state.lock().unwrap();
+          packet.topics.iter().map(|filter| match state.unsubscribe(&self.client_id, filter) {
+            true => ReasonCode::Success,
+            false => ReasonCode::NoSubscriptionExisted
+          }).collect()
+        };
+
+        self.write(DecodedPacket::Unsuback(UnsubackPacket {
+          packet_id: packet.packet_id,
+          reason_codes,
+          properties: Default::default()
+        })).await
+      },
+      DecodedPacket::PingReq(_) => self.write(DecodedPacket::PingResp(PingRespPacket {})).await,
+      _ => Err(Close::Disconnect(ReasonCode::ProtocolError))
+    }
+  }
+
+  async fn handle_publish(&mut self, packet: PublishPacket) -> Result<(), Close> {
+    if !topic::is_valid_name(&packet.topic) {
+      return Err(Close::Disconnect(ReasonCode::TopicNameInvalid));
+    }
+    self.flow_control.receive(&packet).map_err(Close::Disconnect)?;
+
+    let mut received = self.inflight.receive(packet);
+    if let Some(message) = received.message {
+      let published = self.state.lock().unwrap().publish(&self.client_id, &message);
+      if let (Err(_), Some(response)) = (published, received.response.as_mut()) {
+        self.inflight.refuse(response, ReasonCode::UnspecifiedError);
+      }
+    }
+
+    if let Some(response) = received.response {
+      self.flow_control.respond(&response);
+      self.write(response).await?;
+    }
+    Ok(())
+  }
+
+  async fn handle_subscribe(&mut self, packet: SubscribePacket) -> Result<(), Close> {
+    let mut reason_codes = Vec::new();
+    let mut retained = Vec::new();
+    {
+      let mut state = self.state.lock().unwrap();
+      for subscription in packet.subscriptions {
+        if !topic::is_valid_filter(&subscription.topic) {
+          reason_codes.push(ReasonCode::TopicFilterInvalid);
+          continue;
+        }
+
+        reason_codes.push(match subscription.qos {
+          QoS::AtMostOnce => ReasonCode::Success,
+          QoS::AtLeastOnce => ReasonCode::GrantedQoS1,
+          QoS::ExactlyOnce => ReasonCode::GrantedQoS2
+        });
+
+        retained.extend(state.subscribe(&self.client_id, subscription));
+      }
+    }
+
+    self.write(DecodedPacket::Suback(SubackPacket {
+      packet_id: packet.packet_id,
+      reason_codes,
+      properties: Default::default()
+    })).await?;
+
+    for message in retained {
+      self.deliver(message).await?;
+    }
+    Ok(())
+  }
+
+  async fn deliver(&mut self, packet: PublishPacket) -> Result<(), Close> {
+    match self.flow_control.send(ExpiringMessage::new(packet, &TokioClock)) {
+      Some(packet) => self.transmit(packet).await,
+      None => Ok(())
+    }
+  }
+
+  // sends a message flow control let through; a QoS 1 or 2 message only gets its packet id and
+  // in-flight entry now, so held back messages are never retransmitted as duplicates
+  async fn transmit(&mut self, mut packet: PublishPacket) -> Result<(), Close> {
+    if packet.config.qos > QoS::AtMostOnce {
+      let packet_id = match self.allocator.allocate() {
+        Ok(packet_id) => packet_id,
+        // every id is in use, so the message waits for the next acknowledgement
+        Err(_) => {
+          self.flow_control.hold(ExpiringMessage::new(packet, &TokioClock));
+          return Ok(());
+        }
+      };
+      packet.packet_id = Some(packet_id);
+      self.inflight.send(packet.clone()).map_err(Close::Disconnect)?;
+    }
+    self.write(DecodedPacket::Publish(packet)).await
+  }
+
+  async fn complete(&mut self, packet: DecodedPacket) -> Result<(), Close> {
+    let _ = self.allocator.acknowledge(&packet);
+    // a message that expired while held back is dropped [MQTT-3.3.2-5]
+    match self.flow_control.acknowledge(&packet, &TokioClock).next {
+      Some(next) => self.transmit(next).await,
+      None => Ok(())
+    }
+  }
+
+  async fn write(&mut self, packet: DecodedPacket) -> Result<(), Close> {
+    self.keep_alive.sent();
+    self.framed.send(C::Item::from(packet)).await.map_err(|_| Close::Lost)
+  }
+}
@@ -0,0 +1,693 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use bytes::BytesMut;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, watch, Notify};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep_until, Instant};
+use tokio_util::codec::{Decoder, Encoder, Framed, FramedParts};
+
+use crate::{
+  types::*,
+  codec::MQTTCodec,
+  websocket::{WebsocketCodec, WebsocketUpgradeCodec},
+  session::{self, RetainedStore, WillScheduler, SessionStore, MemorySessionStore, StoredSession, ExpiringMessage, TokioClock},
+  topic
+};
+
+mod connection;
+
+use connection::Connection;
+
+const MAXIMUM_UPGRADE_REQUEST: usize = 8192;
+
+// an in-process broker listening on localhost, meant for integration tests
+pub struct Broker {
+  tcp_address: SocketAddr,
+  websocket_address: SocketAddr,
+  shutdown: watch::Sender<bool>,
+  tasks: Vec<JoinHandle<()>>
+}
+
+pub(crate) enum Outgoing {
+  Publish(PublishPacket),
+  Disconnect(ReasonCode)
+}
+
+struct Session {
+  connection_id: u64,
+  subscriptions: Vec<SubscriptionConfig>,
+  // None while the client is offline, its messages are then queued in the session store
+  sender: Option<mpsc::UnboundedSender<Outgoing>>
+}
+
+pub(crate) struct State {
+  sessions: HashMap<String, Session>,
+  store: Box<dyn SessionStore + Send>,
+  retained: RetainedStore<TokioClock>,
+  wills: WillScheduler<TokioClock>,
+  wakeup: Arc<Notify>,
+  next_connection_id: u64
+}
+
+impl Default for State {
+  fn default() -> Self {
+    Self::with_store(Box::new(MemorySessionStore::with_clock(TokioClock)))
+  }
+}
+
+pub(crate) type SharedState = Arc<Mutex<State>>;
+
+impl Broker {
+  pub async fn start() -> io::Result<Broker> {
+    Self::start_with_store(MemorySessionStore::with_clock(TokioClock)).await
+  }
+
+  // sessions already in the store are resumed when their clients reconnect
+  pub async fn start_with_store<S: SessionStore + Send + 'static>(store: S) -> io::Result<Broker> {
+    let tcp_listener = TcpListener::bind("127.0.0.1:0").await?;
+    let websocket_listener = TcpListener::bind("127.0.0.1:0").await?;
+    let tcp_address = tcp_listener.local_addr()?;
+    let websocket_address = websocket_listener.local_addr()?;
+
+    let state = Arc::new(Mutex::new(State::with_store(Box::new(store))));
+    let (shutdown, shutdown_receiver) = watch::channel(false);
+
+    let tasks = vec![
+      tokio::spawn(Self::accept(tcp_listener, state.clone(), shutdown_receiver.clone(), false)),
+      tokio::spawn(Self::accept(websocket_listener, state.clone(), shutdown_receiver.clone(), true)),
+      tokio::spawn(Self::publish_wills(state, shutdown_receiver))
+    ];
+
+    Ok(Broker {
+      tcp_address,
+      websocket_address,
+      shutdown,
+      tasks
+    })
+  }
+
+  pub fn tcp_address(&self) -> SocketAddr {
+    self.tcp_address
+  }
+
+  pub fn websocket_address(&self) -> SocketAddr {
+    self.websocket_address
+  }
+
+  // closes the listeners and every open connection
+  pub async fn stop(self) {
+    let _ = self.shutdown.send(true);
+    for task in self.tasks {
+      let _ = task.await;
+    }
+  }
+
+  async fn accept(listener: TcpListener, state: SharedState, mut shutdown: watch::Receiver<bool>, websocket: bool) {
+    let mut connections = Vec::new();
+    loop {
+      tokio::select! {
+        accepted = listener.accept() => {
+          let stream = match accepted {
+            Ok((stream, _)) => stream,
+            Err(_) => continue
+          };
+          let state = state.clone();
+          let shutdown = shutdown.clone();
+          connections.push(tokio::spawn(async move {
+            if websocket {
+              if let Ok(framed) = Self::upgrade(stream).await {
+                Connection::new(framed, state, shutdown).run().await;
+              }
+            } else {
+              Connection::new(Framed::new(stream, MQTTCodec::new().with_role(Role::Server)), state, shutdown).run().await;
+            }
+          }));
+        },
+        _ = shutdown.changed() => break
+      }
+    }
+
+    for connection in connections {
+      let _ = connection.await;
+    }
+  }
+
+  async fn publish_wills(state: SharedState, mut shutdown: watch::Receiver<bool>) {
+    let wakeup = state.lock().unwrap().wakeup.clone();
+    loop {
+      let deadline = state.lock().unwrap().wills.next_deadline().map(Instant::from_std);
+      tokio::select! {
+        _ = wakeup.notified() => {},
+        _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {},
+        _ = shutdown.changed() => break
+      }
+
+      let mut state = state.lock().unwrap();
+      for will in state.wills.poll() {
+        let _ = state.publish("", &will);
+      }
+    }
+  }
+
+  async fn upgrade(mut stream: TcpStream) -> io::Result<Framed<TcpStream, WebsocketCodec>> {
+    let mut buffer = BytesMut::new();
+    let header_length = loop {
+      if let Some(position) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+        break position + 4;
+      }
+      if buffer.len() > MAXIMUM_UPGRADE_REQUEST || stream.read_buf(&mut buffer).await? == 0 {
+        return Err(io::ErrorKind::InvalidData.into());
+      }
+    };
+
+    let mut request = buffer.split_to(header_length);
+    let mut codec = WebsocketUpgradeCodec {};
+    let websocket_key = match codec.decode(&mut request) {
+      Ok(Some(websocket_key)) => websocket_key,
+      _ => return Err(io::ErrorKind::InvalidData.into())
+    };
+
+    let mut response = BytesMut::new();
+    if codec.encode(websocket_key, &mut response).is_err() {
+      return Err(io::ErrorKind::InvalidData.into());
+    }
+    stream.write_all(&response).await?;
+
+    // anything sent after the upgrade request already belongs to the websocket stream
+    let mut parts = FramedParts::new::<DecodedPacket>(stream, WebsocketCodec::with_codec(MQTTCodec::new().with_role(Role::Server)));
+    parts.read_buf = buffer;
+    Ok(Framed::from_parts(parts))
+  }
+}
+
+impl State {
+  fn with_store(mut store: Box<dyn SessionStore + Send>) -> Self {
+    let mut sessions = HashMap::new();
+    for client_id in store.client_ids().unwrap_or_default() {
+      if let Ok(Some(stored)) = store.load(&client_id) {
+        sessions.insert(client_id, Session {
+          connection_id: 0,
+          subscriptions: stored.subscriptions,
+          sender: None
+        });
+      }
+    }
+
+    Self {
+      sessions,
+      store,
+      retained: RetainedStore::with_clock(TokioClock),
+      wills: WillScheduler::with_clock(TokioClock),
+      wakeup: Arc::new(Notify::new()),
+      next_connection_id: 0
+    }
+  }
+
+  // delivers a message to every session with a matching subscription; a session whose message
+  // could not be queued is kept and routing carries on, the first error is returned at the end
+  pub(crate) fn route(&mut self, publisher: &str, packet: &PublishPacket) -> io::Result<()> {
+    let mut expired = Vec::new();
+    let mut result = Ok(());
+    for (client_id, session) in self.sessions.iter() {
+      let subscription = session.subscriptions.iter()
+        .filter(|subscription| topic::matches(&subscription.topic, &packet.topic))
+        .filter(|subscription| !(subscription.nl && client_id == publisher))
+        .max_by_key(|subscription| subscription.qos);
+
+      if let Some(subscription) = subscription {
+        let packet = session::forward(packet, subscription);
+        match &session.sender {
+          Some(sender) => {
+            let _ = sender.send(Outgoing::Publish(packet));
+          },
+          None => match self.store.queue(client_id, ExpiringMessage::new(packet, &TokioClock)) {
+            Ok(true) => {},
+            Ok(false) => expired.push(client_id.clone()),
+            Err(error) => if result.is_ok() {
+              result = Err(error);
+            }
+          }
+        }
+      }
+    }
+
+    for client_id in expired {
+      self.sessions.remove(&client_id);
+    }
+    result
+  }
+
+  pub(crate) fn publish(&mut self, publisher: &str, packet: &PublishPacket) -> io::Result<()> {
+    if packet.config.retain {
+      self.retained.store(packet);
+    }
+    self.route(publisher, packet)
+  }
+
+  // registers a connection for a client id, taking over any existing connection; returns the
+  // connection id and the session to resume, if there is one
+  pub(crate) fn connect(&mut self, client_id: &str, packet: &ConnectPacket, sender: mpsc::UnboundedSender<Outgoing>)
+    -> (u64, Option<StoredSession>) {
+
+    for will in self.wills.connect(client_id, packet) {
+      let _ = self.publish(client_id, &will);
+    }
+
+    // a connection that is taken over keeps its in-flight state, only the subscriptions carry over
+    let taken_over = match self.sessions.remove(client_id) {
+      Some(Session { sender: Some(sender), subscriptions, .. }) => {
+        let _ = sender.send(Outgoing::Disconnect(ReasonCode::SessionTakenOver));
+        Some(StoredSession {
+          subscriptions,
+          ..StoredSession::default()
+        })
+      },
+      _ => None
+    };
+
+    let stored = match packet.clean_start {
+      true => None,
+      false => self.store.load(client_id).unwrap_or(None).or(taken_over)
+    };
+    let _ = self.store.remove(client_id);
+
+    self.next_connection_id += 1;
+    self.sessions.insert(client_id.to_owned(), Session {
+      connection_id: self.next_connection_id,
+      subscriptions: stored.as_ref().map(|stored| stored.subscriptions.clone()).unwrap_or_default(),
+      sender: Some(sender)
+    });
+    (self.next_connection_id, stored)
+  }
+
+  // packet is the DISCONNECT sent by the client, if the connection ended with one; the session
+  // is kept in the store with the connection's in-flight state until its expiry interval passes
+  pub(crate) fn disconnect(&mut self, client_id: &str, connection_id: u64, packet: Option<&DisconnectPacket>,
+    mut stored: StoredSession) {
+
+    let session = match self.sessions.get_mut(client_id) {
+      Some(session) if session.connection_id == connection_id => session,
+      _ => return
+    };
+
+    self.wills.disconnect(client_id, packet, stored.expiry_interval);
+    self.wakeup.notify_one();
+
+    session.sender = None;
+    stored.subscriptions = session.subscriptions.clone();
+    if stored.expiry_interval == 0 || self.store.save(client_id, stored).is_err() {
+      self.sessions.remove(client_id);
+    }
+  }
+
+  // records the subscription and returns the retained messages it should receive
+  pub(crate) fn subscribe(&mut self, client_id: &str, subscription: SubscriptionConfig) -> Vec<PublishPacket> {
+    let session = match self.sessions.get_mut(client_id) {
+      Some(session) => session,
+      None => return Vec::new()
+    };
+
+    let existed = match session.subscriptions.iter_mut().find(|existing| existing.topic == subscription.topic) {
+      Some(existing) => {
+        *existing = subscription.clone();
+        true
+      },
+      None => {
+        session.subscriptions.push(subscription.clone());
+        false
+      }
+    };
+    self.retained.on_subscribe(&subscription, existed)
+  }
+
+  pub(crate) fn unsubscribe(&mut self, client_id: &str, filter: &str) -> bool {
+    match self.sessions.get_mut(client_id) {
+      Some(session) => {
+        let count = session.subscriptions.len();
+        session.subscriptions.retain(|subscription| subscription.topic != filter);
+        session.subscriptions.len() != count
+      },
+      None => false
+    }
+  }
+
+  pub(crate) fn assign_client_id(&mut self) -> String {
+    self.next_connection_id += 1;
+    format!("auto-{}", self.next_connection_id)
+  }
+}
+
+// the request tests share the connection helpers
+#[cfg(all(test, feature = "client"))]
+pub(crate) mod tests {
+  use std::convert::TryFrom;
+  use futures::{SinkExt, StreamExt};
+  use crate::client::{Client, Messages};
+  use crate::websocket::{WebsocketMessage, CloseFrame};
+  use super::*;
+
+  pub(crate) fn connect_packet(client_id: &str) -> ConnectPacket {
+    ConnectPacket::builder().client_id(client_id).build().unwrap()
+  }
+
+  fn subscribe_packet(filter: &str, qos: u8) -> SubscribePacket {
+    SubscribePacket {
+      packet_id: 0,
+      subscriptions: vec![SubscriptionConfig {
+        topic: filter.to_owned(),
+        retain_handling: RetainHandling::SendOnSubscribe,
+        rap: false,
+        nl: false,
+        qos: QoS::try_from(qos).unwrap()
+      }],
+      properties: Default::default()
+    }
+  }
+
+  pub(crate) fn publish_packet(topic: &str, payload: &str, qos: u8, retain: bool) -> PublishPacket {
+    PublishPacket::builder(topic).payload(payload).qos(QoS::try_from(qos).unwrap()).retain(retain).build().unwrap()
+  }
+
+  pub(crate) async fn connect_with(broker: &Broker, packet: ConnectPacket) -> (Client, Messages) {
+    let stream = TcpStream::connect(broker.tcp_address()).await.unwrap();
+    Client::connect(stream, packet).await.unwrap()
+  }
+
+  pub(crate) async fn connect(broker: &Broker, client_id: &str) -> (Client, Messages) {
+    connect_with(broker, connect_packet(client_id)).await
+  }
+
+  // a store that cannot queue messages for offline clients
+  struct QueueErrorStore(MemorySessionStore<TokioClock>);
+
+  impl SessionStore for QueueErrorStore {
+    fn save(&mut self, client_id: &str, session: StoredSession) -> io::Result<()> {
+      self.0.save(client_id, session)
+    }
+
+    fn load(&mut self, client_id: &str) -> io::Result<Option<StoredSession>> {
+      self.0.load(client_id)
+    }
+
+    fn remove(&mut self, client_id: &str) -> io::Result<()> {
+      self.0.remove(client_id)
+    }
+
+    fn queue(&mut self, _client_id: &str, _message: ExpiringMessage) -> io::Result<bool> {
+      Err(io::ErrorKind::Other.into())
+    }
+
+    fn client_ids(&mut self) -> io::Result<Vec<String>> {
+      self.0.client_ids()
+    }
+
+    fn purge_expired(&mut self) -> io::Result<Vec<String>> {
+      self.0.purge_expired()
+    }
+  }
+
+  #[tokio::test]
+  async fn route_test() {
+    let broker = Broker::start().await.unwrap();
+    let (subscriber, mut messages) = connect(&broker, "subscriber").await;
+    let (publisher, _) = connect(&broker, "publisher").await;
+
+    let reason_codes = subscriber.subscribe(subscribe_packet("sensors/+/temperature", 2)).await.unwrap();
+    assert_eq!(reason_codes, vec![ReasonCode::GrantedQoS2]);
+
+    assert_eq!(publisher.publish(publish_packet("sensors/a/humidity", "1", 0, false)).await.unwrap(), ReasonCode::Success);
+    for qos in 0..3 {
+      let reason_code = publisher.publish(publish_packet("sensors/a/temperature", &qos.to_string(), qos, false)).await.unwrap();
+      assert_eq!(reason_code, ReasonCode::Success);
+
+      let message = messages.next().await.unwrap();
+      assert_eq!(message.topic, "sensors/a/temperature");
+      assert_eq!(message.payload, qos.to_string());
+      assert_eq!(u8::from(message.config.qos), qos);
+    }
+
+    broker.stop().await;
+  }
+
+  #[tokio::test]
+  async fn downgrade_qos_test() {
+    let broker = Broker::start().await.unwrap();
+    let (subscriber, mut messages) = connect(&broker, "subscriber").await;
+    let (publisher, _) = connect(&broker, "publisher").await;
+
+    subscriber.subscribe(subscribe_packet("test", 1)).await.unwrap();
+    publisher.publish(publish_packet("test", "hello", 2, false)).await.unwrap();
+
+    let message = messages.next().await.unwrap();
+    assert_eq!(message.config.qos, QoS::AtLeastOnce);
+    broker.stop().await;
+  }
+
+  #[tokio::test]
+  async fn retained_test() {
+    let broker = Broker::start().await.unwrap();
+    let (publisher, _) = connect(&broker, "publisher").await;
+
+    publisher.publish(publish_packet("status", "online", 1, true)).await.unwrap();
+
+    let (subscriber, mut messages) = connect(&broker, "subscriber").await;
+    subscriber.subscribe(subscribe_packet("#", 1)).await.unwrap();
+
+    let message = messages.next().await.unwrap();
+    assert_eq!(message.payload, "online");
+    assert!(message.config.retain);
+
+    // an empty retained payload clears the topic
+    publisher.publish(publish_packet("status", "", 1, true)).await.unwrap();
+    let message = messages.next().await.unwrap();
+    assert_eq!(message.payload, "");
+
+    let (late_subscriber, mut late_messages) = connect(&broker, "late").await;
+    late_subscriber.subscribe(subscribe_packet("#", 1)).await.unwrap();
+    publisher.publish(publish_packet("other", "fresh", 0, false)).await.unwrap();
+    assert_eq!(late_messages.next().await.unwrap().payload, "fresh");
+
+    broker.stop().await;
+  }
+
+  #[tokio::test]
+  async fn retain_handling_test() {
+    let broker = Broker::start().await.unwrap();
+    let (publisher, _) = connect(&broker, "publisher").await;
+    publisher.publish(publish_packet("status", "online", 1, true)).await.unwrap();
+
+    let (subscriber, mut messages) = connect(&broker, "subscriber").await;
+    let mut subscribe = subscribe_packet("status", 1);
+    subscribe.subscriptions[0].retain_handling = RetainHandling::DoNotSend;
+    subscribe.subscriptions[0].rap = true;
+    subscriber.subscribe(subscribe).await.unwrap();
+
+    // nothing is replayed, and live retained messages keep their flag with Retain As Published
+    publisher.publish(publish_packet("status", "offline", 1, true)).await.unwrap();
+    let message = messages.next().await.unwrap();
+    assert_eq!(message.payload, "offline");
+    assert!(message.config.retain);
+
+    broker.stop().await;
+  }
+
+  #[tokio::test]
+  async fn will_test() {
+    let broker = Broker::start().await.unwrap();
+    let (subscriber, mut messages) = connect(&broker, "subscriber").await;
+    subscriber.subscribe(subscribe_packet("status/#", 1)).await.unwrap();
+
+    let mut packet = connect_packet("lost");
+    packet.will_config = Some(WillConfig {
+      topic: "status/lost".to_owned(),
+      payload: "offline".to_owned(),
+      retain: false,
+      qos: QoS::AtLeastOnce,
+      properties: Default::default()
+    });
+
+    // a dropped connection publishes the will
+    let mut framed = Framed::new(TcpStream::connect(broker.tcp_address()).await.unwrap(), MQTTCodec::new());
+    framed.send(DecodedPacket::Connect(packet.clone())).await.unwrap();
+    framed.next().await.unwrap().unwrap();
+    drop(framed);
+    assert_eq!(messages.next().await.unwrap().payload, "offline");
+
+    // a normal disconnect discards it, while DisconnectWithWill publishes it
+    for (reason_code, payload) in [(ReasonCode::Success, "unused"), (ReasonCode::DisconnectWithWill, "gone")].iter() {
+      packet.will_config.as_mut().unwrap().payload = payload.to_string();
+      let stream = TcpStream::connect(broker.tcp_address()).await.unwrap();
+      let (client, _) = Client::connect(stream, packet.clone()).await.unwrap();
+      client.disconnect(DisconnectPacket { reason_code: *reason_code, properties: Default::default() }).await.unwrap();
+    }
+    assert_eq!(messages.next().await.unwrap().payload, "gone");
+
+    broker.stop().await;
+  }
+
+  #[tokio::test]
+  async fn persistent_session_test() {
+    let broker = Broker::start().await.unwrap();
+    let (publisher, _) = connect(&broker, "publisher").await;
+
+    let mut packet = connect_packet("persistent");
+    packet.clean_start = false;
+    packet.properties.session_expiry_interval = Some(60);
+
+    let stream = TcpStream::connect(broker.tcp_address()).await.unwrap();
+    let (client, _) = Client::connect(stream, packet.clone()).await.unwrap();
+    assert!(!client.connack().session_present);
+    client.subscribe(subscribe_packet("offline/#", 1)).await.unwrap();
+    client.disconnect(DisconnectPacket { reason_code: ReasonCode::Success, properties: Default::default() }).await.unwrap();
+
+    publisher.publish(publish_packet("offline/a", "queued", 1, false)).await.unwrap();
+
+    let stream = TcpStream::connect(broker.tcp_address()).await.unwrap();
+    let (client, mut messages) = Client::connect(stream, packet.clone()).await.unwrap();
+    assert!(client.connack().session_present);
+    assert_eq!(messages.next().await.unwrap().payload, "queued");
+
+    // the subscription survived as well
+    publisher.publish(publish_packet("offline/b", "live", 1, false)).await.unwrap();
+    assert_eq!(messages.next().await.unwrap().payload, "live");
+    client.disconnect(DisconnectPacket { reason_code: ReasonCode::Success, properties: Default::default() }).await.unwrap();
+
+    // a clean start discards the session
+    packet.clean_start = true;
+    let stream = TcpStream::connect(broker.tcp_address()).await.unwrap();
+    let (client, _) = Client::connect(stream, packet).await.unwrap();
+    assert!(!client.connack().session_present);
+
+    broker.stop().await;
+  }
+
+  #[tokio::test]
+  async fn queue_error_test() {
+    let broker = Broker::start_with_store(QueueErrorStore(MemorySessionStore::with_clock(TokioClock))).await.unwrap();
+    let (publisher, _) = connect(&broker, "publisher").await;
+
+    let mut packet = connect_packet("persistent");
+    packet.clean_start = false;
+    packet.properties.session_expiry_interval = Some(60);
+
+    let stream = TcpStream::connect(broker.tcp_address()).await.unwrap();
+    let (client, _) = Client::connect(stream, packet.clone()).await.unwrap();
+    client.subscribe(subscribe_packet("offline/#", 1)).await.unwrap();
+    client.disconnect(DisconnectPacket { reason_code: ReasonCode::Success, properties: Default::default() }).await.unwrap();
+
+    // the publisher hears about the failure and the session is kept
+    let reason_code = publisher.publish(publish_packet("offline/a", "lost", 1, false)).await.unwrap();
+    assert_eq!(reason_code, ReasonCode::UnspecifiedError);
+
+    let stream = TcpStream::connect(broker.tcp_address()).await.unwrap();
+    let (client, _) = Client::connect(stream, packet).await.unwrap();
+    assert!(client.connack().session_present);
+
+    broker.stop().await;
+  }
+
+  #[tokio::test]
+  async fn invalid_filter_test() {
+    let broker = Broker::start().await.unwrap();
+    let (client, _) = connect(&broker, "client").await;
+
+    let reason_codes = client.subscribe(subscribe_packet("a/#/b", 0)).await.unwrap();
+    assert_eq!(reason_codes, vec![ReasonCode::TopicFilterInvalid]);
+
+    let reason_codes = client.unsubscribe(UnsubscribePacket {
+      packet_id: 0,
+      topics: vec!["missing".to_owned()],
+      properties: Default::default()
+    }).await.unwrap();
+    assert_eq!(reason_codes, vec![ReasonCode::NoSubscriptionExisted]);
+
+    broker.stop().await;
+  }
+
+  #[tokio::test]
+  async fn unsupported_protocol_test() {
+    let broker = Broker::start().await.unwrap();
+
+    // a CONNECT for MQTT 3.1.1
+    let mut stream = TcpStream::connect(broker.tcp_address()).await.unwrap();
+    stream.write_all(&[0x10, 0x0A, 0x00, 0x04, b'M', b'Q', b'T', b'T', 0x04, 0x02, 0x00, 0x00]).await.unwrap();
+
+    let mut framed = Framed::new(stream, MQTTCodec::new());
+    match framed.next().await.unwrap().unwrap() {
+      DecodedPacket::Connack(packet) => assert_eq!(packet.reason_code, ReasonCode::UnsupportedProtocolVersion),
+      packet => panic!("unexpected {:?}", packet)
+    }
+    assert!(framed.next().await.is_none());
+
+    broker.stop().await;
+  }
+
+  #[tokio::test]
+  async fn websocket_test() {
+    let broker = Broker::start().await.unwrap();
+    let (publisher, _) = connect(&broker, "publisher").await;
+
+    let mut stream = TcpStream::connect(broker.websocket_address()).await.unwrap();
+    stream.write_all(b"GET /mqtt HTTP/1.1\r\n\
+      Host: localhost\r\n\
+      Upgrade: websocket\r\n\
+      Connection: Upgrade\r\n\
+      Sec-WebSocket-Key: x3JJHMbDL1EzLkh9GBhXDw==\r\n\
+      Sec-WebSocket-Protocol: mqtt\r\n\
+      Sec-WebSocket-Version: 13\r\n\r\n").await.unwrap();
+
+    let mut response = BytesMut::new();
+    while !response.ends_with(b"\r\n\r\n") {
+      stream.read_buf(&mut response).await.unwrap();
+    }
+    assert!(response.starts_with(b"HTTP/1.1 101 Switching Protocols\r\n"));
+
+    let mut framed = Framed::new(stream, WebsocketCodec::new());
+    framed.send(DecodedPacket::Connect(connect_packet("websocket"))).await.unwrap();
+    match framed.next().await.unwrap().unwrap() {
+      WebsocketMessage::Packet(DecodedPacket::Connack(packet)) => assert_eq!(packet.reason_code, ReasonCode::Success),
+      message => panic!("unexpected {:?}", message)
+    }
+
+    // a ping is answered without affecting the session
+    framed.send(WebsocketMessage::Ping(b"ping".to_vec())).await.unwrap();
+    assert_eq!(framed.next().await.unwrap().unwrap(), WebsocketMessage::Pong(b"ping".to_vec()));
+
+    let mut subscribe = subscribe_packet("test", 0);
+    subscribe.packet_id = 1;
+    framed.send(DecodedPacket::Subscribe(subscribe)).await.unwrap();
+    match framed.next().await.unwrap().unwrap() {
+      WebsocketMessage::Packet(DecodedPacket::Suback(packet)) => assert_eq!(packet.reason_codes, vec![ReasonCode::Success]),
+      message => panic!("unexpected {:?}", message)
+    }
+
+    publisher.publish(publish_packet("test", "hello", 0, false)).await.unwrap();
+    let publish = DecodedPacket::Publish(publish_packet("test", "hello", 0, false));
+    assert_eq!(framed.next().await.unwrap().unwrap(), WebsocketMessage::Packet(publish));
+
+    // the close handshake echoes the status code, then the broker closes the connection
+    framed.send(WebsocketMessage::Close(Some(CloseFrame { code: 1000, reason: "done".to_owned() }))).await.unwrap();
+    assert_eq!(framed.next().await.unwrap().unwrap(), WebsocketMessage::Close(Some(CloseFrame { code: 1000, reason: String::new() })));
+    assert!(framed.next().await.is_none());
+
+    broker.stop().await;
+  }
+
+  #[tokio::test]
+  async fn session_taken_over_test() {
+    let broker = Broker::start().await.unwrap();
+    let (first, _) = connect(&broker, "client").await;
+    let (_second, _) = connect(&broker, "client").await;
+
+    match first.publish(publish_packet("test", "hello", 1, false)).await {
+      Err(crate::error::ClientError::Disconnected) => {},
+      result => panic!("unexpected {:?}", result)
+    }
+    broker.stop().await;
+  }
+}
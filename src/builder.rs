@@ -0,0 +1,734 @@
+use crate::{
+  types::*,
+  error::BuildError,
+  utils::check_utf8,
+  topic
+};
+
+// typed setters for the properties a packet allows, plus user properties which every packet allows
+macro_rules! properties {
+  ($($name:ident($type:ty)),* $(,)?) => {
+    $(
+      pub fn $name(mut self, value: $type) -> Self {
+        self.packet.properties.$name = Some(value.into());
+        self
+      }
+    )*
+
+    pub fn user_property(mut self, key: &str, value: &str) -> Self {
+      self.packet.properties.user_properties.push(Utf8Pair::new_unchecked(key.to_owned(), value.to_owned()));
+      self
+    }
+  };
+}
+
+fn check_packet_id(packet_id: Option<u16>) -> Result<u16, BuildError> {
+  match packet_id {
+    Some(0) => Err(BuildError::InvalidPacketId),
+    // left at 0 for the client to assign
+    packet_id => Ok(packet_id.unwrap_or(0))
+  }
+}
+
+fn check_reason_code(reason_code: ReasonCode, packet_type: PacketType) -> Result<(), BuildError> {
+  match reason_code.is_allowed(packet_type) {
+    true => Ok(()),
+    false => Err(BuildError::ReasonCodeNotAllowed(reason_code, packet_type))
+  }
+}
+
+fn check_properties(properties: &[Property]) -> Result<(), BuildError> {
+  let invalid = properties.iter().find(|property| match property {
+    Property::ReceiveMaximum(0) | Property::MaximumPacketSize(0) | Property::TopicAlias(0) |
+    Property::SubscriptionIdentifier(0) | Property::MaximumQoS(2..=255) | Property::RetainAvailable(2..=255) |
+    Property::PayloadFormatIndicator(2..=255) | Property::RequestProblemInformation(2..=255) |
+    Property::RequestResponseInformation(2..=255) => true,
+    Property::ContentType(string) | Property::ResponseTopic(string) | Property::AssignedClientIdentifier(string) |
+    Property::AuthenticationMethod(string) | Property::ResponseInformation(string) | Property::ServerReference(string) |
+    Property::ReasonString(string) => check_utf8(string).is_err(),
+    Property::UserProperty(pair) => pair.check().is_err(),
+    _ => false
+  });
+
+  match invalid {
+    Some(property) => Err(BuildError::InvalidProperty(property.clone())),
+    None => Ok(())
+  }
+}
+
+pub struct ConnectBuilder {
+  packet: ConnectPacket
+}
+
+impl ConnectPacket {
+  pub fn builder() -> ConnectBuilder {
+    ConnectBuilder {
+      packet: ConnectPacket {
+        client_id: String::new(),
+        clean_start: true,
+        will_config: None,
+        keep_alive: 0,
+        username: None,
+        password: None,
+        properties: Default::default()
+      }
+    }
+  }
+}
+
+impl ConnectBuilder {
+  // an empty client id asks the server to assign one
+  pub fn client_id(mut self, client_id: &str) -> Self {
+    self.packet.client_id = client_id.to_owned();
+    self
+  }
+
+  pub fn clean_start(mut self, clean_start: bool) -> Self {
+    self.packet.clean_start = clean_start;
+    self
+  }
+
+  pub fn keep_alive(mut self, keep_alive: u16) -> Self {
+    self.packet.keep_alive = keep_alive;
+    self
+  }
+
+  pub fn username(mut self, username: &str) -> Self {
+    self.packet.username = Some(username.to_owned());
+    self
+  }
+
+  pub fn password(mut self, password: &str) -> Self {
+    self.packet.password = Some(password.to_owned());
+    self
+  }
+
+  pub fn will(mut self, will: WillConfig) -> Self {
+    self.packet.will_config = Some(will);
+    self
+  }
+
+  properties! {
+    session_expiry_interval(u32),
+    receive_maximum(u16),
+    maximum_packet_size(u32),
+    topic_alias_maximum(u16),
+    request_response_information(bool),
+    request_problem_information(bool),
+    authentication_method(&str),
+    authentication_data(&[u8])
+  }
+
+  pub fn build(self) -> Result<ConnectPacket, BuildError> {
+    if let Some(will) = &self.packet.will_config {
+      check_will(will)?;
+    }
+    check_properties(&self.packet.properties.to_properties())?;
+    Ok(self.packet)
+  }
+}
+
+fn check_will(will: &WillConfig) -> Result<(), BuildError> {
+  if !topic::is_valid_name(&will.topic) {
+    return Err(BuildError::InvalidTopicName(will.topic.clone()));
+  }
+  check_properties(&will.properties.to_properties())
+}
+
+pub struct WillBuilder {
+  packet: WillConfig
+}
+
+impl WillConfig {
+  pub fn builder(topic: &str, payload: &str) -> WillBuilder {
+    WillBuilder {
+      packet: WillConfig {
+        topic: topic.to_owned(),
+        payload: payload.to_owned(),
+        retain: false,
+        qos: QoS::AtMostOnce,
+        properties: Default::default()
+      }
+    }
+  }
+}
+
+impl WillBuilder {
+  pub fn qos(mut self, qos: QoS) -> Self {
+    self.packet.qos = qos;
+    self
+  }
+
+  pub fn retain(mut self, retain: bool) -> Self {
+    self.packet.retain = retain;
+    self
+  }
+
+  properties! {
+    will_delay_interval(u32),
+    payload_format_indicator(u8),
+    message_expiry_interval(u32),
+    content_type(&str),
+    response_topic(&str),
+    correlation_data(&[u8])
+  }
+
+  pub fn build(self) -> Result<WillConfig, BuildError> {
+    check_will(&self.packet)?;
+    Ok(self.packet)
+  }
+}
+
+pub struct ConnackBuilder {
+  packet: ConnackPacket
+}
+
+impl ConnackPacket {
+  pub fn builder() -> ConnackBuilder {
+    ConnackBuilder {
+      packet: ConnackPacket {
+        session_present: false,
+        reason_code: ReasonCode::Success,
+        properties: Default::default()
+      }
+    }
+  }
+}
+
+impl ConnackBuilder {
+  pub fn session_present(mut self, session_present: bool) -> Self {
+    self.packet.session_present = session_present;
+    self
+  }
+
+  pub fn reason_code(mut self, reason_code: ReasonCode) -> Self {
+    self.packet.reason_code = reason_code;
+    self
+  }
+
+  properties! {
+    session_expiry_interval(u32),
+    receive_maximum(u16),
+    maximum_qos(u8),
+    retain_available(u8),
+    maximum_packet_size(u32),
+    assigned_client_identifier(&str),
+    topic_alias_maximum(u16),
+    reason_string(&str),
+    wildcard_subscription_available(bool),
+    subscription_identifier_available(bool),
+    shared_subscription_available(bool),
+    server_keep_alive(u16),
+    response_information(&str),
+    server_reference(&str),
+    authentication_method(&str),
+    authentication_data(&[u8])
+  }
+
+  pub fn build(self) -> Result<ConnackPacket, BuildError> {
+    // a refused connection never has a session [MQTT-3.2.2-6]
+    if self.packet.session_present && self.packet.reason_code.is_error() {
+      return Err(BuildError::SessionPresentOnError);
+    }
+    check_reason_code(self.packet.reason_code, PacketType::CONNACK)?;
+    check_properties(&self.packet.properties.to_properties())?;
+    Ok(self.packet)
+  }
+}
+
+pub struct PublishBuilder {
+  packet: PublishPacket
+}
+
+impl PublishPacket {
+  pub fn builder(topic: &str) -> PublishBuilder {
+    PublishBuilder {
+      packet: PublishPacket {
+        topic: topic.to_owned(),
+        packet_id: None,
+        payload: String::new(),
+        config: PublishConfig::default(),
+        properties: Default::default()
+      }
+    }
+  }
+}
+
+impl PublishBuilder {
+  pub fn payload(mut self, payload: &str) -> Self {
+    self.packet.payload = payload.to_owned();
+    self
+  }
+
+  pub fn qos(mut self, qos: QoS) -> Self {
+    self.packet.config.qos = qos;
+    self
+  }
+
+  pub fn retain(mut self, retain: bool) -> Self {
+    self.packet.config.retain = retain;
+    self
+  }
+
+  pub fn dup(mut self, dup: bool) -> Self {
+    self.packet.config.dup = dup;
+    self
+  }
+
+  // only needed when encoding directly, the client assigns packet ids itself
+  pub fn packet_id(mut self, packet_id: u16) -> Self {
+    self.packet.packet_id = Some(packet_id);
+    self
+  }
+
+  // a message can match several subscriptions, so this may be given more than once
+  pub fn subscription_identifier(mut self, identifier: u64) -> Self {
+    self.packet.properties.subscription_identifiers.push(identifier);
+    self
+  }
+
+  properties! {
+    payload_format_indicator(u8),
+    message_expiry_interval(u32),
+    topic_alias(u16),
+    response_topic(&str),
+    correlation_data(&[u8]),
+    content_type(&str)
+  }
+
+  pub fn build(self) -> Result<PublishPacket, BuildError> {
+    let packet = &self.packet;
+    match (packet.config.qos, packet.packet_id) {
+      (QoS::AtMostOnce, Some(_)) => return Err(BuildError::PacketIdWithoutQoS),
+      (_, Some(0)) => return Err(BuildError::InvalidPacketId),
+      _ => {}
+    }
+    if packet.config.qos == QoS::AtMostOnce && packet.config.dup {
+      return Err(BuildError::DupWithoutQoS);
+    }
+
+    // an empty topic is only allowed when a topic alias stands in for it
+    let aliased = packet.topic.is_empty() && packet.properties.topic_alias.is_some();
+    if !aliased && !topic::is_valid_name(&packet.topic) {
+      return Err(BuildError::InvalidTopicName(packet.topic.clone()));
+    }
+
+    check_properties(&packet.properties.to_properties())?;
+    Ok(self.packet)
+  }
+}
+
+// PUBACK, PUBREC, PUBREL and PUBCOMP only differ in their packet type
+macro_rules! acknowledgement_builder {
+  ($builder:ident, $packet:ident, $packet_type:expr) => {
+    pub struct $builder {
+      packet: $packet
+    }
+
+    impl $packet {
+      pub fn builder(packet_id: u16) -> $builder {
+        $builder {
+          packet: $packet {
+            packet_id,
+            reason_code: ReasonCode::Success,
+            properties: Default::default()
+          }
+        }
+      }
+    }
+
+    impl $builder {
+      pub fn reason_code(mut self, reason_code: ReasonCode) -> Self {
+        self.packet.reason_code = reason_code;
+        self
+      }
+
+      properties! {
+        reason_string(&str)
+      }
+
+      pub fn build(self) -> Result<$packet, BuildError> {
+        check_packet_id(Some(self.packet.packet_id))?;
+        check_reason_code(self.packet.reason_code, $packet_type)?;
+        check_properties(&self.packet.properties.to_properties())?;
+        Ok(self.packet)
+      }
+    }
+  };
+}
+
+acknowledgement_builder!(PubackBuilder, PubackPacket, PacketType::PUBACK);
+acknowledgement_builder!(PubrecBuilder, PubrecPacket, PacketType::PUBREC);
+acknowledgement_builder!(PubrelBuilder, PubrelPacket, PacketType::PUBREL);
+acknowledgement_builder!(PubcompBuilder, PubcompPacket, PacketType::PUBCOMP);
+
+pub struct SubscribeBuilder {
+  packet_id: Option<u16>,
+  packet: SubscribePacket
+}
+
+impl SubscribePacket {
+  pub fn builder() -> SubscribeBuilder {
+    SubscribeBuilder {
+      packet_id: None,
+      packet: SubscribePacket {
+        packet_id: 0,
+        subscriptions: vec![],
+        properties: Default::default()
+      }
+    }
+  }
+}
+
+impl SubscribeBuilder {
+  pub fn packet_id(mut self, packet_id: u16) -> Self {
+    self.packet_id = Some(packet_id);
+    self
+  }
+
+  // subscribes with the default options: retained messages sent, no Retain As Published, no No Local
+  pub fn topic(self, filter: &str, qos: QoS) -> Self {
+    self.subscription(SubscriptionConfig {
+      topic: filter.to_owned(),
+      retain_handling: RetainHandling::SendOnSubscribe,
+      rap: false,
+      nl: false,
+      qos
+    })
+  }
+
+  pub fn subscription(mut self, subscription: SubscriptionConfig) -> Self {
+    self.packet.subscriptions.push(subscription);
+    self
+  }
+
+  properties! {
+    subscription_identifier(u64)
+  }
+
+  pub fn build(mut self) -> Result<SubscribePacket, BuildError> {
+    self.packet.packet_id = check_packet_id(self.packet_id)?;
+
+    if self.packet.subscriptions.is_empty() {
+      return Err(BuildError::NoTopics);
+    }
+    for subscription in self.packet.subscriptions.iter() {
+      if !topic::is_valid_filter(&subscription.topic) {
+        return Err(BuildError::InvalidTopicFilter(subscription.topic.clone()));
+      }
+    }
+
+    check_properties(&self.packet.properties.to_properties())?;
+    Ok(self.packet)
+  }
+}
+
+pub struct UnsubscribeBuilder {
+  packet_id: Option<u16>,
+  packet: UnsubscribePacket
+}
+
+impl UnsubscribePacket {
+  pub fn builder() -> UnsubscribeBuilder {
+    UnsubscribeBuilder {
+      packet_id: None,
+      packet: UnsubscribePacket {
+        packet_id: 0,
+        topics: vec![],
+        properties: Default::default()
+      }
+    }
+  }
+}
+
+impl UnsubscribeBuilder {
+  pub fn packet_id(mut self, packet_id: u16) -> Self {
+    self.packet_id = Some(packet_id);
+    self
+  }
+
+  pub fn topic(mut self, filter: &str) -> Self {
+    self.packet.topics.push(filter.to_owned());
+    self
+  }
+
+  properties! {}
+
+  pub fn build(mut self) -> Result<UnsubscribePacket, BuildError> {
+    self.packet.packet_id = check_packet_id(self.packet_id)?;
+
+    if self.packet.topics.is_empty() {
+      return Err(BuildError::NoTopics);
+    }
+    if let Some(filter) = self.packet.topics.iter().find(|filter| !topic::is_valid_filter(filter)) {
+      return Err(BuildError::InvalidTopicFilter(filter.clone()));
+    }
+    check_properties(&self.packet.properties.to_properties())?;
+    Ok(self.packet)
+  }
+}
+
+// SUBACK and UNSUBACK carry one reason code per topic of the request
+macro_rules! subscription_acknowledgement_builder {
+  ($builder:ident, $packet:ident, $packet_type:expr) => {
+    pub struct $builder {
+      packet: $packet
+    }
+
+    impl $packet {
+      pub fn builder(packet_id: u16) -> $builder {
+        $builder {
+          packet: $packet {
+            packet_id,
+            reason_codes: vec![],
+            properties: Default::default()
+          }
+        }
+      }
+    }
+
+    impl $builder {
+      pub fn reason_code(mut self, reason_code: ReasonCode) -> Self {
+        self.packet.reason_codes.push(reason_code);
+        self
+      }
+
+      properties! {
+        reason_string(&str)
+      }
+
+      pub fn build(self) -> Result<$packet, BuildError> {
+        check_packet_id(Some(self.packet.packet_id))?;
+        if self.packet.reason_codes.is_empty() {
+          return Err(BuildError::NoReasonCodes);
+        }
+        for reason_code in self.packet.reason_codes.iter() {
+          check_reason_code(*reason_code, $packet_type)?;
+        }
+        check_properties(&self.packet.properties.to_properties())?;
+        Ok(self.packet)
+      }
+    }
+  };
+}
+
+subscription_acknowledgement_builder!(SubackBuilder, SubackPacket, PacketType::SUBACK);
+subscription_acknowledgement_builder!(UnsubackBuilder, UnsubackPacket, PacketType::UNSUBACK);
+
+pub struct DisconnectBuilder {
+  packet: DisconnectPacket
+}
+
+impl DisconnectPacket {
+  pub fn builder() -> DisconnectBuilder {
+    DisconnectBuilder {
+      packet: DisconnectPacket {
+        reason_code: ReasonCode::Success,
+        properties: Default::default()
+      }
+    }
+  }
+}
+
+impl DisconnectBuilder {
+  pub fn reason_code(mut self, reason_code: ReasonCode) -> Self {
+    self.packet.reason_code = reason_code;
+    self
+  }
+
+  properties! {
+    session_expiry_interval(u32),
+    reason_string(&str),
+    server_reference(&str)
+  }
+
+  pub fn build(self) -> Result<DisconnectPacket, BuildError> {
+    check_reason_code(self.packet.reason_code, PacketType::DISCONNECT)?;
+    check_properties(&self.packet.properties.to_properties())?;
+    Ok(self.packet)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn connect_test() {
+    let will = WillConfig::builder("status", "offline")
+      .qos(QoS::AtLeastOnce)
+      .retain(true)
+      .will_delay_interval(10)
+      .build()
+      .unwrap();
+
+    let packet = ConnectPacket::builder()
+      .client_id("client")
+      .clean_start(false)
+      .keep_alive(30)
+      .username("user")
+      .password("secret")
+      .will(will.clone())
+      .session_expiry_interval(60)
+      .session_expiry_interval(120)
+      .request_response_information(true)
+      .user_property("a", "1")
+      .user_property("a", "2")
+      .build()
+      .unwrap();
+
+    assert_eq!(packet, ConnectPacket {
+      client_id: "client".to_owned(),
+      clean_start: false,
+      will_config: Some(will),
+      keep_alive: 30,
+      username: Some("user".to_owned()),
+      password: Some("secret".to_owned()),
+      properties: ConnectProperties {
+        session_expiry_interval: Some(120),
+        request_response_information: Some(1),
+        user_properties: vec![Utf8Pair::new("a", "1").unwrap(), Utf8Pair::new("a", "2").unwrap()],
+        ..Default::default()
+      }
+    });
+  }
+
+  #[test]
+  fn connect_invalid_test() {
+    assert_eq!(WillConfig::builder("a/#", "").build().unwrap_err(), BuildError::InvalidTopicName("a/#".to_owned()));
+    assert_eq!(ConnectPacket::builder().receive_maximum(0).build().unwrap_err(),
+      BuildError::InvalidProperty(Property::ReceiveMaximum(0)));
+  }
+
+  #[test]
+  fn connack_test() {
+    let packet = ConnackPacket::builder()
+      .session_present(true)
+      .assigned_client_identifier("auto-1")
+      .build()
+      .unwrap();
+    assert!(packet.session_present);
+    assert_eq!(packet.properties.assigned_client_identifier, Some("auto-1".to_owned()));
+
+    let error = ConnackPacket::builder().session_present(true).reason_code(ReasonCode::NotAuthorized).build();
+    assert_eq!(error.unwrap_err(), BuildError::SessionPresentOnError);
+
+    let error = ConnackPacket::builder().reason_code(ReasonCode::NoMatchingSubscribers).build();
+    assert_eq!(error.unwrap_err(), BuildError::ReasonCodeNotAllowed(ReasonCode::NoMatchingSubscribers, PacketType::CONNACK));
+  }
+
+  #[test]
+  fn publish_test() {
+    let packet = PublishPacket::builder("a/b")
+      .payload("hello")
+      .qos(QoS::AtLeastOnce)
+      .packet_id(7)
+      .content_type("text/plain")
+      .subscription_identifier(1)
+      .subscription_identifier(2)
+      .build()
+      .unwrap();
+
+    assert_eq!(packet, PublishPacket {
+      topic: "a/b".to_owned(),
+      packet_id: Some(7),
+      payload: "hello".to_owned(),
+      config: PublishConfig { dup: false, qos: QoS::AtLeastOnce, retain: false },
+      properties: PublishProperties {
+        content_type: Some("text/plain".to_owned()),
+        subscription_identifiers: vec![1, 2],
+        ..Default::default()
+      }
+    });
+  }
+
+  #[test]
+  fn publish_invalid_test() {
+    assert_eq!(PublishPacket::builder("a").packet_id(1).build().unwrap_err(), BuildError::PacketIdWithoutQoS);
+    assert_eq!(PublishPacket::builder("a").dup(true).build().unwrap_err(), BuildError::DupWithoutQoS);
+    assert_eq!(PublishPacket::builder("a").qos(QoS::AtLeastOnce).packet_id(0).build().unwrap_err(), BuildError::InvalidPacketId);
+    assert_eq!(PublishPacket::builder("a/+").build().unwrap_err(), BuildError::InvalidTopicName("a/+".to_owned()));
+    assert_eq!(PublishPacket::builder("").build().unwrap_err(), BuildError::InvalidTopicName(String::new()));
+    assert!(PublishPacket::builder("").topic_alias(1).build().is_ok());
+    assert_eq!(PublishPacket::builder("").topic_alias(0).build().unwrap_err(),
+      BuildError::InvalidProperty(Property::TopicAlias(0)));
+  }
+
+  #[test]
+  fn acknowledgement_test() {
+    let packet = PubrecPacket::builder(3).reason_code(ReasonCode::QuotaExceeded).reason_string("full").build().unwrap();
+    assert_eq!(packet, PubrecPacket {
+      packet_id: 3,
+      reason_code: ReasonCode::QuotaExceeded,
+      properties: AckProperties { reason_string: Some("full".to_owned()), ..Default::default() }
+    });
+
+    assert_eq!(PubackPacket::builder(0).build().unwrap_err(), BuildError::InvalidPacketId);
+    assert_eq!(PubackPacket::builder(1).reason_code(ReasonCode::Banned).build().unwrap_err(),
+      BuildError::ReasonCodeNotAllowed(ReasonCode::Banned, PacketType::PUBACK));
+    assert!(PubrelPacket::builder(1).reason_code(ReasonCode::PacketIdentifierNotFound).build().is_ok());
+    assert!(PubrelPacket::builder(1).reason_code(ReasonCode::QuotaExceeded).build().is_err());
+    assert!(PubrelPacket::builder(1).build().is_ok());
+    assert!(PubcompPacket::builder(1).build().is_ok());
+  }
+
+  #[test]
+  fn subscribe_test() {
+    let packet = SubscribePacket::builder()
+      .topic("a/+", QoS::AtLeastOnce)
+      .subscription(SubscriptionConfig { topic: "b/#".to_owned(), retain_handling: RetainHandling::DoNotSend, rap: true, nl: true, qos: QoS::ExactlyOnce })
+      .subscription_identifier(5)
+      .build()
+      .unwrap();
+    assert_eq!(packet.packet_id, 0);
+    assert_eq!(packet.subscriptions.len(), 2);
+    assert_eq!(packet.properties.subscription_identifier, Some(5));
+
+    assert_eq!(SubscribePacket::builder().build().unwrap_err(), BuildError::NoTopics);
+    assert_eq!(SubscribePacket::builder().topic("a/#/b", QoS::AtMostOnce).build().unwrap_err(),
+      BuildError::InvalidTopicFilter("a/#/b".to_owned()));
+    assert_eq!(SubscribePacket::builder().packet_id(0).topic("a", QoS::AtMostOnce).build().unwrap_err(), BuildError::InvalidPacketId);
+  }
+
+  #[test]
+  fn unsubscribe_test() {
+    let packet = UnsubscribePacket::builder().packet_id(4).topic("a/+").user_property("k", "v").build().unwrap();
+    assert_eq!(packet.packet_id, 4);
+    assert_eq!(packet.topics, vec!["a/+"]);
+
+    assert_eq!(UnsubscribePacket::builder().build().unwrap_err(), BuildError::NoTopics);
+
+    let error = UnsubscribePacket::builder().topic("a").user_property("k", "\0").build().unwrap_err();
+    assert_eq!(error, BuildError::InvalidProperty(Property::UserProperty(Utf8Pair::new_unchecked("k".to_owned(), "\0".to_owned()))));
+  }
+
+  #[test]
+  fn subscription_acknowledgement_test() {
+    let packet = SubackPacket::builder(1).reason_code(ReasonCode::GrantedQoS1).reason_code(ReasonCode::NotAuthorized)
+      .build().unwrap();
+    assert_eq!(packet.reason_codes, vec![ReasonCode::GrantedQoS1, ReasonCode::NotAuthorized]);
+
+    assert_eq!(UnsubackPacket::builder(1).build().unwrap_err(), BuildError::NoReasonCodes);
+    assert_eq!(UnsubackPacket::builder(1).reason_code(ReasonCode::GrantedQoS1).build().unwrap_err(),
+      BuildError::ReasonCodeNotAllowed(ReasonCode::GrantedQoS1, PacketType::UNSUBACK));
+  }
+
+  #[test]
+  fn disconnect_test() {
+    let packet = DisconnectPacket::builder()
+      .reason_code(ReasonCode::DisconnectWithWill)
+      .session_expiry_interval(0)
+      .build()
+      .unwrap();
+    assert_eq!(packet, DisconnectPacket {
+      reason_code: ReasonCode::DisconnectWithWill,
+      properties: DisconnectProperties { session_expiry_interval: Some(0), ..Default::default() }
+    });
+
+    let reason = "a".repeat(65536);
+    assert!(matches!(DisconnectPacket::builder().reason_string(&reason).build(),
+      Err(BuildError::InvalidProperty(Property::ReasonString(_)))));
+    assert_eq!(DisconnectPacket::builder().reason_code(ReasonCode::GrantedQoS1).build().unwrap_err(),
+      BuildError::ReasonCodeNotAllowed(ReasonCode::GrantedQoS1, PacketType::DISCONNECT));
+  }
+}
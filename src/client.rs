@@ -0,0 +1,620 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::{Stream, SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{sleep_until, Instant};
+use tokio_util::codec::Framed;
+
+use crate::{
+  types::*,
+  codec::MQTTCodec,
+  error::ClientError,
+  session::{PacketIdAllocator, Inflight, FlowControl, KeepAlive, KeepAliveAction, ExpiringMessage, TokioClock}
+};
+
+type Reply<T> = oneshot::Sender<Result<T, ClientError>>;
+
+enum Command {
+  Publish(PublishPacket, Reply<ReasonCode>),
+  Subscribe(SubscribePacket, Reply<Vec<ReasonCode>>),
+  Unsubscribe(UnsubscribePacket, Reply<Vec<ReasonCode>>),
+  Disconnect(DisconnectPacket, Reply<()>)
+}
+
+enum Pending {
+  Publish(Reply<ReasonCode>),
+  Subscribe(Reply<Vec<ReasonCode>>),
+  Unsubscribe(Reply<Vec<ReasonCode>>)
+}
+
+// a handle to a connection driven by a background task; the connection is
+// closed with a normal DISCONNECT once every handle has been dropped
+#[derive(Clone)]
+pub struct Client {
+  commands: mpsc::UnboundedSender<Command>,
+  connack: ConnackPacket
+}
+
+pub struct Messages {
+  receiver: mpsc::UnboundedReceiver<PublishPacket>
+}
+
+pub struct Response<T> {
+  receiver: oneshot::Receiver<Result<T, ClientError>>
+}
+
+impl Client {
+  pub async fn connect<T>(transport: T, packet: ConnectPacket) -> Result<(Client, Messages), ClientError>
+  where T: AsyncRead + AsyncWrite + Unpin + Send + 'static {
+    let mut framed = Framed::new(transport, MQTTCodec::new().with_role(Role::Client));
+
+    let receive_maximum = packet.properties.receive_maximum.unwrap_or(0);
+    let mut flow_control = FlowControl::new(receive_maximum);
+    let mut keep_alive = KeepAlive::with_clock(Role::Client, packet.keep_alive, TokioClock);
+
+    framed.send(DecodedPacket::Connect(packet)).await?;
+
+    let connack = match framed.next().await {
+      Some(Ok(DecodedPacket::Connack(connack))) => connack,
+      Some(Ok(_)) => return Err(ClientError::ProtocolError(ReasonCode::ProtocolError)),
+      Some(Err(error)) => return Err(error.into()),
+      None => return Err(ClientError::Disconnected)
+    };
+
+    if connack.reason_code.is_error() {
+      return Err(ClientError::ConnectionRefused(connack.reason_code));
+    }
+
+    flow_control.apply_connack(&connack);
+    keep_alive.apply_connack(&connack);
+    keep_alive.received(&DecodedPacket::Connack(connack.clone()));
+
+    let (commands, command_receiver) = mpsc::unbounded_channel();
+    let (message_sender, message_receiver) = mpsc::unbounded_channel();
+
+    let connection = Connection {
+      framed,
+      commands: command_receiver,
+      messages: message_sender,
+      allocator: PacketIdAllocator::new(),
+      inflight: Inflight::new(),
+      flow_control,
+      keep_alive,
+      pending: HashMap::new()
+    };
+    tokio::spawn(connection.run());
+
+    Ok((Client { commands, connack }, Messages::new(message_receiver)))
+  }
+
+  pub fn connack(&self) -> &ConnackPacket {
+    &self.connack
+  }
+
+  // resolves with Success for QoS 0, otherwise with the PUBACK, PUBREC or PUBCOMP reason code;
+  // the packet identifier is assigned by the client
+  pub fn publish(&self, packet: PublishPacket) -> Response<ReasonCode> {
+    self.request(|reply| Command::Publish(packet, reply))
+  }
+
+  pub fn subscribe(&self, packet: SubscribePacket) -> Response<Vec<ReasonCode>> {
+    self.request(|reply| Command::Subscribe(packet, reply))
+  }
+
+  pub fn unsubscribe(&self, packet: UnsubscribePacket) -> Response<Vec<ReasonCode>> {
+    self.request(|reply| Command::Unsubscribe(packet, reply))
+  }
+
+  pub fn disconnect(&self, packet: DisconnectPacket) -> Response<()> {
+    self.request(|reply| Command::Disconnect(packet, reply))
+  }
+
+  fn request<T>(&self, command: impl FnOnce(Reply<T>) -> Command) -> Response<T> {
+    let (reply, receiver) = oneshot::channel();
+    // if the connection is gone the reply is dropped and the response resolves to Disconnected
+    let _ = self.commands.send(command(reply));
+    Response { receiver }
+  }
+}
+
+impl Messages {
+  pub(crate) fn new(receiver: mpsc::UnboundedReceiver<PublishPacket>) -> Self {
+    Self { receiver }
+  }
+}
+
+impl<T> Future for Response<T> {
+  type Output = Result<T, ClientError>;
+
+  fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    match Pin::new(&mut self.receiver).poll(cx) {
+      Poll::Ready(Ok(result)) => Poll::Ready(result),
+      Poll::Ready(Err(_)) => Poll::Ready(Err(ClientError::Disconnected)),
+      Poll::Pending => Poll::Pending
+    }
+  }
+}
+
+impl Stream for Messages {
+  type Item = PublishPacket;
+
+  fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    self.receiver.poll_recv(cx)
+  }
+}
+
+struct Connection<T> {
+  framed: Framed<T, MQTTCodec>,
+  commands: mpsc::UnboundedReceiver<Command>,
+  messages: mpsc::UnboundedSender<PublishPacket>,
+  allocator: PacketIdAllocator,
+  inflight: Inflight,
+  flow_control: FlowControl,
+  keep_alive: KeepAlive<TokioClock>,
+  pending: HashMap<u16, Pending>
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Connection<T> {
+  async fn run(mut self) {
+    let _ = self.drive().await;
+
+    for (_, pending) in self.pending.drain() {
+      match pending {
+        Pending::Publish(reply) => { let _ = reply.send(Err(ClientError::Disconnected)); },
+        Pending::Subscribe(reply) => { let _ = reply.send(Err(ClientError::Disconnected)); },
+        Pending::Unsubscribe(reply) => { let _ = reply.send(Err(ClientError::Disconnected)); }
+      }
+    }
+  }
+
+  async fn drive(&mut self) -> Result<(), ClientError> {
+    loop {
+      let deadline = self.keep_alive.next_deadline().map(Instant::from_std);
+
+      tokio::select! {
+        command = self.commands.recv() => match command {
+          Some(Command::Disconnect(packet, reply)) => {
+            let _ = reply.send(self.write(DecodedPacket::Disconnect(packet)).await);
+            return Ok(());
+          },
+          Some(command) => self.handle_command(command).await?,
+          None => {
+            return self.write(DecodedPacket::Disconnect(DisconnectPacket {
+              reason_code: ReasonCode::Success,
+              properties: Default::default()
+            })).await;
+          }
+        },
+        packet = self.framed.next() => match packet {
+          Some(Ok(DecodedPacket::Disconnect(_))) => return Err(ClientError::Disconnected),
+          Some(Ok(packet)) => self.handle_packet(packet).await?,
+          Some(Err(error)) => {
+            let _ = self.write(DecodedPacket::Disconnect(DisconnectPacket {
+              reason_code: error.reason_code(),
+              properties: Default::default()
+            })).await;
+            return Err(error.into());
+          },
+          None => return Err(ClientError::Disconnected)
+        },
+        _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+          match self.keep_alive.poll() {
+            Some(KeepAliveAction::SendPingReq) => self.write(DecodedPacket::PingReq(PingReqPacket {})).await?,
+            Some(KeepAliveAction::Close) => return Err(ClientError::Disconnected),
+            None => {}
+          }
+        }
+      }
+    }
+  }
+
+  async fn handle_command(&mut self, command: Command) -> Result<(), ClientError> {
+    match command {
+      Command::Publish(mut packet, reply) => {
+        if packet.config.qos == QoS::AtMostOnce {
+          packet.packet_id = None;
+          self.write(DecodedPacket::Publish(packet)).await?;
+          let _ = reply.send(Ok(ReasonCode::Success));
+          return Ok(());
+        }
+
+        let packet_id = match self.allocator.allocate() {
+          Ok(packet_id) => packet_id,
+          Err(reason_code) => {
+            let _ = reply.send(Err(ClientError::ProtocolError(reason_code)));
+            return Ok(());
+          }
+        };
+        packet.packet_id = Some(packet_id);
+
+        if let Err(reason_code) = self.inflight.send(packet.clone()) {
+          let _ = self.allocator.release(packet_id);
+          let _ = reply.send(Err(ClientError::ProtocolError(reason_code)));
+          return Ok(());
+        }
+        self.pending.insert(packet_id, Pending::Publish(reply));
+
+        if let Some(packet) = self.flow_control.send(ExpiringMessage::new(packet, &TokioClock)) {
+          self.write(DecodedPacket::Publish(packet)).await?;
+        }
+      },
+      Command::Subscribe(mut packet, reply) => {
+        match self.allocator.allocate() {
+          Ok(packet_id) => {
+            packet.packet_id = packet_id;
+            self.pending.insert(packet_id, Pending::Subscribe(reply));
+            self.write(DecodedPacket::Subscribe(packet)).await?;
+          },
+          Err(reason_code) => { let _ = reply.send(Err(ClientError::ProtocolError(reason_code))); }
+        }
+      },
+      Command::Unsubscribe(mut packet, reply) => {
+        match self.allocator.allocate() {
+          Ok(packet_id) => {
+            packet.packet_id = packet_id;
+            self.pending.insert(packet_id, Pending::Unsubscribe(reply));
+            self.write(DecodedPacket::Unsubscribe(packet)).await?;
+          },
+          Err(reason_code) => { let _ = reply.send(Err(ClientError::ProtocolError(reason_code))); }
+        }
+      },
+      // handled by the connection loop as it ends the connection
+      Command::Disconnect(..) => {}
+    }
+    Ok(())
+  }
+
+  async fn handle_packet(&mut self, packet: DecodedPacket) -> Result<(), ClientError> {
+    self.keep_alive.received(&packet);
+
+    match packet {
+      DecodedPacket::Publish(packet) => {
+        if let Err(reason_code) = self.flow_control.receive(&packet) {
+          return self.protocol_error(reason_code).await;
+        }
+
+        let received = self.inflight.receive(packet);
+        if let Some(message) = received.message {
+          let _ = self.messages.send(message);
+        }
+        if let Some(response) = received.response {
+          self.flow_control.respond(&response);
+          self.write(response).await?;
+        }
+      },
+      DecodedPacket::Puback(packet) => {
+        if let Err(reason_code) = self.inflight.handle_puback(&packet) {
+          return self.protocol_error(reason_code).await;
+        }
+        self.complete_publish(DecodedPacket::Puback(packet)).await?;
+      },
+      DecodedPacket::Pubrec(packet) => {
+        match self.inflight.handle_pubrec(&packet) {
+          Ok(Some(pubrel)) => self.write(DecodedPacket::Pubrel(pubrel)).await?,
+          Ok(None) => self.complete_publish(DecodedPacket::Pubrec(packet)).await?,
+          Err(reason_code) => return self.protocol_error(reason_code).await
+        }
+      },
+      DecodedPacket::Pubcomp(packet) => {
+        if let Err(reason_code) = self.inflight.handle_pubcomp(&packet) {
+          return self.protocol_error(reason_code).await;
+        }
+        self.complete_publish(DecodedPacket::Pubcomp(packet)).await?;
+      },
+      DecodedPacket::Pubrel(packet) => {
+        let response = DecodedPacket::Pubcomp(self.inflight.handle_pubrel(&packet));
+        self.flow_control.respond(&response);
+        self.write(response).await?;
+      },
+      DecodedPacket::Suback(packet) => {
+        let _ = self.allocator.release(packet.packet_id);
+        if let Some(Pending::Subscribe(reply)) = self.pending.remove(&packet.packet_id) {
+          let _ = reply.send(Ok(packet.reason_codes));
+        }
+      },
+      DecodedPacket::Unsuback(packet) => {
+        let _ = self.allocator.release(packet.packet_id);
+        if let Some(Pending::Unsubscribe(reply)) = self.pending.remove(&packet.packet_id) {
+          let _ = reply.send(Ok(packet.reason_codes));
+        }
+      },
+      DecodedPacket::PingResp(_) => {},
+      _ => return self.protocol_error(ReasonCode::ProtocolError).await
+    }
+    Ok(())
+  }
+
+  // frees the identifier and quota of a finished publish and resolves its response
+  async fn complete_publish(&mut self, packet: DecodedPacket) -> Result<(), ClientError> {
+    let (packet_id, reason_code) = match &packet {
+      DecodedPacket::Puback(packet) => (packet.packet_id, packet.reason_code),
+      DecodedPacket::Pubrec(packet) => (packet.packet_id, packet.reason_code),
+      DecodedPacket::Pubcomp(packet) => (packet.packet_id, packet.reason_code),
+      _ => return Ok(())
+    };
+
+    let _ = self.allocator.acknowledge(&packet);
+    if let Some(Pending::Publish(reply)) = self.pending.remove(&packet_id) {
+      let _ = reply.send(Ok(reason_code));
+    }
+
+    let released = self.flow_control.acknowledge(&packet, &TokioClock);
+    for expired in released.expired {
+      if let Some(packet_id) = expired.packet_id {
+        self.inflight.cancel(packet_id);
+        let _ = self.allocator.release(packet_id);
+        if let Some(Pending::Publish(reply)) = self.pending.remove(&packet_id) {
+          let _ = reply.send(Err(ClientError::MessageExpired));
+        }
+      }
+    }
+    if let Some(next) = released.next {
+      self.write(DecodedPacket::Publish(next)).await?;
+    }
+    Ok(())
+  }
+
+  async fn protocol_error(&mut self, reason_code: ReasonCode) -> Result<(), ClientError> {
+    self.write(DecodedPacket::Disconnect(DisconnectPacket {
+      reason_code,
+      properties: Default::default()
+    })).await?;
+    Err(ClientError::ProtocolError(reason_code))
+  }
+
+  async fn write(&mut self, packet: DecodedPacket) -> Result<(), ClientError> {
+    self.keep_alive.sent();
+    self.framed.send(packet).await?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::convert::TryFrom;
+  use std::time::Duration;
+  use tokio::io::DuplexStream;
+  use super::*;
+
+  fn connect_packet(keep_alive: u16) -> ConnectPacket {
+    ConnectPacket {
+      client_id: "client".to_owned(),
+      clean_start: true,
+      will_config: None,
+      keep_alive,
+      username: None,
+      password: None,
+      properties: Default::default()
+    }
+  }
+
+  fn publish_packet(qos: u8) -> PublishPacket {
+    PublishPacket {
+      topic: "test".to_owned(),
+      packet_id: None,
+      payload: "hello".to_owned(),
+      config: PublishConfig { dup: false, qos: QoS::try_from(qos).unwrap(), retain: false },
+      properties: Default::default()
+    }
+  }
+
+  async fn expect(server: &mut Framed<DuplexStream, MQTTCodec>) -> DecodedPacket {
+    server.next().await.unwrap().unwrap()
+  }
+
+  async fn connect(keep_alive: u16) -> (Client, Messages, Framed<DuplexStream, MQTTCodec>) {
+    let (client_stream, server_stream) = tokio::io::duplex(4096);
+    let mut server = Framed::new(server_stream, MQTTCodec::new());
+
+    let handshake = tokio::spawn(async move {
+      match expect(&mut server).await {
+        DecodedPacket::Connect(packet) => assert_eq!(packet.client_id, "client"),
+        packet => panic!("unexpected {:?}", packet)
+      }
+      server.send(DecodedPacket::Connack(ConnackPacket {
+        session_present: false,
+        reason_code: ReasonCode::Success,
+        properties: Default::default()
+      })).await.unwrap();
+      server
+    });
+
+    let (client, messages) = Client::connect(client_stream, connect_packet(keep_alive)).await.unwrap();
+    (client, messages, handshake.await.unwrap())
+  }
+
+  #[tokio::test]
+  async fn connection_refused_test() {
+    let (client_stream, server_stream) = tokio::io::duplex(4096);
+    let mut server = Framed::new(server_stream, MQTTCodec::new());
+
+    tokio::spawn(async move {
+      expect(&mut server).await;
+      server.send(DecodedPacket::Connack(ConnackPacket {
+        session_present: false,
+        reason_code: ReasonCode::NotAuthorized,
+        properties: Default::default()
+      })).await.unwrap();
+    });
+
+    match Client::connect(client_stream, connect_packet(0)).await {
+      Err(ClientError::ConnectionRefused(ReasonCode::NotAuthorized)) => {},
+      _ => panic!("connection should be refused")
+    }
+  }
+
+  #[tokio::test]
+  async fn publish_qos0_test() {
+    let (client, _messages, mut server) = connect(0).await;
+
+    assert_eq!(client.publish(publish_packet(0)).await.unwrap(), ReasonCode::Success);
+    assert_eq!(expect(&mut server).await, DecodedPacket::Publish(publish_packet(0)));
+  }
+
+  #[tokio::test]
+  async fn publish_qos1_test() {
+    let (client, _messages, mut server) = connect(0).await;
+
+    let response = client.publish(publish_packet(1));
+    let packet_id = match expect(&mut server).await {
+      DecodedPacket::Publish(packet) => packet.packet_id.unwrap(),
+      packet => panic!("unexpected {:?}", packet)
+    };
+    server.send(DecodedPacket::Puback(PubackPacket {
+      packet_id,
+      reason_code: ReasonCode::NoMatchingSubscribers,
+      properties: Default::default()
+    })).await.unwrap();
+
+    assert_eq!(response.await.unwrap(), ReasonCode::NoMatchingSubscribers);
+  }
+
+  #[tokio::test]
+  async fn publish_qos2_test() {
+    let (client, _messages, mut server) = connect(0).await;
+
+    let response = client.publish(publish_packet(2));
+    let packet_id = match expect(&mut server).await {
+      DecodedPacket::Publish(packet) => packet.packet_id.unwrap(),
+      packet => panic!("unexpected {:?}", packet)
+    };
+    server.send(DecodedPacket::Pubrec(PubrecPacket {
+      packet_id,
+      reason_code: ReasonCode::Success,
+      properties: Default::default()
+    })).await.unwrap();
+
+    assert_eq!(expect(&mut server).await, DecodedPacket::Pubrel(PubrelPacket {
+      packet_id,
+      reason_code: ReasonCode::Success,
+      properties: Default::default()
+    }));
+    server.send(DecodedPacket::Pubcomp(PubcompPacket {
+      packet_id,
+      reason_code: ReasonCode::Success,
+      properties: Default::default()
+    })).await.unwrap();
+
+    assert_eq!(response.await.unwrap(), ReasonCode::Success);
+  }
+
+  #[tokio::test]
+  async fn subscribe_and_receive_test() {
+    let (client, mut messages, mut server) = connect(0).await;
+
+    let response = client.subscribe(SubscribePacket {
+      packet_id: 0,
+      subscriptions: vec![SubscriptionConfig {
+        topic: "test".to_owned(),
+        retain_handling: RetainHandling::SendOnSubscribe,
+        rap: false,
+        nl: false,
+        qos: QoS::ExactlyOnce
+      }],
+      properties: Default::default()
+    });
+    let packet_id = match expect(&mut server).await {
+      DecodedPacket::Subscribe(packet) => packet.packet_id,
+      packet => panic!("unexpected {:?}", packet)
+    };
+    assert_ne!(packet_id, 0);
+    server.send(DecodedPacket::Suback(SubackPacket {
+      packet_id,
+      reason_codes: vec![ReasonCode::GrantedQoS2],
+      properties: Default::default()
+    })).await.unwrap();
+    assert_eq!(response.await.unwrap(), vec![ReasonCode::GrantedQoS2]);
+
+    let mut message = publish_packet(2);
+    message.packet_id = Some(7);
+    server.send(DecodedPacket::Publish(message.clone())).await.unwrap();
+    assert_eq!(expect(&mut server).await, DecodedPacket::Pubrec(PubrecPacket {
+      packet_id: 7,
+      reason_code: ReasonCode::Success,
+      properties: Default::default()
+    }));
+
+    // the retransmission is acknowledged again but not delivered twice
+    let mut duplicate = message.clone();
+    duplicate.config.dup = true;
+    server.send(DecodedPacket::Publish(duplicate)).await.unwrap();
+    expect(&mut server).await;
+
+    server.send(DecodedPacket::Pubrel(PubrelPacket {
+      packet_id: 7,
+      reason_code: ReasonCode::Success,
+      properties: Default::default()
+    })).await.unwrap();
+    assert_eq!(expect(&mut server).await, DecodedPacket::Pubcomp(PubcompPacket {
+      packet_id: 7,
+      reason_code: ReasonCode::Success,
+      properties: Default::default()
+    }));
+
+    assert_eq!(messages.next().await.unwrap(), message);
+
+    drop(client);
+    drop(server);
+    assert_eq!(messages.next().await, None);
+  }
+
+  #[tokio::test]
+  async fn unsubscribe_test() {
+    let (client, _messages, mut server) = connect(0).await;
+
+    let response = client.unsubscribe(UnsubscribePacket {
+      packet_id: 0,
+      topics: vec!["test".to_owned()],
+      properties: Default::default()
+    });
+    let packet_id = match expect(&mut server).await {
+      DecodedPacket::Unsubscribe(packet) => packet.packet_id,
+      packet => panic!("unexpected {:?}", packet)
+    };
+    server.send(DecodedPacket::Unsuback(UnsubackPacket {
+      packet_id,
+      reason_codes: vec![ReasonCode::NoSubscriptionExisted],
+      properties: Default::default()
+    })).await.unwrap();
+
+    assert_eq!(response.await.unwrap(), vec![ReasonCode::NoSubscriptionExisted]);
+  }
+
+  #[tokio::test]
+  async fn disconnect_test() {
+    let (client, _messages, mut server) = connect(0).await;
+
+    let pending = client.publish(publish_packet(1));
+    expect(&mut server).await;
+
+    let disconnect = DisconnectPacket {
+      reason_code: ReasonCode::DisconnectWithWill,
+      properties: Default::default()
+    };
+    client.disconnect(disconnect.clone()).await.unwrap();
+    assert_eq!(expect(&mut server).await, DecodedPacket::Disconnect(disconnect));
+
+    match pending.await {
+      Err(ClientError::Disconnected) => {},
+      _ => panic!("pending publish should fail")
+    }
+    match client.publish(publish_packet(0)).await {
+      Err(ClientError::Disconnected) => {},
+      _ => panic!("publish after disconnect should fail")
+    }
+  }
+
+  #[tokio::test]
+  async fn keep_alive_test() {
+    let (_client, _messages, mut server) = connect(5).await;
+    tokio::time::pause();
+
+    tokio::time::advance(Duration::from_secs(5)).await;
+    assert_eq!(expect(&mut server).await, DecodedPacket::PingReq(PingReqPacket {}));
+    server.send(DecodedPacket::PingResp(PingRespPacket {})).await.unwrap();
+  }
+}
@@ -3,11 +3,89 @@ use bytes::{BytesMut, Buf, BufMut};
 
 use crate::{
   types::*,
-  error::{DecodeError, EncodeError},
-  header::*
+  error::{DecodeError, EncodeError, Violation},
+  header::*,
+  conformance,
+  limits
 };
 
-pub struct MQTTCodec {}
+// lenient by default so peers that bend the rules still interoperate; a strict codec also
+// rejects packets that break a normative statement of the specification, on decode and encode
+#[derive(Clone, Debug, Default)]
+pub struct MQTTCodec {
+  strict: bool,
+  // without a role packets for either direction are decoded
+  role: Option<Role>,
+  connected: bool,
+  // when tracked, the limits the peer announced bind what we send and our own bind what we receive
+  track_limits: bool,
+  send_limits: Option<Limits>,
+  receive_limits: Option<Limits>
+}
+
+impl MQTTCodec {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn strict() -> Self {
+    Self { strict: true, ..Self::default() }
+  }
+
+  // only decodes the packets the given side of the connection may receive
+  pub fn with_role(self, role: Role) -> Self {
+    Self { role: Some(role), ..self }
+  }
+
+  // enforces the limits negotiated by CONNECT and CONNACK on every later packet
+  pub fn with_limits(self) -> Self {
+    Self { track_limits: true, ..self }
+  }
+
+  pub fn is_strict(&self) -> bool {
+    self.strict
+  }
+
+  pub fn role(&self) -> Option<Role> {
+    self.role
+  }
+
+  pub fn send_limits(&self) -> Option<&Limits> {
+    self.send_limits.as_ref()
+  }
+
+  pub fn receive_limits(&self) -> Option<&Limits> {
+    self.receive_limits.as_ref()
+  }
+
+  fn announced_limits(&self, packet: &DecodedPacket) -> Option<Limits> {
+    match packet {
+      DecodedPacket::Connect(packet) if self.track_limits => Some(Limits::from_connect(packet)),
+      DecodedPacket::Connack(packet) if self.track_limits => Some(Limits::from_connack(packet)),
+      _ => None
+    }
+  }
+
+  fn check_direction(&self, packet_type: PacketType) -> Result<(), DecodeError> {
+    let role = match self.role {
+      Some(role) => role,
+      None => return Ok(())
+    };
+    if !packet_type.is_received_by(role) {
+      return Err(DecodeError::UnexpectedPacket { packet_type, role });
+    }
+
+    match (role, packet_type, self.connected) {
+      (Role::Server, PacketType::CONNECT, true) => Err(DecodeError::Violation(
+        Violation::new("MQTT-3.1.0-2", ReasonCode::ProtocolError, "second CONNECT on the connection")
+      )),
+      (Role::Server, PacketType::CONNECT, false) | (Role::Client, _, _) | (_, _, true) => Ok(()),
+      _ => Err(DecodeError::Violation(
+        Violation::new("MQTT-3.1.0-1", ReasonCode::ProtocolError, "the first packet on a connection must be CONNECT")
+      ))
+    }
+  }
+}
 
 impl Decoder for MQTTCodec {
   type Item = DecodedPacket;
@@ -19,41 +97,111 @@ impl Decoder for MQTTCodec {
       return Ok(None);
     }
 
-    let (packet_type, publish_config, remaining_length) = decode_fixed_header(&mut read_buffer)?;
+    let (packet_type, publish_config, remaining_length) = match decode_fixed_header(&mut read_buffer) {
+      Err(DecodeError::VariableIntegerIncomplete) => return Ok(None),
+      result => result?
+    };
+    self.check_direction(packet_type)?;
+    let header_length = buffer.remaining() - read_buffer.remaining();
+    if let Some(limits) = &self.receive_limits {
+      limits.check_size(header_length + remaining_length).map_err(DecodeError::Violation)?;
+    }
     if read_buffer.remaining() < remaining_length {
       return Ok(None);
     }
 
-    let decoded_packet = match packet_type {
-      PacketType::CONNECT => ConnectPacket::decode(&mut read_buffer)?,
-      PacketType::CONNACK => ConnackPacket::decode(&mut read_buffer)?,
-      PacketType::PUBLISH => {
-        match publish_config {
-          Some(config) => PublishPacket::decode(&mut read_buffer, config, remaining_length)?,
-          _ => return Err(DecodeError::FormatError) 
-        }
-      },
-      PacketType::PUBACK => PubackPacket::decode(&mut read_buffer, remaining_length)?,
-      PacketType::SUBSCRIBE => SubscribePacket::decode(&mut read_buffer, remaining_length)?,
-      PacketType::SUBACK => SubackPacket::decode(&mut read_buffer, remaining_length)?,
-      PacketType::UNSUBSCRIBE => UnsubscribePacket::decode(&mut read_buffer, remaining_length)?,
-      PacketType::UNSUBACK => UnsubackPacket::decode(&mut read_buffer, remaining_length)?,
-      PacketType::PINGREQ => PingReqPacket::decode(&mut read_buffer)?,
-      PacketType::PINGRESP => PingRespPacket::decode(&mut read_buffer)?,
-      _ => return Err(DecodeError::FormatError)
-    };
+    // the body is cut out so that a malformed packet cannot read into the next one
+    let mut body = read_buffer.split_to(remaining_length);
+    let decoded_packet = decode_packet(&mut body, packet_type, publish_config, remaining_length)
+      .map_err(|error| DecodeError::Packet {
+        packet_type,
+        offset: header_length + remaining_length - body.remaining(),
+        error: Box::new(error)
+      })?;
+
+    if self.strict {
+      conformance::check_header_flags(buffer[0]).map_err(DecodeError::Violation)?;
+      let (header, body) = buffer[..header_length + remaining_length].split_at(header_length);
+      if let DecodedPacket::Connect(_) = decoded_packet {
+        conformance::check_connect_flags(body[conformance::connect_flags_offset(body)]).map_err(DecodeError::Violation)?;
+      }
+      conformance::check_variable_integers(&decoded_packet, header, body).map_err(DecodeError::Violation)?;
+      conformance::check_packet(&decoded_packet).map_err(DecodeError::Violation)?;
+    }
+
+    if let Some(limits) = &self.receive_limits {
+      limits.check(&decoded_packet).map_err(DecodeError::Violation)?;
+    }
 
-    buffer.advance(buffer.remaining() - read_buffer.remaining());
+    if packet_type == PacketType::CONNECT {
+      self.connected = true;
+    }
+    if let Some(limits) = self.announced_limits(&decoded_packet) {
+      self.send_limits = Some(limits);
+    }
+    buffer.advance(header_length + remaining_length);
     Ok(Some(decoded_packet))
   }
 }
 
-impl Encoder<DecodedPacket> for MQTTCodec {
-  type Error = EncodeError;
-  fn encode(&mut self, item: DecodedPacket, buffer: &mut BytesMut) -> Result<(), Self::Error> {
+fn decode_packet(buffer: &mut BytesMut, packet_type: PacketType, publish_config: Option<PublishConfig>,
+  remaining_length: usize) -> Result<DecodedPacket, DecodeError> {
+
+  match packet_type {
+    PacketType::CONNECT => ConnectPacket::decode(buffer),
+    PacketType::CONNACK => ConnackPacket::decode(buffer),
+    PacketType::PUBLISH => PublishPacket::decode(buffer, publish_config.unwrap_or_default(), remaining_length),
+    PacketType::PUBACK => PubackPacket::decode(buffer, remaining_length),
+    PacketType::PUBREC => PubrecPacket::decode(buffer, remaining_length),
+    PacketType::PUBREL => PubrelPacket::decode(buffer, remaining_length),
+    PacketType::PUBCOMP => PubcompPacket::decode(buffer, remaining_length),
+    PacketType::SUBSCRIBE => SubscribePacket::decode(buffer, remaining_length),
+    PacketType::SUBACK => SubackPacket::decode(buffer, remaining_length),
+    PacketType::UNSUBSCRIBE => UnsubscribePacket::decode(buffer, remaining_length),
+    PacketType::UNSUBACK => UnsubackPacket::decode(buffer, remaining_length),
+    PacketType::PINGREQ => PingReqPacket::decode(buffer),
+    PacketType::PINGRESP => PingRespPacket::decode(buffer),
+    PacketType::DISCONNECT => DisconnectPacket::decode(buffer, remaining_length),
+    _ => Err(DecodeError::UnsupportedPacketType(packet_type))
+  }
+}
+
+impl MQTTCodec {
+  // encodes the packet within the receiver's Maximum Packet Size, leaving out optional properties that do not fit;
+  // a packet that still does not fit is not written
+  pub fn encode_within(&mut self, item: DecodedPacket, maximum_packet_size: u32, buffer: &mut BytesMut) -> Result<Trimmed, EncodeError> {
+    self.encode_packet(item, Some(maximum_packet_size), buffer)
+  }
+
+  fn encode_packet(&mut self, mut item: DecodedPacket, maximum_packet_size: Option<u32>, buffer: &mut BytesMut) -> Result<Trimmed, EncodeError> {
+    if self.strict {
+      conformance::check_packet(&item).map_err(EncodeError::Violation)?;
+    }
+    if let Some(limits) = &self.send_limits {
+      limits.check(&item).map_err(EncodeError::Violation)?;
+    }
+
     let mut content = bytes::BytesMut::new();
     item.encode(&mut content)?;
 
+    let negotiated = self.send_limits.as_ref().and_then(|limits| limits.maximum_packet_size);
+    let trimmed = match maximum_packet_size.into_iter().chain(negotiated).min() {
+      Some(maximum) => limits::trim(&mut item, &mut content, maximum)?,
+      None => Trimmed::Nothing
+    };
+    let size = packet_size(content.len());
+    if let Some(limits) = &self.send_limits {
+      limits.check_size(size).map_err(EncodeError::Violation)?;
+    }
+    if let Some(maximum) = maximum_packet_size {
+      if size > maximum as usize {
+        return Err(EncodeError::PacketTooLarge { size, maximum });
+      }
+    }
+    if let Some(limits) = self.announced_limits(&item) {
+      self.receive_limits = Some(limits);
+    }
+
     let packet_type = item.get_type();
     buffer.reserve(content.len());
     let config = match item {
@@ -63,6 +211,231 @@ impl Encoder<DecodedPacket> for MQTTCodec {
     encode_fixed_header(buffer, packet_type, &content, config)?;
 
     buffer.put(content);
-    Ok(())
+    Ok(trimmed)
   }
-}
\ No newline at end of file
+}
+
+impl Encoder<DecodedPacket> for MQTTCodec {
+  type Error = EncodeError;
+  fn encode(&mut self, item: DecodedPacket, buffer: &mut BytesMut) -> Result<(), Self::Error> {
+    self.encode_packet(item, None, buffer).map(|_| ())
+  }
+}
+#[cfg(test)]
+mod tests {
+  use crate::reason_code::ReasonCode;
+  use super::*;
+
+  fn decode(bytes: &[u8]) -> Result<Option<DecodedPacket>, DecodeError> {
+    MQTTCodec::new().decode(&mut BytesMut::from(bytes))
+  }
+
+  #[test]
+  fn packet_context_test() {
+    // a PUBACK with a Server Keep Alive property
+    let error = decode(&[0x40, 0x07, 0x00, 0x01, 0x00, 0x03, 0x13, 0x00, 0x0a]).unwrap_err();
+    match &error {
+      DecodeError::Packet { packet_type: PacketType::PUBACK, offset: 9, .. } => {},
+      error => panic!("unexpected error {:?}", error)
+    }
+    assert!(matches!(error.cause(), DecodeError::PropertyNotAllowed(0x13)));
+    assert_eq!(error.reason_code(), ReasonCode::MalformedPacket);
+    assert_eq!(error.to_string(), "PUBACK packet at byte 9: property 0x13 not allowed on this packet");
+  }
+
+  #[test]
+  fn reason_code_test() {
+    // a CONNECT for MQTT 3.1.1
+    let error = decode(&[0x10, 0x07, 0x00, 0x04, b'M', b'Q', b'T', b'T', 0x04]).unwrap_err();
+    assert!(matches!(error.cause(), DecodeError::UnsupportedProtocolVersion(4)));
+    assert_eq!(error.reason_code(), ReasonCode::UnsupportedProtocolVersion);
+
+    // a QoS 0 PUBLISH to a topic with a wildcard
+    let error = decode(&[0x30, 0x04, 0x00, 0x01, b'#', 0x00]).unwrap_err();
+    assert_eq!(error.reason_code(), ReasonCode::TopicNameInvalid);
+
+    // a SUBSCRIBE with the reserved header flags cleared
+    let error = decode(&[0x80, 0x00]).unwrap_err();
+    assert!(matches!(error, DecodeError::InvalidHeaderFlags { packet_type: PacketType::SUBSCRIBE, flags: 0 }));
+    assert_eq!(error.reason_code(), ReasonCode::MalformedPacket);
+
+    // a PUBLISH with QoS 3
+    assert!(matches!(decode(&[0x36, 0x00]).unwrap_err(), DecodeError::InvalidQoS(3)));
+
+    let error = decode(&[0xF0, 0x00]).unwrap_err();
+    assert!(matches!(error.cause(), DecodeError::UnsupportedPacketType(PacketType::AUTH)));
+    assert_eq!(error.reason_code(), ReasonCode::ProtocolError);
+  }
+
+  #[test]
+  fn strict_decode_test() {
+    // a CONNECT with Will QoS 1 but no Will Flag
+    let bytes = [0x10, 0x0D, 0x00, 0x04, b'M', b'Q', b'T', b'T', 0x05, 0x0A, 0x00, 0x00, 0x00, 0x00, 0x00];
+    assert!(MQTTCodec::new().decode(&mut BytesMut::from(&bytes[..])).unwrap().is_some());
+
+    match MQTTCodec::strict().decode(&mut BytesMut::from(&bytes[..])) {
+      Err(DecodeError::Violation(violation)) => {
+        assert_eq!(violation.statement, Some("MQTT-3.1.2-11"));
+        assert_eq!(violation.to_string(), "Will QoS set without the Will Flag [MQTT-3.1.2-11]");
+      },
+      result => panic!("unexpected result {:?}", result)
+    }
+
+    // a QoS 0 PUBLISH with DUP set
+    let bytes = [0x38, 0x04, 0x00, 0x01, b'a', 0x00];
+    assert!(MQTTCodec::new().decode(&mut BytesMut::from(&bytes[..])).unwrap().is_some());
+    let error = MQTTCodec::strict().decode(&mut BytesMut::from(&bytes[..])).unwrap_err();
+    assert!(matches!(&error, DecodeError::Violation(violation) if violation.statement == Some("MQTT-3.3.1-2")));
+    assert_eq!(error.reason_code(), ReasonCode::MalformedPacket);
+
+    // a PINGREQ with a reserved header flag set
+    let bytes = [0xC1, 0x00];
+    assert!(MQTTCodec::new().decode(&mut BytesMut::from(&bytes[..])).unwrap().is_some());
+    let error = MQTTCodec::strict().decode(&mut BytesMut::from(&bytes[..])).unwrap_err();
+    assert!(matches!(&error, DecodeError::Violation(violation) if violation.statement == Some("MQTT-2.1.3-1")));
+    assert_eq!(error.reason_code(), ReasonCode::MalformedPacket);
+  }
+
+  #[test]
+  fn truncated_packet_test() {
+    // a PUBACK, CONNACK, CONNECT, PUBLISH and DISCONNECT cut short, then a PUBACK whose properties run past the packet
+    let packets: [&[u8]; 7] = [
+      &[0x40, 0x01, 0x00],
+      &[0x20, 0x00],
+      &[0x10, 0x02, 0x00, 0x04],
+      &[0x30, 0x01, 0x00],
+      &[0x30, 0x03, 0x00, 0x05, 0x61],
+      &[0xE0, 0x02, 0x00, 0x05],
+      &[0x40, 0x05, 0x00, 0x01, 0x00, 0x10, 0x01]
+    ];
+    for bytes in packets.iter() {
+      let error = decode(bytes).unwrap_err();
+      assert!(matches!(&error, DecodeError::Packet { error, .. } if matches!(**error, DecodeError::UnexpectedEnd)), "{:02x?}", bytes);
+      assert_eq!(error.reason_code(), ReasonCode::MalformedPacket);
+    }
+
+    // a PUBACK whose Correlation Data declares two bytes with one present
+    let error = decode(&[0x40, 0x06, 0x00, 0x01, 0x00, 0x02, 0x09, 0x00]).unwrap_err();
+    assert_eq!(error.reason_code(), ReasonCode::MalformedPacket);
+
+    // the bytes of the next packet are not read as part of a truncated one
+    let mut buffer = BytesMut::from(&[0x40, 0x01, 0x00, 0xC0, 0x00][..]);
+    assert!(MQTTCodec::new().decode(&mut buffer).is_err());
+  }
+
+  #[test]
+  fn remaining_length_test() {
+    // the remaining length is cut off, then too long
+    assert!(decode(&[0x30, 0x80]).unwrap().is_none());
+    assert!(decode(&[0x30, 0xFF, 0xFF, 0xFF]).unwrap().is_none());
+    assert!(matches!(decode(&[0x30, 0xFF, 0xFF, 0xFF, 0xFF]).unwrap_err(), DecodeError::VariableIntegerTooLong));
+
+    // a PINGREQ, then a PUBACK with an empty property section, each with an overlong length
+    for bytes in [&[0xC0, 0x80, 0x00][..], &[0x40, 0x05, 0x00, 0x01, 0x00, 0x80, 0x00]] {
+      assert!(decode(bytes).unwrap().is_some());
+      let error = MQTTCodec::strict().decode(&mut BytesMut::from(bytes)).unwrap_err();
+      assert!(matches!(&error, DecodeError::Violation(violation) if violation.statement == Some("MQTT-1.5.5-1")));
+      assert_eq!(error.reason_code(), ReasonCode::MalformedPacket);
+    }
+
+    // a SUBSCRIBE with Subscription Identifier 1 written in two bytes
+    let bytes = [0x82, 0x0A, 0x00, 0x01, 0x03, 0x0B, 0x81, 0x00, 0x00, 0x01, b'a', 0x00];
+    assert!(decode(&bytes).unwrap().is_some());
+    assert!(matches!(MQTTCodec::strict().decode(&mut BytesMut::from(&bytes[..])), Err(DecodeError::Violation(_))));
+  }
+
+  #[test]
+  fn role_test() {
+    let connect = [0x10, 0x0D, 0x00, 0x04, b'M', b'Q', b'T', b'T', 0x05, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00];
+    let connack = [0x20, 0x03, 0x00, 0x00, 0x00];
+    let pingreq = [0xC0, 0x00];
+
+    let mut server = MQTTCodec::new().with_role(Role::Server);
+    let error = server.decode(&mut BytesMut::from(&pingreq[..])).unwrap_err();
+    assert!(matches!(&error, DecodeError::Violation(violation) if violation.statement == Some("MQTT-3.1.0-1")));
+    assert_eq!(error.reason_code(), ReasonCode::ProtocolError);
+
+    assert!(server.decode(&mut BytesMut::from(&connect[..])).unwrap().is_some());
+    assert!(server.decode(&mut BytesMut::from(&pingreq[..])).unwrap().is_some());
+    let error = server.decode(&mut BytesMut::from(&connack[..])).unwrap_err();
+    assert!(matches!(error, DecodeError::UnexpectedPacket { packet_type: PacketType::CONNACK, role: Role::Server }));
+    assert_eq!(error.reason_code(), ReasonCode::ProtocolError);
+
+    let error = server.decode(&mut BytesMut::from(&connect[..])).unwrap_err();
+    assert!(matches!(&error, DecodeError::Violation(violation) if violation.statement == Some("MQTT-3.1.0-2")));
+    assert_eq!(error.reason_code(), ReasonCode::ProtocolError);
+
+    let mut client = MQTTCodec::new().with_role(Role::Client);
+    assert!(client.decode(&mut BytesMut::from(&connack[..])).unwrap().is_some());
+    assert!(matches!(client.decode(&mut BytesMut::from(&connect[..])), Err(DecodeError::UnexpectedPacket { .. })));
+
+    // without a role either direction is decoded
+    assert!(decode(&connack).unwrap().is_some());
+    assert!(decode(&pingreq).unwrap().is_some());
+  }
+
+  #[test]
+  fn limits_test() {
+    let mut client = MQTTCodec::new().with_role(Role::Client).with_limits();
+    let mut server = MQTTCodec::new().with_role(Role::Server).with_limits();
+    let mut buffer = BytesMut::new();
+
+    let connect = ConnectPacket::builder().maximum_packet_size(16).build().unwrap();
+    client.encode(DecodedPacket::Connect(connect), &mut buffer).unwrap();
+    server.decode(&mut buffer).unwrap().unwrap();
+    let connack = ConnackPacket::builder().retain_available(0).build().unwrap();
+    server.encode(DecodedPacket::Connack(connack), &mut buffer).unwrap();
+    client.decode(&mut buffer).unwrap().unwrap();
+    assert_eq!(client.send_limits(), server.receive_limits());
+    assert_eq!(server.send_limits().unwrap().maximum_packet_size, Some(16));
+
+    // the client refuses to send what the server does not support, and the server to receive it
+    let retained = DecodedPacket::Publish(PublishPacket::builder("a").retain(true).build().unwrap());
+    let error = client.encode(retained.clone(), &mut BytesMut::new()).unwrap_err();
+    assert!(matches!(error, EncodeError::Violation(violation) if violation.reason_code == ReasonCode::RetainNotSupported));
+    MQTTCodec::new().encode(retained, &mut buffer).unwrap();
+    let error = server.decode(&mut buffer).unwrap_err();
+    assert_eq!(error.reason_code(), ReasonCode::RetainNotSupported);
+
+    // 2 bytes of fixed header, 3 of topic and 1 of properties leave 10 for the payload
+    let publish = |payload: &str| DecodedPacket::Publish(PublishPacket::builder("a").payload(payload).build().unwrap());
+    assert!(server.encode(publish("0123456789"), &mut BytesMut::new()).is_ok());
+    let error = server.encode(publish("0123456789a"), &mut BytesMut::new()).unwrap_err();
+    assert!(matches!(error, EncodeError::Violation(violation) if violation.reason_code == ReasonCode::PacketTooLarge));
+
+    // the size is checked as soon as the fixed header is read
+    let mut buffer = BytesMut::from(&[0x30, 0x0F][..]);
+    assert_eq!(client.decode(&mut buffer).unwrap_err().reason_code(), ReasonCode::PacketTooLarge);
+  }
+
+  #[test]
+  fn encode_within_test() {
+    let puback = DecodedPacket::Puback(PubackPacket::builder(1).reason_string("abcdefgh").user_property("a", "b").build().unwrap());
+    let encode = |maximum| {
+      let mut buffer = BytesMut::new();
+      MQTTCodec::new().encode_within(puback.clone(), maximum, &mut buffer).map(|trimmed| (trimmed, buffer.len()))
+    };
+
+    assert_eq!(encode(24).unwrap(), (Trimmed::Nothing, 24));
+    assert_eq!(encode(23).unwrap(), (Trimmed::ReasonString, 13));
+    assert_eq!(encode(12).unwrap(), (Trimmed::UserProperties, 5));
+    assert!(matches!(encode(4), Err(EncodeError::PacketTooLarge { size: 5, maximum: 4 })));
+
+    // the properties of a PUBLISH are part of the application message and are never left out
+    let publish = DecodedPacket::Publish(PublishPacket::builder("a").user_property("a", "b").build().unwrap());
+    let mut buffer = BytesMut::new();
+    assert!(matches!(MQTTCodec::new().encode_within(publish, 8, &mut buffer), Err(EncodeError::PacketTooLarge { .. })));
+    assert!(buffer.is_empty());
+  }
+
+  #[test]
+  fn strict_encode_test() {
+    let packet = DecodedPacket::Unsubscribe(UnsubscribePacket { packet_id: 0, topics: vec![], properties: Default::default() });
+    assert!(MQTTCodec::new().encode(packet.clone(), &mut BytesMut::new()).is_ok());
+
+    match MQTTCodec::strict().encode(packet, &mut BytesMut::new()) {
+      Err(EncodeError::Violation(violation)) => assert_eq!(violation.statement, Some("MQTT-2.2.1-3")),
+      result => panic!("unexpected result {:?}", result)
+    }
+  }
+}
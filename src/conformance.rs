@@ -0,0 +1,391 @@
+use bytes::{BytesMut, Buf};
+
+use crate::{
+  types::*,
+  error::{DecodeError, Violation},
+  utils::contains_non_character,
+  variable_integer,
+  topic
+};
+
+fn violation(statement: &'static str, reason_code: ReasonCode, description: &'static str) -> Result<(), Violation> {
+  Err(Violation::new(statement, reason_code, description))
+}
+
+// the normative rules a well-formed packet has to follow, checked by the codec in strict mode
+pub fn check_packet(packet: &DecodedPacket) -> Result<(), Violation> {
+  check_strings(packet)?;
+  match packet {
+    DecodedPacket::Connect(packet) => check_connect(packet),
+    DecodedPacket::Connack(packet) => check_connack(packet),
+    DecodedPacket::Publish(packet) => check_publish(packet),
+    DecodedPacket::Puback(packet) => check_acknowledgement(packet.packet_id, packet.reason_code, PacketType::PUBACK),
+    DecodedPacket::Pubrec(packet) => check_acknowledgement(packet.packet_id, packet.reason_code, PacketType::PUBREC),
+    DecodedPacket::Pubrel(packet) => check_acknowledgement(packet.packet_id, packet.reason_code, PacketType::PUBREL),
+    DecodedPacket::Pubcomp(packet) => check_acknowledgement(packet.packet_id, packet.reason_code, PacketType::PUBCOMP),
+    DecodedPacket::Subscribe(packet) => check_subscribe(packet),
+    DecodedPacket::Suback(packet) => check_subscription_acknowledgement(packet.packet_id, &packet.reason_codes, PacketType::SUBACK),
+    DecodedPacket::Unsubscribe(packet) => check_unsubscribe(packet),
+    DecodedPacket::Unsuback(packet) => check_subscription_acknowledgement(packet.packet_id, &packet.reason_codes, PacketType::UNSUBACK),
+    DecodedPacket::PingReq(_) | DecodedPacket::PingResp(_) => Ok(()),
+    DecodedPacket::Disconnect(packet) => check_reason_code(packet.reason_code, PacketType::DISCONNECT)
+  }
+}
+
+// the UTF-8 encoded strings of the packet, payloads and binary data aside
+fn check_strings(packet: &DecodedPacket) -> Result<(), Violation> {
+  let (strings, properties): (Vec<&str>, Vec<Property>) = match packet {
+    DecodedPacket::Connect(packet) => {
+      let mut strings = vec![packet.client_id.as_str()];
+      strings.extend(packet.username.as_deref());
+      let mut properties = packet.properties.to_properties();
+      if let Some(will) = &packet.will_config {
+        strings.push(&will.topic);
+        properties.extend(will.properties.to_properties());
+      }
+      (strings, properties)
+    },
+    DecodedPacket::Connack(packet) => (vec![], packet.properties.to_properties()),
+    DecodedPacket::Publish(packet) => (vec![&packet.topic], packet.properties.to_properties()),
+    DecodedPacket::Puback(packet) => (vec![], packet.properties.to_properties()),
+    DecodedPacket::Pubrec(packet) => (vec![], packet.properties.to_properties()),
+    DecodedPacket::Pubrel(packet) => (vec![], packet.properties.to_properties()),
+    DecodedPacket::Pubcomp(packet) => (vec![], packet.properties.to_properties()),
+    DecodedPacket::Subscribe(packet) => {
+      (packet.subscriptions.iter().map(|subscription| subscription.topic.as_str()).collect(), packet.properties.to_properties())
+    },
+    DecodedPacket::Suback(packet) => (vec![], packet.properties.to_properties()),
+    DecodedPacket::Unsubscribe(packet) => (packet.topics.iter().map(String::as_str).collect(), packet.properties.to_properties()),
+    DecodedPacket::Unsuback(packet) => (vec![], packet.properties.to_properties()),
+    DecodedPacket::PingReq(_) | DecodedPacket::PingResp(_) => (vec![], vec![]),
+    DecodedPacket::Disconnect(packet) => (vec![], packet.properties.to_properties())
+  };
+
+  let property_strings = properties.iter().flat_map(|property| match property {
+    Property::ContentType(string) | Property::ResponseTopic(string) | Property::AssignedClientIdentifier(string) |
+    Property::AuthenticationMethod(string) | Property::ResponseInformation(string) | Property::ServerReference(string) |
+    Property::ReasonString(string) => vec![string.as_str()],
+    Property::UserProperty(pair) => vec![pair.name(), pair.value()],
+    _ => vec![]
+  });
+
+  match strings.into_iter().chain(property_strings).any(contains_non_character) {
+    true => Err(Violation::unnumbered("1.5.4", ReasonCode::MalformedPacket, "UTF-8 Encoded String contains a Unicode non-character")),
+    false => Ok(())
+  }
+}
+
+// the header flags of every packet but PUBLISH are reserved; the decoder already rejects
+// wrong flags on PUBREL, SUBSCRIBE and UNSUBSCRIBE, so this catches the packets whose flags must be 0
+pub fn check_header_flags(first_byte: u8) -> Result<(), Violation> {
+  let reserved = match PacketType::ALL[(first_byte >> 4) as usize] {
+    PacketType::PUBLISH => return Ok(()),
+    PacketType::PUBREL | PacketType::SUBSCRIBE | PacketType::UNSUBSCRIBE => 0b0010,
+    _ => 0b0000
+  };
+  match first_byte & 0b1111 == reserved {
+    true => Ok(()),
+    false => violation("MQTT-2.1.3-1", ReasonCode::MalformedPacket, "reserved fixed header flags set to a wrong value")
+  }
+}
+
+// the will flags are dropped on decode when there is no will, so they are checked on the raw byte
+pub fn check_connect_flags(connect_flags: u8) -> Result<(), Violation> {
+  if connect_flags & 0b100 != 0 {
+    return Ok(());
+  }
+  if connect_flags & 0b11000 != 0 {
+    return violation("MQTT-3.1.2-11", ReasonCode::MalformedPacket, "Will QoS set without the Will Flag");
+  }
+  if connect_flags & 0b100000 != 0 {
+    return violation("MQTT-3.1.2-13", ReasonCode::MalformedPacket, "Will Retain set without the Will Flag");
+  }
+  Ok(())
+}
+
+// the connect flags follow the protocol name and the protocol version; body is a decoded CONNECT
+pub fn connect_flags_offset(body: &[u8]) -> usize {
+  2 + u16::from_be_bytes([body[0], body[1]]) as usize + 1
+}
+
+// the decoders accept overlong variable byte integers, so the remaining length and every property
+// section are read again from the raw packet; header is the fixed header and body the rest
+pub fn check_variable_integers(packet: &DecodedPacket, header: &[u8], body: &[u8]) -> Result<(), Violation> {
+  if header.len() - 1 > variable_integer::encoded_length(body.len() as u64) {
+    return overlong();
+  }
+
+  match packet {
+    DecodedPacket::Connect(packet) => {
+      // the connect flags and keep alive come before the properties
+      let client_id_offset = check_properties(body, connect_flags_offset(body) + 3)?;
+      if packet.will_config.is_some() {
+        check_properties(body, client_id_offset + 2 + packet.client_id.len())?;
+      }
+      Ok(())
+    },
+    DecodedPacket::Publish(packet) => {
+      let packet_id_length = if packet.packet_id.is_some() { 2 } else { 0 };
+      check_properties(body, 2 + packet.topic.len() + packet_id_length).map(|_| ())
+    },
+    DecodedPacket::Puback(_) | DecodedPacket::Pubrec(_) | DecodedPacket::Pubrel(_) | DecodedPacket::Pubcomp(_) if body.len() > 3 => {
+      check_properties(body, 3).map(|_| ())
+    },
+    DecodedPacket::Disconnect(_) if body.len() > 1 => check_properties(body, 1).map(|_| ()),
+    DecodedPacket::Connack(_) | DecodedPacket::Subscribe(_) | DecodedPacket::Suback(_)
+      | DecodedPacket::Unsubscribe(_) | DecodedPacket::Unsuback(_) => check_properties(body, 2).map(|_| ()),
+    _ => Ok(())
+  }
+}
+
+// the offset right after the property section
+fn check_properties(body: &[u8], offset: usize) -> Result<usize, Violation> {
+  let mut buffer = BytesMut::from(&body[offset..]);
+  match Property::decode_with(&mut buffer, true) {
+    Err(DecodeError::VariableIntegerNotMinimal) => overlong(),
+    _ => Ok(body.len() - buffer.remaining())
+  }
+}
+
+fn overlong<T>() -> Result<T, Violation> {
+  Err(Violation::new("MQTT-1.5.5-1", ReasonCode::MalformedPacket, "Variable Byte Integer not encoded in the minimum number of bytes"))
+}
+
+fn check_connect(packet: &ConnectPacket) -> Result<(), Violation> {
+  if packet.properties.receive_maximum == Some(0) {
+    return Err(Violation::unnumbered("3.1.2.11.3", ReasonCode::ProtocolError, "Receive Maximum must not be 0"));
+  }
+  if packet.properties.maximum_packet_size == Some(0) {
+    return Err(Violation::unnumbered("3.1.2.11.4", ReasonCode::ProtocolError, "Maximum Packet Size must not be 0"));
+  }
+  Ok(())
+}
+
+fn check_connack(packet: &ConnackPacket) -> Result<(), Violation> {
+  check_reason_code(packet.reason_code, PacketType::CONNACK)?;
+  if packet.session_present && packet.reason_code.is_error() {
+    return violation("MQTT-3.2.2-6", ReasonCode::ProtocolError, "Session Present set on a refused connection");
+  }
+  Ok(())
+}
+
+fn check_publish(packet: &PublishPacket) -> Result<(), Violation> {
+  match (packet.config.qos, packet.packet_id) {
+    (QoS::AtMostOnce, Some(_)) => return violation("MQTT-2.2.1-2", ReasonCode::MalformedPacket, "Packet Identifier on a QoS 0 PUBLISH"),
+    (_, Some(0)) | (QoS::AtLeastOnce, None) | (QoS::ExactlyOnce, None) => return violation("MQTT-2.2.1-3", ReasonCode::MalformedPacket, "Packet Identifier must be non-zero"),
+    _ => {}
+  }
+  if packet.config.qos == QoS::AtMostOnce && packet.config.dup {
+    return violation("MQTT-3.3.1-2", ReasonCode::MalformedPacket, "DUP set on a QoS 0 PUBLISH");
+  }
+  if packet.properties.topic_alias == Some(0) {
+    return violation("MQTT-3.3.2-8", ReasonCode::TopicAliasInvalid, "Topic Alias must not be 0");
+  }
+  Ok(())
+}
+
+fn check_acknowledgement(packet_id: u16, reason_code: ReasonCode, packet_type: PacketType) -> Result<(), Violation> {
+  if packet_id == 0 {
+    return violation("MQTT-2.2.1-5", ReasonCode::ProtocolError, "Packet Identifier must match a request and be non-zero");
+  }
+  check_reason_code(reason_code, packet_type)
+}
+
+fn check_subscribe(packet: &SubscribePacket) -> Result<(), Violation> {
+  if packet.packet_id == 0 {
+    return violation("MQTT-2.2.1-3", ReasonCode::MalformedPacket, "Packet Identifier must be non-zero");
+  }
+  if packet.subscriptions.is_empty() {
+    return violation("MQTT-3.8.3-2", ReasonCode::ProtocolError, "SUBSCRIBE must contain at least one Topic Filter");
+  }
+  for subscription in packet.subscriptions.iter() {
+    check_topic_filter(&subscription.topic)?;
+    if subscription.nl && subscription.topic.starts_with("$share/") {
+      return violation("MQTT-3.8.3-4", ReasonCode::ProtocolError, "No Local set on a Shared Subscription");
+    }
+  }
+  if packet.properties.subscription_identifier == Some(0) {
+    return Err(Violation::unnumbered("3.8.2.1.2", ReasonCode::ProtocolError, "Subscription Identifier must not be 0"));
+  }
+  Ok(())
+}
+
+fn check_unsubscribe(packet: &UnsubscribePacket) -> Result<(), Violation> {
+  if packet.packet_id == 0 {
+    return violation("MQTT-2.2.1-3", ReasonCode::MalformedPacket, "Packet Identifier must be non-zero");
+  }
+  if packet.topics.is_empty() {
+    return violation("MQTT-3.10.3-2", ReasonCode::ProtocolError, "UNSUBSCRIBE must contain at least one Topic Filter");
+  }
+  for filter in packet.topics.iter() {
+    check_topic_filter(filter)?;
+  }
+  Ok(())
+}
+
+fn check_topic_filter(filter: &str) -> Result<(), Violation> {
+  if topic::is_valid_filter(filter) {
+    return Ok(());
+  }
+  if filter.is_empty() {
+    return violation("MQTT-4.7.3-1", ReasonCode::TopicFilterInvalid, "Topic Filter must be at least one character long");
+  }
+  let levels: Vec<&str> = filter.split('/').collect();
+  match levels.iter().enumerate().any(|(index, level)| level.contains('#') && (*level != "#" || index != levels.len() - 1)) {
+    true => violation("MQTT-4.7.1-1", ReasonCode::TopicFilterInvalid, "multi-level wildcard must be the last level of the Topic Filter"),
+    false => violation("MQTT-4.7.1-2", ReasonCode::TopicFilterInvalid, "single-level wildcard must occupy an entire level of the Topic Filter")
+  }
+}
+
+fn check_subscription_acknowledgement(packet_id: u16, reason_codes: &[ReasonCode], packet_type: PacketType) -> Result<(), Violation> {
+  if packet_id == 0 {
+    return violation("MQTT-2.2.1-5", ReasonCode::ProtocolError, "Packet Identifier must match a request and be non-zero");
+  }
+  for reason_code in reason_codes {
+    check_reason_code(*reason_code, packet_type)?;
+  }
+  Ok(())
+}
+
+// decoding always rejects these, so this only matters when encoding
+fn check_reason_code(reason_code: ReasonCode, packet_type: PacketType) -> Result<(), Violation> {
+  match reason_code.is_allowed(packet_type) {
+    true => Ok(()),
+    false => Err(Violation::unnumbered("2.4", ReasonCode::ProtocolError, "Reason Code not allowed on this packet"))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn statement(packet: DecodedPacket) -> Option<&'static str> {
+    check_packet(&packet).unwrap_err().statement
+  }
+
+  fn section(packet: DecodedPacket) -> &'static str {
+    check_packet(&packet).unwrap_err().section
+  }
+
+  #[test]
+  fn connect_flags_test() {
+    assert!(check_connect_flags(0b00000010).is_ok());
+    assert!(check_connect_flags(0b00111110).is_ok());
+    assert_eq!(check_connect_flags(0b00001010).unwrap_err().statement, Some("MQTT-3.1.2-11"));
+    assert_eq!(check_connect_flags(0b00100010).unwrap_err().statement, Some("MQTT-3.1.2-13"));
+
+    let body = [0x00, 0x04, b'M', b'Q', b'T', b'T', 0x05, 0b00000010, 0x00, 0x3C];
+    assert_eq!(connect_flags_offset(&body), 7);
+  }
+
+  #[test]
+  fn header_flags_test() {
+    assert!(check_header_flags(0xC0).is_ok());
+    assert!(check_header_flags(0x3F).is_ok());
+    assert!(check_header_flags(0x62).is_ok());
+    assert_eq!(check_header_flags(0xC1).unwrap_err().statement, Some("MQTT-2.1.3-1"));
+    assert_eq!(check_header_flags(0x48).unwrap_err().statement, Some("MQTT-2.1.3-1"));
+  }
+
+  #[test]
+  fn publish_test() {
+    let publish = |qos, packet_id, dup| DecodedPacket::Publish(PublishPacket {
+      topic: "a".to_owned(),
+      packet_id,
+      payload: String::new(),
+      config: PublishConfig { dup, qos, retain: false },
+      properties: Default::default()
+    });
+
+    assert!(check_packet(&publish(QoS::AtMostOnce, None, false)).is_ok());
+    assert!(check_packet(&publish(QoS::AtLeastOnce, Some(1), true)).is_ok());
+    assert_eq!(statement(publish(QoS::AtMostOnce, Some(1), false)), Some("MQTT-2.2.1-2"));
+    assert_eq!(statement(publish(QoS::AtLeastOnce, Some(0), false)), Some("MQTT-2.2.1-3"));
+    assert_eq!(statement(publish(QoS::ExactlyOnce, None, false)), Some("MQTT-2.2.1-3"));
+    assert_eq!(statement(publish(QoS::AtMostOnce, None, true)), Some("MQTT-3.3.1-2"));
+  }
+
+  #[test]
+  fn subscribe_test() {
+    let subscribe = |packet_id, subscriptions| DecodedPacket::Subscribe(SubscribePacket {
+      packet_id,
+      subscriptions,
+      properties: Default::default()
+    });
+    let subscription = |topic: &str, nl| SubscriptionConfig {
+      topic: topic.to_owned(),
+      retain_handling: RetainHandling::DoNotSend,
+      rap: false,
+      nl,
+      qos: QoS::ExactlyOnce
+    };
+
+    assert!(check_packet(&subscribe(1, vec![subscription("a", true)])).is_ok());
+    assert_eq!(statement(subscribe(0, vec![subscription("a", false)])), Some("MQTT-2.2.1-3"));
+    assert_eq!(statement(subscribe(1, vec![])), Some("MQTT-3.8.3-2"));
+    assert_eq!(statement(subscribe(1, vec![subscription("$share/group/a", true)])), Some("MQTT-3.8.3-4"));
+
+    let unsubscribe = |topics: &[&str]| DecodedPacket::Unsubscribe(UnsubscribePacket {
+      packet_id: 1,
+      topics: topics.iter().map(|topic| topic.to_string()).collect(),
+      properties: Default::default()
+    });
+    assert_eq!(statement(unsubscribe(&[])), Some("MQTT-3.10.3-2"));
+    assert!(check_packet(&unsubscribe(&["a/+/b", "#", "+"])).is_ok());
+  }
+
+  #[test]
+  fn topic_filter_test() {
+    let subscribe = |topic: &str| DecodedPacket::Subscribe(SubscribePacket {
+      packet_id: 1,
+      subscriptions: vec![SubscriptionConfig {
+        topic: topic.to_owned(),
+        retain_handling: RetainHandling::DoNotSend,
+        rap: false,
+        nl: false,
+        qos: QoS::AtMostOnce
+      }],
+      properties: Default::default()
+    });
+    let unsubscribe = |topic: &str| DecodedPacket::Unsubscribe(UnsubscribePacket {
+      packet_id: 1,
+      topics: vec!["a".to_owned(), topic.to_owned()],
+      properties: Default::default()
+    });
+
+    for (filter, expected) in [("", "MQTT-4.7.3-1"), ("a/#/b", "MQTT-4.7.1-1"), ("a#", "MQTT-4.7.1-1"), ("a/b+", "MQTT-4.7.1-2")] {
+      assert_eq!(statement(subscribe(filter)), Some(expected), "{:?}", filter);
+      assert_eq!(statement(unsubscribe(filter)), Some(expected), "{:?}", filter);
+    }
+    assert_eq!(check_packet(&subscribe("a/+#")).unwrap_err().reason_code, ReasonCode::TopicFilterInvalid);
+  }
+
+  #[test]
+  fn non_character_test() {
+    let unsubscribe = DecodedPacket::Unsubscribe(UnsubscribePacket {
+      packet_id: 1,
+      topics: vec!["a\u{FFFF}".to_owned()],
+      properties: Default::default()
+    });
+    assert_eq!(section(unsubscribe), "1.5.4");
+
+    let mut properties = DisconnectProperties::default();
+    properties.user_properties.push(Utf8Pair::new("name", "\u{FDD0}").unwrap());
+    let disconnect = DecodedPacket::Disconnect(DisconnectPacket { reason_code: ReasonCode::Success, properties });
+    assert_eq!(section(disconnect), "1.5.4");
+  }
+
+  #[test]
+  fn reason_code_test() {
+    let puback = DecodedPacket::Puback(PubackPacket { packet_id: 1, reason_code: ReasonCode::Banned, properties: Default::default() });
+    assert_eq!(section(puback.clone()), "2.4");
+    assert_eq!(statement(puback), None);
+
+    let connack = DecodedPacket::Connack(ConnackPacket {
+      session_present: true,
+      reason_code: ReasonCode::NotAuthorized,
+      properties: Default::default()
+    });
+    assert_eq!(statement(connack), Some("MQTT-3.2.2-6"));
+  }
+}
@@ -1,17 +1,19 @@
-use bytes::{BytesMut, Buf, BufMut};
+use bytes::{BytesMut, BufMut};
 
 use crate::{
   error::{EncodeError, DecodeError},
-  types::DecodedPacket,
-  property::Property,
-  reason_code::ReasonCode
+  types::{DecodedPacket, PacketType},
+  properties::ConnackProperties,
+  reason_code::ReasonCode,
+  utils::get_u8
 };
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ConnackPacket {
   pub session_present: bool,
   pub reason_code: ReasonCode,
-  pub properties: Vec<Property>
+  pub properties: ConnackProperties
 }
 
 impl super::types::Encode for ConnackPacket {
@@ -24,7 +26,7 @@ impl super::types::Encode for ConnackPacket {
     
     buffer.put_u8(self.reason_code as u8);
     
-    Property::encode(buffer, &self.properties)?;
+    self.properties.encode(buffer)?;
 
     Ok(())
   }
@@ -32,19 +34,19 @@ impl super::types::Encode for ConnackPacket {
 
 impl ConnackPacket {
   pub fn decode(buffer: &mut BytesMut) -> Result<DecodedPacket, DecodeError> {
-    let session_present = match buffer.get_u8() {
+    let session_present = match get_u8(buffer)? {
       0b10000000 => true,
       0b00000000 => false,
-      _ => return Err(DecodeError::FormatError)
+      flags => return Err(DecodeError::InvalidConnackFlags(flags))
     };
 
-    let reason_code = ReasonCode::decode(buffer)?;
-    let properties = Property::decode(buffer)?;
+    let reason_code = ReasonCode::decode(buffer, PacketType::CONNACK)?;
+    let properties = ConnackProperties::decode(buffer)?;
 
     let packet = ConnackPacket {
-      session_present: session_present,
-      reason_code: reason_code,
-      properties: properties
+      session_present,
+      reason_code,
+      properties
     };
 
     Ok(DecodedPacket::Connack(packet))
@@ -65,7 +67,7 @@ mod tests {
     let packet = ConnackPacket {
       session_present: true,
       reason_code: ReasonCode::Success,
-      properties: vec![]
+      properties: ConnackProperties::default()
     };
 
     let packet2 = packet.clone();
@@ -1,13 +1,16 @@
-use bytes::{BytesMut, Buf, BufMut};
+use std::convert::TryFrom;
+
+use bytes::{BytesMut, BufMut};
 
 use crate::{
   error::{EncodeError, DecodeError},
-  types::DecodedPacket,
-  property::Property,
-  utils::{decode_utf8, encode_utf8}
+  types::{DecodedPacket, QoS},
+  properties::{ConnectProperties, WillProperties},
+  utils::{decode_utf8, decode_utf8_with_length, encode_utf8, encode_binary, get_u8, get_u16}
 };
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ConnectPacket {
   pub client_id: String,
   pub clean_start: bool,
@@ -19,46 +22,47 @@ pub struct ConnectPacket {
   pub username: Option<String>,
   pub password: Option<String>,
 
-  pub properties: Vec<Property>
+  pub properties: ConnectProperties
 }
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct WillConfig {
   pub topic: String,
   pub payload: String,
   pub retain: bool,
-  pub qos: u8,
-  pub properties: Vec<Property>
+  pub qos: QoS,
+  pub properties: WillProperties
 }
 
-const PROTOCOL_NAME: &'static str = "MQTT";
+const PROTOCOL_NAME: &str = "MQTT";
 const PROTOCOL_VERSION: u8 = 5;
 
 impl super::types::Encode for ConnectPacket {
   fn encode(&self, buffer: &mut BytesMut) -> Result<(), EncodeError> {
-    encode_utf8(buffer, PROTOCOL_NAME);
+    encode_utf8(buffer, PROTOCOL_NAME)?;
     buffer.put_u8(PROTOCOL_VERSION);
 
     self.encode_connect(buffer);
 
     buffer.put_u16(self.keep_alive);
 
-    Property::encode(buffer, &self.properties)?;
+    self.properties.encode(buffer)?;
 
-    encode_utf8(buffer, &self.client_id);
+    encode_utf8(buffer, &self.client_id)?;
 
     if let Some(will_config) = &self.will_config {
-      Property::encode(buffer, &will_config.properties)?;
-      encode_utf8(buffer, &will_config.topic);
-      encode_utf8(buffer, &will_config.payload);
+      will_config.properties.encode(buffer)?;
+      encode_utf8(buffer, &will_config.topic)?;
+      encode_binary(buffer, will_config.payload.as_bytes())?;
     }
 
     if let Some(username) = &self.username {
-      encode_utf8(buffer, &username);
+      encode_utf8(buffer, username)?;
     }
 
     if let Some(password) = &self.password {
-      encode_utf8(buffer, &password);
+      encode_binary(buffer, password.as_bytes())?;
     }
 
     Ok(())
@@ -69,10 +73,10 @@ impl super::types::Encode for ConnectPacket {
 impl ConnectPacket {
   fn encode_connect(&self, buffer: &mut BytesMut) {
     let mut connect_flags = 0;
-    if let Some(_) = self.username {
+    if self.username.is_some() {
       connect_flags += 0b10000000; 
     }
-    if let Some(_) = self.password {
+    if self.password.is_some() {
       connect_flags += 0b1000000;
     }
 
@@ -83,7 +87,7 @@ impl ConnectPacket {
         connect_flags += 0b100000;
       }
 
-      connect_flags += will_config.qos << 3;
+      connect_flags += (will_config.qos as u8) << 3;
     }
 
     if self.clean_start {
@@ -94,22 +98,22 @@ impl ConnectPacket {
   }
 
   fn check_protocol(buffer: &mut BytesMut) -> Result<(), DecodeError> {
-    let protocol_name = decode_utf8(buffer).map_err(|_| DecodeError::ProtocolNotSupportedError)?;
+    let protocol_name = decode_utf8(buffer)?;
 
     if protocol_name != PROTOCOL_NAME {
-      return Err(DecodeError::ProtocolNotSupportedError);
+      return Err(DecodeError::UnsupportedProtocolName(protocol_name));
     }
     
-    let protocol_version = buffer.get_u8();
+    let protocol_version = get_u8(buffer)?;
     if protocol_version != PROTOCOL_VERSION {
-      return Err(DecodeError::ProtocolNotSupportedError);
+      return Err(DecodeError::UnsupportedProtocolVersion(protocol_version));
     }
     Ok(())
   }
 
   fn decode_connect_flags(connect_flags: u8) -> Result<(bool, bool, bool, u8, bool, bool), DecodeError> {
     if connect_flags & 0b1 != 0 {
-      return Err(DecodeError::FormatError);
+      return Err(DecodeError::InvalidConnectFlags(connect_flags));
     }
 
     let username_flag = ((connect_flags & 0b10000000) >> 7) == 1;
@@ -122,29 +126,35 @@ impl ConnectPacket {
     Ok((username_flag, password_flag, will_retain, will_qos, will_flag, clean_start))
   }
 
+  // the will payload and the password are binary data, which unlike a UTF-8 string may hold U+0000
+  fn decode_binary_string(buffer: &mut BytesMut) -> Result<String, DecodeError> {
+    let length = get_u16(buffer)? as usize;
+    decode_utf8_with_length(buffer, length)
+  }
+
   pub fn decode(buffer: &mut BytesMut) -> Result<DecodedPacket, DecodeError> {
     Self::check_protocol(buffer)?;
 
-    let (username_flag, password_flag, will_retain, will_qos, will_flag, clean_start) = Self::decode_connect_flags(buffer.get_u8())?;
+    let (username_flag, password_flag, will_retain, will_qos, will_flag, clean_start) = Self::decode_connect_flags(get_u8(buffer)?)?;
 
-    let keep_alive = buffer.get_u16();
+    let keep_alive = get_u16(buffer)?;
 
-    let properties = Property::decode(buffer)?;
+    let properties = ConnectProperties::decode(buffer)?;
 
     let client_id = decode_utf8(buffer)?;
 
     let will_config = match will_flag {
       true => {
-        let will_properties = Property::decode(buffer)?;
+        let will_properties = WillProperties::decode(buffer)?;
 
         let topic = decode_utf8(buffer)?;
-        let payload = decode_utf8(buffer)?;
+        let payload = Self::decode_binary_string(buffer)?;
 
         Some(WillConfig {
           topic,
           payload,
           retain: will_retain,
-          qos: will_qos,
+          qos: QoS::try_from(will_qos)?,
           properties: will_properties
         })
       }
@@ -158,7 +168,7 @@ impl ConnectPacket {
 
     let mut password: Option<String> = None;
     if password_flag {
-      password = Some(decode_utf8(buffer)?);
+      password = Some(Self::decode_binary_string(buffer)?);
     }
 
     let packet = ConnectPacket {
@@ -191,13 +201,13 @@ mod tests {
         topic: "topic".to_owned(),
         payload: "payload".to_owned(),
         retain: false,
-        qos: 1,
-        properties: vec![]
+        qos: QoS::AtLeastOnce,
+        properties: WillProperties::default()
       }),
       keep_alive: 20,
       username: Some("username".to_owned()),
-      password: Some("password".to_owned()),
-      properties: vec![]
+      password: Some("pass\0word".to_owned()),
+      properties: ConnectProperties::default()
     };
 
     let packet2 = packet.clone();
@@ -208,4 +218,12 @@ mod tests {
 
     assert_eq!(DecodedPacket::Connect(packet2), packet);
   }
+
+  #[test]
+  fn decode_will_qos_test() {
+    // a CONNECT with the Will Flag and Will QoS 3
+    let mut buffer = BytesMut::from(&[0x00, 0x04, b'M', b'Q', b'T', b'T', 0x05, 0x1E, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      0x00, 0x01, b'a', 0x00, 0x00][..]);
+    assert!(matches!(ConnectPacket::decode(&mut buffer), Err(DecodeError::InvalidQoS(3))));
+  }
 }
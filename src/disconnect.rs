@@ -2,33 +2,42 @@ use bytes::{BytesMut, BufMut};
 
 use crate::{
   error::{EncodeError, DecodeError},
-  types::DecodedPacket,
-  property::Property,
+  types::{DecodedPacket, PacketType},
+  properties::DisconnectProperties,
   reason_code::ReasonCode
 };
 
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DisconnectPacket {
   pub reason_code: ReasonCode,
-  pub properties: Vec<Property>
+  pub properties: DisconnectProperties
 }
 
 impl super::types::Encode for DisconnectPacket {
   fn encode(&self, buffer: &mut BytesMut) -> Result<(), EncodeError> {
     buffer.put_u8(self.reason_code as u8);
-    Property::encode(buffer, &self.properties)?;
+    self.properties.encode(buffer)?;
     Ok(())
   }
 }
 
 impl DisconnectPacket {
-  pub fn decode(buffer: &mut BytesMut) -> Result<DecodedPacket, DecodeError> {
-    let reason_code = ReasonCode::decode(buffer)?;
-    let properties = Property::decode(buffer)?;
+  pub fn decode(buffer: &mut BytesMut, remaining_length: usize) -> Result<DecodedPacket, DecodeError> {
+    let reason_code = match remaining_length {
+      0 => ReasonCode::Success,
+      _ => ReasonCode::decode(buffer, PacketType::DISCONNECT)?
+    };
+
+    let properties = match remaining_length {
+      0..=1 => DisconnectProperties::default(),
+      _ => DisconnectProperties::decode(buffer)?
+    };
+
     let packet = DisconnectPacket {
-      reason_code: reason_code,
-      properties: properties
+      reason_code,
+      properties
     };
 
     Ok(DecodedPacket::Disconnect(packet))
@@ -48,15 +57,27 @@ mod tests {
   fn codec_test() {
     let packet = DisconnectPacket {
       reason_code: ReasonCode::Success,
-      properties: vec![]
+      properties: DisconnectProperties::default()
     };
 
     let packet2 = packet.clone();
     let mut buffer = BytesMut::new();
     packet.encode(&mut buffer).unwrap();
 
-    let packet = DisconnectPacket::decode(&mut buffer).unwrap();
+    let remaining_length = buffer.len();
+    let packet = DisconnectPacket::decode(&mut buffer, remaining_length).unwrap();
 
     assert_eq!(DecodedPacket::Disconnect(packet2), packet);
   }
+
+  #[test]
+  fn decode_empty_test() {
+    let mut buffer = BytesMut::new();
+    let packet = DisconnectPacket::decode(&mut buffer, 0).unwrap();
+
+    assert_eq!(DecodedPacket::Disconnect(DisconnectPacket {
+      reason_code: ReasonCode::Success,
+      properties: DisconnectProperties::default()
+    }), packet);
+  }
 }
\ No newline at end of file
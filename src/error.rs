@@ -1,14 +1,184 @@
+use std::fmt;
 use std::io::Error;
 use std::str::Utf8Error;
 
+use crate::{
+  types::{PacketType, Role},
+  property::Property,
+  reason_code::ReasonCode
+};
+
+// a packet that breaks a normative statement of the MQTT 5 specification
+#[derive(Clone, Debug, PartialEq)]
+pub struct Violation {
+  // the statement id such as MQTT-3.1.2-11, None for a rule the specification does not number
+  pub statement: Option<&'static str>,
+  // the section of the specification the rule is in, such as 3.1.2
+  pub section: &'static str,
+  pub description: &'static str,
+  // the reason code the specification asks for when the rule is broken
+  pub reason_code: ReasonCode
+}
+
+impl Violation {
+  pub(crate) fn new(statement: &'static str, reason_code: ReasonCode, description: &'static str) -> Self {
+    // MQTT-3.1.2-11 is statement 11 of section 3.1.2
+    let section = statement.trim_start_matches("MQTT-").rsplit_once('-').map_or(statement, |(section, _)| section);
+    Self { statement: Some(statement), section, description, reason_code }
+  }
+
+  pub(crate) fn unnumbered(section: &'static str, reason_code: ReasonCode, description: &'static str) -> Self {
+    Self { statement: None, section, description, reason_code }
+  }
+}
+
+impl fmt::Display for Violation {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.statement {
+      Some(statement) => write!(f, "{} [{}]", self.description, statement),
+      None => write!(f, "{} [section {}]", self.description, self.section)
+    }
+  }
+}
+
+// a number that is not the value of any variant of the type, e.g. a reason code read from a log
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ConversionError {
+  pub type_name: &'static str,
+  pub value: u8
+}
+
+impl fmt::Display for ConversionError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{:#04x} is not a valid {}", self.value, self.type_name)
+  }
+}
+
+impl std::error::Error for ConversionError {}
+
 #[derive(Debug)]
 pub enum DecodeError {
-  ProtocolNotSupportedError,
-  FormatError,
+  // the packet failed to decode after reading this many of its bytes, fixed header included
+  Packet { packet_type: PacketType, offset: usize, error: Box<DecodeError> },
+  // the packet ended in the middle of a field
+  UnexpectedEnd,
+  UnsupportedProtocolName(String),
+  UnsupportedProtocolVersion(u8),
+  UnsupportedPacketType(PacketType),
+  // a packet that only flows in the other direction, e.g. a CONNACK sent to a server
+  UnexpectedPacket { packet_type: PacketType, role: Role },
+  InvalidHeaderFlags { packet_type: PacketType, flags: u8 },
+  InvalidQoS(u8),
+  InvalidRetainHandling(u8),
+  VariableIntegerTooLong,
+  // the buffer ended before the last byte of a variable byte integer
+  VariableIntegerIncomplete,
+  // a variable byte integer that uses more bytes than its value needs
+  VariableIntegerNotMinimal,
+  // U+0000 is not allowed anywhere in a UTF-8 encoded string
+  NullCharacter,
+  InvalidConnectFlags(u8),
+  InvalidConnackFlags(u8),
+  InvalidSubscriptionOptions(u8),
+  InvalidTopicName(String),
+  UnknownProperty(u8),
+  // the identifier of a single-valued property that appeared more than once
+  DuplicateProperty(u8),
+  // the identifier of a property the packet does not allow
+  PropertyNotAllowed(u8),
+  UnknownReasonCode(u8),
+  ReasonCodeNotAllowed { packet_type: PacketType, reason_code: ReasonCode },
+  InvalidWebsocketFrame(u8),
+  UnsupportedWebsocketOpcode(u8),
+  // the opcode of a ping, pong or close frame that is fragmented, longer than 125 bytes or has a 1 byte close payload
+  InvalidWebsocketControlFrame(u8),
+  // a close frame status code that must not be sent on the wire
+  InvalidWebsocketCloseCode(u16),
+  // a data frame declaring a payload longer than the largest packet that may be received
+  WebsocketFrameTooLarge { size: u64, maximum: u64 },
+  // the name of the upgrade request header that is missing or wrong
+  InvalidUpgradeHeader(&'static str),
+  // a rule only checked in strict mode
+  Violation(Violation),
   Utf8Error(Utf8Error),
   IoError(Error)
 }
 
+impl DecodeError {
+  // the error without the packet context around it
+  pub fn cause(&self) -> &DecodeError {
+    match self {
+      DecodeError::Packet { error, .. } => error.cause(),
+      error => error
+    }
+  }
+
+  // the reason code for the DISCONNECT, or the CONNACK if the CONNECT failed, sent back to the peer
+  pub fn reason_code(&self) -> ReasonCode {
+    match self {
+      DecodeError::Packet { error, .. } => error.reason_code(),
+      DecodeError::UnsupportedProtocolName(_) | DecodeError::UnsupportedProtocolVersion(_) => ReasonCode::UnsupportedProtocolVersion,
+      DecodeError::UnsupportedPacketType(PacketType::RESERVED) => ReasonCode::MalformedPacket,
+      DecodeError::UnsupportedPacketType(_) | DecodeError::UnexpectedPacket { .. } | DecodeError::DuplicateProperty(_) |
+        DecodeError::InvalidRetainHandling(_) => ReasonCode::ProtocolError,
+      DecodeError::InvalidTopicName(_) => ReasonCode::TopicNameInvalid,
+      DecodeError::WebsocketFrameTooLarge { .. } => ReasonCode::PacketTooLarge,
+      DecodeError::Violation(violation) => violation.reason_code,
+      DecodeError::IoError(_) => ReasonCode::UnspecifiedError,
+      _ => ReasonCode::MalformedPacket
+    }
+  }
+}
+
+impl fmt::Display for DecodeError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      DecodeError::Packet { packet_type, offset, error } => write!(f, "{} packet at byte {}: {}", packet_type, offset, error),
+      DecodeError::UnexpectedEnd => write!(f, "packet ended before the end of a field"),
+      DecodeError::UnsupportedProtocolName(name) => write!(f, "unsupported protocol name {:?}", name),
+      DecodeError::UnsupportedProtocolVersion(version) => write!(f, "unsupported protocol version {}", version),
+      DecodeError::UnsupportedPacketType(packet_type) => write!(f, "unsupported packet type {}", packet_type),
+      DecodeError::UnexpectedPacket { packet_type, role } => write!(f, "{} cannot be sent to a {}", packet_type, role),
+      DecodeError::InvalidHeaderFlags { packet_type, flags } => write!(f, "invalid {} header flags {:#06b}", packet_type, flags),
+      DecodeError::InvalidQoS(qos) => write!(f, "QoS must be 0, 1 or 2, got {}", qos),
+      DecodeError::InvalidRetainHandling(value) => write!(f, "retain handling must be 0, 1 or 2, got {}", value),
+      DecodeError::VariableIntegerTooLong => write!(f, "variable byte integer longer than 4 bytes"),
+      DecodeError::VariableIntegerIncomplete => write!(f, "incomplete variable byte integer"),
+      DecodeError::VariableIntegerNotMinimal => write!(f, "variable byte integer not encoded in the minimum number of bytes"),
+      DecodeError::NullCharacter => write!(f, "UTF-8 string contains the null character U+0000"),
+      DecodeError::InvalidConnectFlags(flags) => write!(f, "reserved bit set in connect flags {:#010b}", flags),
+      DecodeError::InvalidConnackFlags(flags) => write!(f, "reserved bits set in connect acknowledge flags {:#010b}", flags),
+      DecodeError::InvalidSubscriptionOptions(options) => write!(f, "reserved bits set in subscription options {:#010b}", options),
+      DecodeError::InvalidTopicName(topic) => write!(f, "invalid topic name {:?}", topic),
+      DecodeError::UnknownProperty(identifier) => write!(f, "unknown property identifier {:#04x}", identifier),
+      DecodeError::DuplicateProperty(identifier) => write!(f, "property {:#04x} included more than once", identifier),
+      DecodeError::PropertyNotAllowed(identifier) => write!(f, "property {:#04x} not allowed on this packet", identifier),
+      DecodeError::UnknownReasonCode(code) => write!(f, "unknown reason code {:#04x}", code),
+      DecodeError::ReasonCodeNotAllowed { packet_type, reason_code } => write!(f, "reason code {} not allowed on {}", reason_code, packet_type),
+      DecodeError::InvalidWebsocketFrame(first_byte) => write!(f, "reserved bits set in websocket frame {:#010b}", first_byte),
+      DecodeError::UnsupportedWebsocketOpcode(opcode) => write!(f, "unsupported websocket opcode {:#x}", opcode),
+      DecodeError::InvalidWebsocketControlFrame(opcode) => write!(f, "invalid websocket control frame with opcode {:#x}", opcode),
+      DecodeError::InvalidWebsocketCloseCode(code) => write!(f, "invalid websocket close status code {}", code),
+      DecodeError::WebsocketFrameTooLarge { size, maximum } => write!(f, "websocket frame of {} bytes exceeds the maximum packet size of {}", size, maximum),
+      DecodeError::InvalidUpgradeHeader(header) => write!(f, "missing or invalid {} header in websocket upgrade", header),
+      DecodeError::Violation(violation) => write!(f, "{}", violation),
+      DecodeError::Utf8Error(error) => write!(f, "invalid UTF-8 string: {}", error),
+      DecodeError::IoError(error) => write!(f, "{}", error)
+    }
+  }
+}
+
+impl std::error::Error for DecodeError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      DecodeError::Packet { error, .. } => Some(error.as_ref()),
+      DecodeError::Utf8Error(error) => Some(error),
+      DecodeError::IoError(error) => Some(error),
+      _ => None
+    }
+  }
+}
+
 impl From<Utf8Error> for DecodeError {
   fn from(error: Utf8Error) -> Self {
     DecodeError::Utf8Error(error)
@@ -24,13 +194,159 @@ impl From<Error> for DecodeError {
 #[derive(Debug)]
 pub enum EncodeError {
   VariableIntegerOutOfRangeError,
-  FormatError,
-  WebsocketError,
+  // the length of a string or binary data that does not fit its two byte length prefix
+  StringTooLong(usize),
+  BinaryTooLong(usize),
+  NullCharacter,
+  // a PUBLISH fixed header needs the DUP, QoS and RETAIN flags
+  MissingPublishConfig,
+  // the payload length of a ping, pong or close frame over the 125 byte limit
+  ControlFrameTooLong(usize),
+  // a rule checked in strict mode or against the negotiated limits
+  Violation(Violation),
+  // a packet that does not fit the Maximum Packet Size even without its optional properties
+  PacketTooLarge { size: usize, maximum: u32 },
   IoError(Error)
 }
 
+impl fmt::Display for EncodeError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      EncodeError::VariableIntegerOutOfRangeError => write!(f, "variable byte integer larger than 268435455"),
+      EncodeError::StringTooLong(length) => write!(f, "UTF-8 string of {} bytes is longer than 65535", length),
+      EncodeError::BinaryTooLong(length) => write!(f, "binary data of {} bytes is longer than 65535", length),
+      EncodeError::NullCharacter => write!(f, "UTF-8 string contains the null character U+0000"),
+      EncodeError::MissingPublishConfig => write!(f, "PUBLISH fixed header encoded without its flags"),
+      EncodeError::ControlFrameTooLong(length) => write!(f, "websocket control frame payload of {} bytes is longer than 125", length),
+      EncodeError::Violation(violation) => write!(f, "{}", violation),
+      EncodeError::PacketTooLarge { size, maximum } => write!(f, "packet of {} bytes exceeds the Maximum Packet Size of {}", size, maximum),
+      EncodeError::IoError(error) => write!(f, "{}", error)
+    }
+  }
+}
+
+impl std::error::Error for EncodeError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      EncodeError::IoError(error) => Some(error),
+      _ => None
+    }
+  }
+}
+
 impl From<Error> for EncodeError {
   fn from(error: Error) -> Self {
     EncodeError::IoError(error)
   }
-}
\ No newline at end of file
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+  ConnectionRefused(ReasonCode),
+  ProtocolError(ReasonCode),
+  Disconnected,
+  Timeout,
+  // the Message Expiry Interval ran out while the publish waited for send quota
+  MessageExpired,
+  DecodeError(DecodeError),
+  EncodeError(EncodeError)
+}
+
+impl fmt::Display for ClientError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ClientError::ConnectionRefused(reason_code) => write!(f, "connection refused: {}", reason_code),
+      ClientError::ProtocolError(reason_code) => write!(f, "protocol error: {}", reason_code),
+      ClientError::Disconnected => write!(f, "disconnected from the server"),
+      ClientError::Timeout => write!(f, "timed out waiting for the server"),
+      ClientError::MessageExpired => write!(f, "message expired before it could be sent"),
+      ClientError::DecodeError(error) => write!(f, "{}", error),
+      ClientError::EncodeError(error) => write!(f, "{}", error)
+    }
+  }
+}
+
+impl std::error::Error for ClientError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      ClientError::DecodeError(error) => Some(error),
+      ClientError::EncodeError(error) => Some(error),
+      _ => None
+    }
+  }
+}
+
+impl From<DecodeError> for ClientError {
+  fn from(error: DecodeError) -> Self {
+    ClientError::DecodeError(error)
+  }
+}
+
+impl From<EncodeError> for ClientError {
+  fn from(error: EncodeError) -> Self {
+    ClientError::EncodeError(error)
+  }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BuildError {
+  InvalidPacketId,
+  PacketIdWithoutQoS,
+  DupWithoutQoS,
+  InvalidTopicName(String),
+  InvalidTopicFilter(String),
+  NoTopics,
+  NoReasonCodes,
+  SessionPresentOnError,
+  ReasonCodeNotAllowed(ReasonCode, PacketType),
+  InvalidProperty(Property)
+}
+
+impl fmt::Display for BuildError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      BuildError::InvalidPacketId => write!(f, "packet identifier must not be 0"),
+      BuildError::PacketIdWithoutQoS => write!(f, "a QoS 0 publish must not have a packet identifier"),
+      BuildError::DupWithoutQoS => write!(f, "the DUP flag must not be set on a QoS 0 publish"),
+      BuildError::InvalidTopicName(topic) => write!(f, "invalid topic name {:?}", topic),
+      BuildError::InvalidTopicFilter(filter) => write!(f, "invalid topic filter {:?}", filter),
+      BuildError::NoTopics => write!(f, "at least one topic filter is required"),
+      BuildError::NoReasonCodes => write!(f, "at least one reason code is required"),
+      BuildError::SessionPresentOnError => write!(f, "session present must be 0 when the connection is refused"),
+      BuildError::ReasonCodeNotAllowed(reason_code, packet_type) => write!(f, "reason code {} is not allowed on {}", reason_code, packet_type),
+      BuildError::InvalidProperty(property) => write!(f, "invalid property value {:?}", property)
+    }
+  }
+}
+
+impl std::error::Error for BuildError {}
+
+#[cfg(test)]
+mod tests {
+  use std::error::Error as _;
+  use super::*;
+
+  #[test]
+  fn violation_test() {
+    let violation = Violation::new("MQTT-3.1.2-11", ReasonCode::MalformedPacket, "Will QoS set without the Will Flag");
+    assert_eq!((violation.statement, violation.section), (Some("MQTT-3.1.2-11"), "3.1.2"));
+    assert_eq!(violation.to_string(), "Will QoS set without the Will Flag [MQTT-3.1.2-11]");
+
+    let violation = Violation::unnumbered("2.4", ReasonCode::ProtocolError, "Reason Code not allowed on this packet");
+    assert_eq!(violation.to_string(), "Reason Code not allowed on this packet [section 2.4]");
+  }
+
+  #[test]
+  fn client_error_test() {
+    let error = ClientError::from(DecodeError::UnknownProperty(0x7F));
+    assert_eq!(error.to_string(), "unknown property identifier 0x7f");
+    assert!(matches!(error.source().unwrap().downcast_ref(), Some(DecodeError::UnknownProperty(0x7F))));
+
+    let error = ClientError::from(EncodeError::NullCharacter);
+    assert!(matches!(error.source().unwrap().downcast_ref(), Some(EncodeError::NullCharacter)));
+
+    let error = ClientError::ConnectionRefused(ReasonCode::NotAuthorized);
+    assert_eq!(error.to_string(), "connection refused: Not authorized (0x87)");
+    assert!(error.source().is_none());
+  }
+}
@@ -1,10 +1,13 @@
-use bytes::{BytesMut, Buf, BufMut};
+use std::convert::TryFrom;
+
+use bytes::{BytesMut, BufMut};
 
 use crate::{
   error::{EncodeError, DecodeError},
   variable_integer,
-  types::PacketType,
-  publish::PublishConfig
+  types::{PacketType, QoS},
+  publish::PublishConfig,
+  utils::get_u8
 };
 
 pub fn encode_fixed_header(buffer: &mut BytesMut, packet_type: PacketType, payload: &BytesMut, 
@@ -14,8 +17,8 @@ pub fn encode_fixed_header(buffer: &mut BytesMut, packet_type: PacketType, paylo
   let first_byte: u8 = match packet_type {
     PacketType::PUBLISH => {
       match config {
-        Some(config) => header + ((config.dup as u8) << 3) + (config.qos << 1) + (config.retain as u8),
-        None => return Err(EncodeError::FormatError)
+        Some(config) => header + ((config.dup as u8) << 3) + ((config.qos as u8) << 1) + (config.retain as u8),
+        None => return Err(EncodeError::MissingPublishConfig)
       }
     },
     PacketType::PUBREL | PacketType::SUBSCRIBE | PacketType::UNSUBSCRIBE => header + 0b0010,
@@ -29,25 +32,29 @@ pub fn encode_fixed_header(buffer: &mut BytesMut, packet_type: PacketType, paylo
   Ok(())
 }
 
+// the length of a whole packet, fixed header included
+pub fn packet_size(remaining_length: usize) -> usize {
+  1 + variable_integer::encoded_length(remaining_length as u64) + remaining_length
+}
+
 pub fn decode_fixed_header(buffer: &mut BytesMut) -> Result<(PacketType, Option<PublishConfig>, usize), DecodeError> {
-  let first_byte = buffer.get_u8();
-  let type_bits = (first_byte & 0b11110000) >> 4;
-  let packet_type: PacketType = match type_bits {
-    0..=15 => type_bits.into(),
-    _ => return Err(DecodeError::FormatError)
-  };
+  let first_byte = get_u8(buffer)?;
+  let packet_type = PacketType::ALL[(first_byte >> 4) as usize];
   let remaining_length = variable_integer::decode(buffer)?;
 
   let flags = first_byte & 0b1111;
   let publish_config = match packet_type {
-    PacketType::PUBLISH => Some(PublishConfig {
-      dup: (flags & 0b1000) == 0b1000,
-      qos: (flags & 0b0110) >> 1,
-      retain: (flags & 0b0001) == 0b0001
-    }),
+    PacketType::PUBLISH => {
+      let qos = QoS::try_from((flags & 0b0110) >> 1)?;
+      Some(PublishConfig {
+        dup: (flags & 0b1000) == 0b1000,
+        qos,
+        retain: (flags & 0b0001) == 0b0001
+      })
+    },
     PacketType::PUBREL | PacketType::SUBSCRIBE | PacketType::UNSUBSCRIBE => {
       if flags != 0b0010 {
-        return Err(DecodeError::FormatError);
+        return Err(DecodeError::InvalidHeaderFlags { packet_type, flags });
       }
       None
     },
@@ -91,11 +98,12 @@ mod tests {
     let payload = BytesMut::from(&ENCODE_PAYLOAD[..]);
     for i in 0..12 {
       let mut buffer = BytesMut::new();
-      let config = PublishConfig { dup: (i % 2) != 0, qos: ((i / 2) % 3), retain: ((i / 6) % 2) != 0 };
-      let first_byte = (3 << 4) + ((config.dup as u8) << 3) + (config.qos << 1) + (config.retain as u8);
+      let config = PublishConfig { dup: (i % 2) != 0, qos: QoS::try_from((i / 2) % 3).unwrap(), retain: ((i / 6) % 2) != 0 };
+      let first_byte = (3 << 4) + ((config.dup as u8) << 3) + ((config.qos as u8) << 1) + (config.retain as u8);
       encode_fixed_header(&mut buffer, PUBLISH, &payload, Some(config)).unwrap();
       assert_eq!(&buffer[..], [first_byte, 0x02]);
     }
+    assert!(matches!(encode_fixed_header(&mut BytesMut::new(), PUBLISH, &payload, None), Err(EncodeError::MissingPublishConfig)));
   }
 
   #[test]
@@ -123,8 +131,8 @@ mod tests {
   #[test]
   fn decode_fixed_header_publish_test() {
     for i in 0..12 {
-      let config = PublishConfig { dup: (i % 2) != 0, qos: ((i / 2) % 3), retain: ((i / 6) % 2) != 0 };
-      let first_byte = (3 << 4) + ((config.dup as u8) << 3) + (config.qos << 1) + (config.retain as u8);
+      let config = PublishConfig { dup: (i % 2) != 0, qos: QoS::try_from((i / 2) % 3).unwrap(), retain: ((i / 6) % 2) != 0 };
+      let first_byte = (3 << 4) + ((config.dup as u8) << 3) + ((config.qos as u8) << 1) + (config.retain as u8);
 
       let mut buffer = BytesMut::from(&[first_byte, 0x02][..]);
       let (packet_type, publish_config, remaining_length) = decode_fixed_header(&mut buffer).unwrap();
@@ -1,13 +1,28 @@
+// the packet tests compare booleans with assert_eq!, which reads the same as the other fields they check
+#![cfg_attr(test, allow(clippy::bool_assert_comparison))]
+
 pub mod types;
 pub mod header;
 pub mod error;
 pub mod codec;
+pub mod builder;
 pub mod websocket;
+pub mod session;
+#[cfg(feature = "client")]
+pub mod client;
+#[cfg(feature = "client")]
+pub mod request;
+#[cfg(feature = "broker")]
+pub mod broker;
+pub mod topic;
 
 mod connect;
 mod connack;
 mod publish;
 mod puback;
+mod pubrec;
+mod pubrel;
+mod pubcomp;
 mod subscribe;
 mod suback;
 mod unsubscribe;
@@ -16,7 +31,13 @@ mod pingreq;
 mod pingresp;
 mod disconnect;
 
+mod conformance;
 mod utils;
 mod variable_integer;
 mod property;
+mod properties;
 mod reason_code;
+mod qos;
+mod limits;
+#[cfg(feature = "serde")]
+mod serde_base64;
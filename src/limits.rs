@@ -0,0 +1,225 @@
+use std::convert::TryFrom;
+
+use bytes::BytesMut;
+
+use crate::{
+  types::*,
+  error::{EncodeError, Violation},
+  header::packet_size
+};
+
+// the limits one side announces in its CONNECT or CONNACK, which bind the packets its peer sends
+#[derive(Clone, Debug, PartialEq)]
+pub struct Limits {
+  // CONNECT limits bind the server and CONNACK limits the client, each under its own statements
+  announced_in: PacketType,
+  pub maximum_packet_size: Option<u32>,
+  pub topic_alias_maximum: u16,
+  pub maximum_qos: QoS,
+  pub retain_available: bool,
+  pub wildcard_subscription_available: bool,
+  pub subscription_identifier_available: bool,
+  pub shared_subscription_available: bool
+}
+
+impl Limits {
+  pub fn from_connect(packet: &ConnectPacket) -> Self {
+    Self {
+      announced_in: PacketType::CONNECT,
+      maximum_packet_size: packet.properties.maximum_packet_size,
+      topic_alias_maximum: packet.properties.topic_alias_maximum.unwrap_or(0),
+      maximum_qos: QoS::ExactlyOnce,
+      retain_available: true,
+      wildcard_subscription_available: true,
+      subscription_identifier_available: true,
+      shared_subscription_available: true
+    }
+  }
+
+  pub fn from_connack(packet: &ConnackPacket) -> Self {
+    let properties = &packet.properties;
+    Self {
+      announced_in: PacketType::CONNACK,
+      maximum_packet_size: properties.maximum_packet_size,
+      topic_alias_maximum: properties.topic_alias_maximum.unwrap_or(0),
+      maximum_qos: properties.maximum_qos.map_or(QoS::ExactlyOnce, |qos| QoS::try_from(qos).unwrap_or(QoS::ExactlyOnce)),
+      retain_available: properties.retain_available != Some(0),
+      wildcard_subscription_available: properties.wildcard_subscription_available != Some(false),
+      subscription_identifier_available: properties.subscription_identifier_available != Some(false),
+      shared_subscription_available: properties.shared_subscription_available != Some(false)
+    }
+  }
+
+  // size is the length of the whole packet, fixed header included
+  pub fn check_size(&self, size: usize) -> Result<(), Violation> {
+    match self.maximum_packet_size {
+      Some(maximum) if size as u64 > maximum as u64 => Err(Violation::new(
+        if self.announced_in == PacketType::CONNECT { "MQTT-3.1.2-24" } else { "MQTT-3.2.2-15" },
+        ReasonCode::PacketTooLarge,
+        "packet exceeds the Maximum Packet Size"
+      )),
+      _ => Ok(())
+    }
+  }
+
+  pub fn check(&self, packet: &DecodedPacket) -> Result<(), Violation> {
+    match packet {
+      DecodedPacket::Publish(packet) => self.check_publish(packet),
+      DecodedPacket::Subscribe(packet) => self.check_subscribe(packet),
+      _ => Ok(())
+    }
+  }
+
+  fn check_publish(&self, packet: &PublishPacket) -> Result<(), Violation> {
+    if let Some(topic_alias) = packet.properties.topic_alias {
+      if topic_alias > self.topic_alias_maximum {
+        let statement = if self.announced_in == PacketType::CONNECT { "MQTT-3.3.2-11" } else { "MQTT-3.3.2-9" };
+        return violation(statement, ReasonCode::TopicAliasInvalid, "Topic Alias exceeds the Topic Alias Maximum");
+      }
+    }
+    if packet.config.qos > self.maximum_qos {
+      return violation("MQTT-3.2.2-11", ReasonCode::QoSNotSupported, "QoS exceeds the Maximum QoS");
+    }
+    if packet.config.retain && !self.retain_available {
+      return violation("MQTT-3.2.2-14", ReasonCode::RetainNotSupported, "RETAIN set but Retain is not available");
+    }
+    Ok(())
+  }
+
+  fn check_subscribe(&self, packet: &SubscribePacket) -> Result<(), Violation> {
+    if packet.properties.subscription_identifier.is_some() && !self.subscription_identifier_available {
+      return Err(Violation::unnumbered("3.2.2.3.12", ReasonCode::SubscriptionIdentifiersNotSupported, "Subscription Identifiers are not available"));
+    }
+    for subscription in packet.subscriptions.iter() {
+      if subscription.topic.starts_with("$share/") && !self.shared_subscription_available {
+        return Err(Violation::unnumbered("3.2.2.3.13", ReasonCode::SharedSubscriptionsNotSupported, "Shared Subscriptions are not available"));
+      }
+      if subscription.topic.contains(['+', '#']) && !self.wildcard_subscription_available {
+        return Err(Violation::unnumbered("3.2.2.3.11", ReasonCode::WildcardSubsdcriptionNotSupported, "Wildcard Subscriptions are not available"));
+      }
+    }
+    Ok(())
+  }
+}
+
+// the optional properties left out of a packet so that it fits the Maximum Packet Size
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Trimmed {
+  Nothing,
+  ReasonString,
+  // the Reason String went first if there was one
+  UserProperties
+}
+
+// drops the Reason String and then the User Properties of a CONNACK, acknowledgement or DISCONNECT
+// until its content fits, re-encoding it after each step [MQTT-3.2.2-19, MQTT-3.2.2-20]
+pub(crate) fn trim(packet: &mut DecodedPacket, content: &mut BytesMut, maximum: u32) -> Result<Trimmed, EncodeError> {
+  let mut trimmed = Trimmed::Nothing;
+  for step in [Trimmed::ReasonString, Trimmed::UserProperties] {
+    if packet_size(content.len()) <= maximum as usize {
+      break;
+    }
+    if remove(packet, step) {
+      content.clear();
+      packet.encode(content)?;
+      trimmed = step;
+    }
+  }
+  Ok(trimmed)
+}
+
+fn remove(packet: &mut DecodedPacket, step: Trimmed) -> bool {
+  let (reason_string, user_properties) = match packet {
+    DecodedPacket::Connack(packet) => (&mut packet.properties.reason_string, &mut packet.properties.user_properties),
+    DecodedPacket::Puback(packet) => (&mut packet.properties.reason_string, &mut packet.properties.user_properties),
+    DecodedPacket::Pubrec(packet) => (&mut packet.properties.reason_string, &mut packet.properties.user_properties),
+    DecodedPacket::Pubrel(packet) => (&mut packet.properties.reason_string, &mut packet.properties.user_properties),
+    DecodedPacket::Pubcomp(packet) => (&mut packet.properties.reason_string, &mut packet.properties.user_properties),
+    DecodedPacket::Suback(packet) => (&mut packet.properties.reason_string, &mut packet.properties.user_properties),
+    DecodedPacket::Unsuback(packet) => (&mut packet.properties.reason_string, &mut packet.properties.user_properties),
+    DecodedPacket::Disconnect(packet) => (&mut packet.properties.reason_string, &mut packet.properties.user_properties),
+    _ => return false
+  };
+  match step {
+    Trimmed::Nothing => false,
+    Trimmed::ReasonString => reason_string.take().is_some(),
+    Trimmed::UserProperties => !std::mem::take(user_properties).is_empty()
+  }
+}
+
+fn violation(statement: &'static str, reason_code: ReasonCode, description: &'static str) -> Result<(), Violation> {
+  Err(Violation::new(statement, reason_code, description))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn publish(qos: QoS, retain: bool, topic_alias: Option<u16>) -> DecodedPacket {
+    let mut packet = PublishPacket {
+      topic: "a".to_owned(),
+      packet_id: if qos == QoS::AtMostOnce { None } else { Some(1) },
+      payload: String::new(),
+      config: PublishConfig { dup: false, qos, retain },
+      properties: Default::default()
+    };
+    packet.properties.topic_alias = topic_alias;
+    DecodedPacket::Publish(packet)
+  }
+
+  fn subscribe(topic: &str, subscription_identifier: Option<u64>) -> DecodedPacket {
+    let mut packet = SubscribePacket {
+      packet_id: 1,
+      subscriptions: vec![SubscriptionConfig {
+        topic: topic.to_owned(),
+        retain_handling: RetainHandling::SendOnSubscribe,
+        rap: false,
+        nl: false,
+        qos: QoS::AtMostOnce
+      }],
+      properties: Default::default()
+    };
+    packet.properties.subscription_identifier = subscription_identifier;
+    DecodedPacket::Subscribe(packet)
+  }
+
+  fn statement(result: Result<(), Violation>) -> Option<&'static str> {
+    result.unwrap_err().statement
+  }
+
+  #[test]
+  fn connect_test() {
+    let packet = ConnectPacket::builder().maximum_packet_size(100).topic_alias_maximum(2).build().unwrap();
+    let limits = Limits::from_connect(&packet);
+
+    assert!(limits.check_size(100).is_ok());
+    assert_eq!(limits.check_size(101).unwrap_err().reason_code, ReasonCode::PacketTooLarge);
+    assert!(limits.check(&publish(QoS::ExactlyOnce, true, Some(2))).is_ok());
+    assert_eq!(statement(limits.check(&publish(QoS::AtMostOnce, false, Some(3)))), Some("MQTT-3.3.2-11"));
+  }
+
+  #[test]
+  fn connack_test() {
+    let packet = ConnackPacket::builder()
+      .maximum_qos(1)
+      .retain_available(0)
+      .wildcard_subscription_available(false)
+      .subscription_identifier_available(false)
+      .shared_subscription_available(false)
+      .build()
+      .unwrap();
+    let limits = Limits::from_connack(&packet);
+
+    assert!(limits.check_size(usize::MAX).is_ok());
+    assert!(limits.check(&publish(QoS::AtLeastOnce, false, None)).is_ok());
+    assert_eq!(statement(limits.check(&publish(QoS::AtMostOnce, false, Some(1)))), Some("MQTT-3.3.2-9"));
+    assert_eq!(statement(limits.check(&publish(QoS::ExactlyOnce, false, None))), Some("MQTT-3.2.2-11"));
+    assert_eq!(limits.check(&publish(QoS::AtMostOnce, true, None)).unwrap_err().reason_code, ReasonCode::RetainNotSupported);
+
+    assert!(limits.check(&subscribe("a/b", None)).is_ok());
+    assert_eq!(limits.check(&subscribe("a/+", None)).unwrap_err().reason_code, ReasonCode::WildcardSubsdcriptionNotSupported);
+    assert_eq!(limits.check(&subscribe("a/+", None)).unwrap_err().section, "3.2.2.3.11");
+    assert_eq!(limits.check(&subscribe("$share/g/a", None)).unwrap_err().reason_code, ReasonCode::SharedSubscriptionsNotSupported);
+    assert_eq!(limits.check(&subscribe("a", Some(1))).unwrap_err().reason_code, ReasonCode::SubscriptionIdentifiersNotSupported);
+  }
+}
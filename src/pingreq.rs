@@ -6,6 +6,7 @@ use crate::{
 };
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PingReqPacket {}
 
 impl super::types::Encode for PingReqPacket {
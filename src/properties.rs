@@ -0,0 +1,236 @@
+use bytes::BytesMut;
+
+use crate::{
+  error::{EncodeError, DecodeError},
+  property::{Property, Utf8Pair}
+};
+
+// a typed property set: an Option for each single-valued property the packet allows, a Vec for
+// each property that may repeat, and the user properties every packet allows
+macro_rules! property_set {
+  (
+    $name:ident {
+      $($(#[$meta:meta])* $field:ident: $type:ty => $variant:ident),* $(,)?
+    }
+    $(repeated {
+      $($list:ident: $list_type:ty => $list_variant:ident),* $(,)?
+    })?
+  ) => {
+    #[derive(Clone, Debug, Default, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+    pub struct $name {
+      $($(#[$meta])* pub $field: Option<$type>,)*
+      $($(pub $list: Vec<$list_type>,)*)?
+      pub user_properties: Vec<Utf8Pair>
+    }
+
+    impl $name {
+      pub fn decode(buffer: &mut BytesMut) -> Result<Self, DecodeError> {
+        Self::from_properties(Property::decode(buffer)?)
+      }
+
+      pub fn encode(&self, buffer: &mut BytesMut) -> Result<(), EncodeError> {
+        Property::encode(buffer, &self.to_properties())
+      }
+
+      // fails on a property the packet does not allow or a single-valued property given twice
+      pub fn from_properties(properties: Vec<Property>) -> Result<Self, DecodeError> {
+        let mut set = Self::default();
+        for property in properties {
+          let identifier = property.identifier();
+          match property {
+            $(Property::$variant(value) => {
+              if set.$field.replace(value).is_some() {
+                return Err(DecodeError::DuplicateProperty(identifier));
+              }
+            },)*
+            $($(Property::$list_variant(value) => set.$list.push(value),)*)?
+            Property::UserProperty(pair) => set.user_properties.push(pair),
+            _ => return Err(DecodeError::PropertyNotAllowed(identifier))
+          }
+        }
+        Ok(set)
+      }
+
+      pub fn to_properties(&self) -> Vec<Property> {
+        let mut properties = Vec::new();
+        $(
+          if let Some(value) = &self.$field {
+            properties.push(Property::$variant(value.clone()));
+          }
+        )*
+        $($(properties.extend(self.$list.iter().cloned().map(Property::$list_variant));)*)?
+        properties.extend(self.user_properties.iter().cloned().map(Property::UserProperty));
+        properties
+      }
+
+      pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+      }
+    }
+  };
+}
+
+property_set! {
+  ConnectProperties {
+    session_expiry_interval: u32 => SessionExpiryInterval,
+    receive_maximum: u16 => ReceiveMaximum,
+    maximum_packet_size: u32 => MaximumPacketSize,
+    topic_alias_maximum: u16 => TopicAliasMaximum,
+    request_response_information: u8 => RequestResponseInformation,
+    request_problem_information: u8 => RequestProblemInformation,
+    authentication_method: String => AuthenticationMethod,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_base64::option"))]
+    authentication_data: Vec<u8> => AuthenticationData
+  }
+}
+
+property_set! {
+  WillProperties {
+    will_delay_interval: u32 => WillDelayInterval,
+    payload_format_indicator: u8 => PayloadFormatIndicator,
+    message_expiry_interval: u32 => MessageExpiryInterval,
+    content_type: String => ContentType,
+    response_topic: String => ResponseTopic,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_base64::option"))]
+    correlation_data: Vec<u8> => CorrelationData
+  }
+}
+
+property_set! {
+  ConnackProperties {
+    session_expiry_interval: u32 => SessionExpiryInterval,
+    receive_maximum: u16 => ReceiveMaximum,
+    maximum_qos: u8 => MaximumQoS,
+    retain_available: u8 => RetainAvailable,
+    maximum_packet_size: u32 => MaximumPacketSize,
+    assigned_client_identifier: String => AssignedClientIdentifier,
+    topic_alias_maximum: u16 => TopicAliasMaximum,
+    reason_string: String => ReasonString,
+    wildcard_subscription_available: bool => WildcardSubscriptionAvailable,
+    subscription_identifier_available: bool => SubscriptionIdentifierAvailable,
+    shared_subscription_available: bool => SharedSubscriptionAvailable,
+    server_keep_alive: u16 => ServerKeepAlive,
+    response_information: String => ResponseInformation,
+    server_reference: String => ServerReference,
+    authentication_method: String => AuthenticationMethod,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_base64::option"))]
+    authentication_data: Vec<u8> => AuthenticationData
+  }
+}
+
+property_set! {
+  PublishProperties {
+    payload_format_indicator: u8 => PayloadFormatIndicator,
+    message_expiry_interval: u32 => MessageExpiryInterval,
+    topic_alias: u16 => TopicAlias,
+    response_topic: String => ResponseTopic,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_base64::option"))]
+    correlation_data: Vec<u8> => CorrelationData,
+    content_type: String => ContentType
+  }
+  // one for each subscription the message matched
+  repeated {
+    subscription_identifiers: u64 => SubscriptionIdentifier
+  }
+}
+
+// PUBACK, PUBREC, PUBREL, PUBCOMP, SUBACK and UNSUBACK
+property_set! {
+  AckProperties {
+    reason_string: String => ReasonString
+  }
+}
+
+property_set! {
+  SubscribeProperties {
+    subscription_identifier: u64 => SubscriptionIdentifier
+  }
+}
+
+property_set! {
+  UnsubscribeProperties {}
+}
+
+property_set! {
+  DisconnectProperties {
+    session_expiry_interval: u32 => SessionExpiryInterval,
+    reason_string: String => ReasonString,
+    server_reference: String => ServerReference
+  }
+}
+
+impl From<WillProperties> for PublishProperties {
+  // the Will Delay Interval only concerns the server and is not forwarded
+  fn from(properties: WillProperties) -> Self {
+    PublishProperties {
+      payload_format_indicator: properties.payload_format_indicator,
+      message_expiry_interval: properties.message_expiry_interval,
+      topic_alias: None,
+      response_topic: properties.response_topic,
+      correlation_data: properties.correlation_data,
+      content_type: properties.content_type,
+      subscription_identifiers: vec![],
+      user_properties: properties.user_properties
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn codec_test() {
+    let properties = PublishProperties {
+      message_expiry_interval: Some(60),
+      content_type: Some("text/plain".to_owned()),
+      subscription_identifiers: vec![1, 2],
+      user_properties: vec![Utf8Pair::new("a", "1").unwrap(), Utf8Pair::new("a", "2").unwrap()],
+      ..PublishProperties::default()
+    };
+
+    let mut buffer = BytesMut::new();
+    properties.encode(&mut buffer).unwrap();
+    assert_eq!(PublishProperties::decode(&mut buffer).unwrap(), properties);
+    assert!(buffer.is_empty());
+  }
+
+  #[test]
+  fn not_allowed_test() {
+    let properties = vec![Property::ReasonString("reason".to_owned()), Property::ServerKeepAlive(10)];
+    match AckProperties::from_properties(properties) {
+      Err(DecodeError::PropertyNotAllowed(0x13)) => {},
+      result => panic!("unexpected result {:?}", result)
+    }
+  }
+
+  #[test]
+  fn duplicate_test() {
+    let properties = vec![Property::TopicAlias(1), Property::TopicAlias(2)];
+    match PublishProperties::from_properties(properties) {
+      Err(DecodeError::DuplicateProperty(0x23)) => {},
+      result => panic!("unexpected result {:?}", result)
+    }
+  }
+
+  #[test]
+  fn is_empty_test() {
+    assert!(UnsubscribeProperties::default().is_empty());
+    let properties = UnsubscribeProperties { user_properties: vec![Utf8Pair::new("a", "1").unwrap()] };
+    assert!(!properties.is_empty());
+  }
+
+  #[test]
+  fn will_properties_test() {
+    let will = WillProperties {
+      will_delay_interval: Some(10),
+      content_type: Some("text/plain".to_owned()),
+      ..WillProperties::default()
+    };
+    assert_eq!(PublishProperties::from(will), PublishProperties {
+      content_type: Some("text/plain".to_owned()),
+      ..PublishProperties::default()
+    });
+  }
+}
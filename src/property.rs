@@ -1,24 +1,75 @@
+use std::convert::TryFrom;
 use bytes::{BytesMut, Buf, BufMut};
 
 use crate::{
   error::{EncodeError, DecodeError},
   variable_integer,
-  utils::{decode_utf8, encode_utf8, get_remaining_length}
+  utils::{check_remaining, decode_utf8, encode_utf8, check_utf8, decode_binary, encode_binary, get_u8, get_u16, get_u32}
 };
 
+// the name and value of a User Property, both following the rules of any other UTF-8 string
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize),
+  serde(try_from = "(String, String)", into = "(String, String)"))]
+pub struct Utf8Pair {
+  name: String,
+  value: String
+}
+
+impl Utf8Pair {
+  pub fn new(name: &str, value: &str) -> Result<Self, EncodeError> {
+    Self::try_from((name.to_owned(), value.to_owned()))
+  }
+
+  // builders check the pair when the packet is built, decoding when the strings are read
+  pub(crate) fn new_unchecked(name: String, value: String) -> Self {
+    Utf8Pair { name, value }
+  }
+
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  pub fn value(&self) -> &str {
+    &self.value
+  }
+
+  pub(crate) fn check(&self) -> Result<(), EncodeError> {
+    check_utf8(&self.name)?;
+    check_utf8(&self.value)
+  }
+}
+
+impl TryFrom<(String, String)> for Utf8Pair {
+  type Error = EncodeError;
+
+  fn try_from((name, value): (String, String)) -> Result<Self, Self::Error> {
+    let pair = Utf8Pair::new_unchecked(name, value);
+    pair.check()?;
+    Ok(pair)
+  }
+}
+
+impl From<Utf8Pair> for (String, String) {
+  fn from(pair: Utf8Pair) -> Self {
+    (pair.name, pair.value)
+  }
+}
+
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Property {
   PayloadFormatIndicator(u8),
   MessageExpiryInterval(u32),
   ContentType(String),
   ResponseTopic(String),
-  CorrelationData(Vec<u8>),
+  CorrelationData(#[cfg_attr(feature = "serde", serde(with = "crate::serde_base64"))] Vec<u8>),
   SubscriptionIdentifier(u64),
   SessionExpiryInterval(u32),
   AssignedClientIdentifier(String),
   ServerKeepAlive(u16),
   AuthenticationMethod(String),
-  AuthenticationData(Vec<u8>),
+  AuthenticationData(#[cfg_attr(feature = "serde", serde(with = "crate::serde_base64"))] Vec<u8>),
   RequestProblemInformation(u8),
   WillDelayInterval(u32),
   RequestResponseInformation(u8),
@@ -30,7 +81,7 @@ pub enum Property {
   TopicAlias(u16),
   MaximumQoS(u8),
   RetainAvailable(u8),
-  UserProperty((String, String)),
+  UserProperty(Utf8Pair),
   MaximumPacketSize(u32),
   WildcardSubscriptionAvailable(bool),
   SubscriptionIdentifierAvailable(bool),
@@ -38,75 +89,115 @@ pub enum Property {
 }
 
 impl Property {
+  pub fn identifier(&self) -> u8 {
+    match self {
+      Property::PayloadFormatIndicator(_) => 0x01,
+      Property::MessageExpiryInterval(_) => 0x02,
+      Property::ContentType(_) => 0x03,
+      Property::ResponseTopic(_) => 0x08,
+      Property::CorrelationData(_) => 0x09,
+      Property::SubscriptionIdentifier(_) => 0x0B,
+      Property::SessionExpiryInterval(_) => 0x11,
+      Property::AssignedClientIdentifier(_) => 0x12,
+      Property::ServerKeepAlive(_) => 0x13,
+      Property::AuthenticationMethod(_) => 0x15,
+      Property::AuthenticationData(_) => 0x16,
+      Property::RequestProblemInformation(_) => 0x17,
+      Property::WillDelayInterval(_) => 0x18,
+      Property::RequestResponseInformation(_) => 0x19,
+      Property::ResponseInformation(_) => 0x1A,
+      Property::ServerReference(_) => 0x1C,
+      Property::ReasonString(_) => 0x1F,
+      Property::ReceiveMaximum(_) => 0x21,
+      Property::TopicAliasMaximum(_) => 0x22,
+      Property::TopicAlias(_) => 0x23,
+      Property::MaximumQoS(_) => 0x24,
+      Property::RetainAvailable(_) => 0x25,
+      Property::UserProperty(_) => 0x26,
+      Property::MaximumPacketSize(_) => 0x27,
+      Property::WildcardSubscriptionAvailable(_) => 0x28,
+      Property::SubscriptionIdentifierAvailable(_) => 0x29,
+      Property::SharedSubscriptionAvailable(_) => 0x2A
+    }
+  }
+
   pub fn decode(buffer: &mut BytesMut) -> Result<Vec<Property>, DecodeError>  {
-    let remaining_length = variable_integer::decode(buffer)? as usize;
+    Property::decode_with(buffer, false)
+  }
+
+  // with minimal set, an overlong property length or Subscription Identifier is an error
+  pub fn decode_with(buffer: &mut BytesMut, minimal: bool) -> Result<Vec<Property>, DecodeError>  {
+    let remaining_length = variable_integer::decode_with(buffer, minimal)? as usize;
+    check_remaining(buffer, remaining_length)?;
 
-    let starting_length = buffer.remaining();
+    // a property running past the property length is cut short instead of reading into the rest of the packet
+    let buffer = &mut buffer.split_to(remaining_length);
     let mut properties = Vec::new();
-    while get_remaining_length(&buffer, starting_length, remaining_length) > 0 {
-      let property = match buffer.get_u8() {
-        0x01 => Property::PayloadFormatIndicator(buffer.get_u8()),
-        0x02 => Property::MessageExpiryInterval(buffer.get_u32()),
-        0x08 => Property::ContentType(decode_utf8(buffer)?),
-        0x09 => Property::ResponseTopic(decode_utf8(buffer)?),
-        0x0B => return Err(DecodeError::FormatError),
-        0x11 => Property::SubscriptionIdentifier(variable_integer::decode(buffer)?),
-        0x12 => Property::SessionExpiryInterval(buffer.get_u32()),
-        0x13 => Property::AssignedClientIdentifier(decode_utf8(buffer)?),
-        0x14 => Property::ServerKeepAlive(buffer.get_u16()),
+    while buffer.has_remaining() {
+      let property = match get_u8(buffer)? {
+        0x01 => Property::PayloadFormatIndicator(get_u8(buffer)?),
+        0x02 => Property::MessageExpiryInterval(get_u32(buffer)?),
+        0x03 => Property::ContentType(decode_utf8(buffer)?),
+        0x08 => Property::ResponseTopic(decode_utf8(buffer)?),
+        0x09 => Property::CorrelationData(decode_binary(buffer)?),
+        0x0B => Property::SubscriptionIdentifier(variable_integer::decode_with(buffer, minimal)?),
+        0x11 => Property::SessionExpiryInterval(get_u32(buffer)?),
+        0x12 => Property::AssignedClientIdentifier(decode_utf8(buffer)?),
+        0x13 => Property::ServerKeepAlive(get_u16(buffer)?),
         0x15 => Property::AuthenticationMethod(decode_utf8(buffer)?),
-        0x16 => return Err(DecodeError::FormatError),
-        0x17 => Property::RequestProblemInformation(buffer.get_u8()),
-        0x18 => Property::WillDelayInterval(buffer.get_u32()),
-        0x19 => Property::RequestResponseInformation(buffer.get_u8()),
+        0x16 => Property::AuthenticationData(decode_binary(buffer)?),
+        0x17 => Property::RequestProblemInformation(get_u8(buffer)?),
+        0x18 => Property::WillDelayInterval(get_u32(buffer)?),
+        0x19 => Property::RequestResponseInformation(get_u8(buffer)?),
         0x1A => Property::ResponseInformation(decode_utf8(buffer)?),
         0x1C => Property::ServerReference(decode_utf8(buffer)?),
         0x1F => Property::ReasonString(decode_utf8(buffer)?),
-        0x21 => Property::ReceiveMaximum(buffer.get_u16()),
-        0x22 => Property::TopicAliasMaximum(buffer.get_u16()),
-        0x23 => Property::TopicAlias(buffer.get_u16()),
-        0x24 => Property::MaximumQoS(buffer.get_u8()),
-        0x25 => Property::RetainAvailable(buffer.get_u8()),
-        0x26 => Property::UserProperty((decode_utf8(buffer)?, decode_utf8(buffer)?)),
-        0x27 => Property::MaximumPacketSize(buffer.get_u32()),
-        0x28 => Property::WildcardSubscriptionAvailable(buffer.get_u8() > 0),
-        0x29 => Property::SubscriptionIdentifierAvailable(buffer.get_u8() > 0),
-        0x2A => Property::SharedSubscriptionAvailable(buffer.get_u8() > 0),
-        _ => return Err(DecodeError::FormatError)
+        0x21 => Property::ReceiveMaximum(get_u16(buffer)?),
+        0x22 => Property::TopicAliasMaximum(get_u16(buffer)?),
+        0x23 => Property::TopicAlias(get_u16(buffer)?),
+        0x24 => Property::MaximumQoS(get_u8(buffer)?),
+        0x25 => Property::RetainAvailable(get_u8(buffer)?),
+        0x26 => Property::UserProperty(Utf8Pair::new_unchecked(decode_utf8(buffer)?, decode_utf8(buffer)?)),
+        0x27 => Property::MaximumPacketSize(get_u32(buffer)?),
+        0x28 => Property::WildcardSubscriptionAvailable(get_u8(buffer)? > 0),
+        0x29 => Property::SubscriptionIdentifierAvailable(get_u8(buffer)? > 0),
+        0x2A => Property::SharedSubscriptionAvailable(get_u8(buffer)? > 0),
+        identifier => return Err(DecodeError::UnknownProperty(identifier))
       };
       properties.push(property);
     }
     Ok(properties)
   }
-  pub fn encode(buffer: &mut BytesMut, properties: &Vec<Property>) -> Result<(), EncodeError> {
+  pub fn encode(buffer: &mut BytesMut, properties: &[Property]) -> Result<(), EncodeError> {
     let mut content = bytes::BytesMut::new();
-    for property in properties.clone().iter() {
+    for property in properties.iter() {
+      content.put_u8(property.identifier());
       match property {
         Property::PayloadFormatIndicator(val) => content.put_u8(*val),
         Property::MessageExpiryInterval(val) => content.put_u32(*val),
-        Property::ContentType(val) => encode_utf8(&mut content, val),
-        Property::ResponseTopic(val) => encode_utf8(&mut content, val),
-        Property::CorrelationData(_) => return Err(EncodeError::FormatError),
+        Property::ContentType(val) => encode_utf8(&mut content, val)?,
+        Property::ResponseTopic(val) => encode_utf8(&mut content, val)?,
+        Property::CorrelationData(val) => encode_binary(&mut content, val)?,
         Property::SubscriptionIdentifier(val) => variable_integer::encode(&mut content, *val)?,
         Property::SessionExpiryInterval(val) => content.put_u32(*val),
-        Property::AssignedClientIdentifier(val) => encode_utf8(&mut content, val),
+        Property::AssignedClientIdentifier(val) => encode_utf8(&mut content, val)?,
         Property::ServerKeepAlive(val) => content.put_u16(*val),
-        Property::AuthenticationMethod(val) => encode_utf8(&mut content, val),
-        Property::AuthenticationData(_) => return Err(EncodeError::FormatError),
+        Property::AuthenticationMethod(val) => encode_utf8(&mut content, val)?,
+        Property::AuthenticationData(val) => encode_binary(&mut content, val)?,
         Property::RequestProblemInformation(val) => content.put_u8(*val),
         Property::WillDelayInterval(val) => content.put_u32(*val),
         Property::RequestResponseInformation(val) => content.put_u8(*val),
-        Property::ResponseInformation(val) => encode_utf8(&mut content, val),
-        Property::ServerReference(val) => encode_utf8(&mut content, val),
-        Property::ReasonString(val) => encode_utf8(&mut content, val),
+        Property::ResponseInformation(val) => encode_utf8(&mut content, val)?,
+        Property::ServerReference(val) => encode_utf8(&mut content, val)?,
+        Property::ReasonString(val) => encode_utf8(&mut content, val)?,
         Property::ReceiveMaximum(val) => content.put_u16(*val),
         Property::TopicAliasMaximum(val) => content.put_u16(*val),
         Property::TopicAlias(val) => content.put_u16(*val),
         Property::MaximumQoS(val) => content.put_u8(*val),
         Property::RetainAvailable(val) => content.put_u8(*val),
-        Property::UserProperty((val1, val2)) => { 
-          encode_utf8(&mut content, val1);
-          encode_utf8(&mut content, val2);
+        Property::UserProperty(pair) => {
+          encode_utf8(&mut content, pair.name())?;
+          encode_utf8(&mut content, pair.value())?;
         },
         Property::MaximumPacketSize(val) => content.put_u32(*val),
         Property::WildcardSubscriptionAvailable(val) => content.put_u8(*val as u8),
@@ -121,4 +212,88 @@ impl Property {
     buffer.put(content);
     Ok(())
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use bytes::BytesMut;
+  use super::*;
+
+  #[test]
+  fn codec_test() {
+    let properties = vec![
+      Property::PayloadFormatIndicator(1),
+      Property::MessageExpiryInterval(60),
+      Property::ContentType("text/plain".to_owned()),
+      Property::ResponseTopic("reply".to_owned()),
+      Property::CorrelationData(vec![0x00, 0xFF]),
+      Property::SubscriptionIdentifier(268435455),
+      Property::SessionExpiryInterval(30),
+      Property::AssignedClientIdentifier("client".to_owned()),
+      Property::ServerKeepAlive(10),
+      Property::AuthenticationMethod("method".to_owned()),
+      Property::AuthenticationData(vec![1, 2, 3]),
+      Property::RequestProblemInformation(1),
+      Property::WillDelayInterval(5),
+      Property::RequestResponseInformation(1),
+      Property::ResponseInformation("response".to_owned()),
+      Property::ServerReference("server".to_owned()),
+      Property::ReasonString("reason".to_owned()),
+      Property::ReceiveMaximum(20),
+      Property::TopicAliasMaximum(10),
+      Property::TopicAlias(3),
+      Property::MaximumQoS(1),
+      Property::RetainAvailable(1),
+      Property::UserProperty(Utf8Pair::new("key", "value").unwrap()),
+      Property::MaximumPacketSize(1024),
+      Property::WildcardSubscriptionAvailable(true),
+      Property::SubscriptionIdentifierAvailable(false),
+      Property::SharedSubscriptionAvailable(true)
+    ];
+
+    let mut buffer = BytesMut::new();
+    Property::encode(&mut buffer, &properties).unwrap();
+
+    assert_eq!(Property::decode(&mut buffer).unwrap(), properties);
+    assert!(buffer.is_empty());
+
+    // each property on its own, right after the one-byte property length
+    for property in properties {
+      let mut buffer = BytesMut::new();
+      Property::encode(&mut buffer, std::slice::from_ref(&property)).unwrap();
+      assert_eq!(buffer[1], property.identifier(), "{:?}", property);
+      assert_eq!(Property::decode(&mut buffer).unwrap(), vec![property]);
+      assert!(buffer.is_empty());
+    }
+  }
+
+  #[test]
+  fn truncated_binary_test() {
+    // Correlation Data and Authentication Data declaring 5 bytes with only 1 present
+    for identifier in [0x09, 0x16] {
+      let mut buffer = BytesMut::from(&[0x04, identifier, 0x00, 0x05, 0x01][..]);
+      assert!(matches!(Property::decode(&mut buffer), Err(DecodeError::UnexpectedEnd)));
+    }
+  }
+
+  #[test]
+  fn encode_identifier_test() {
+    let mut buffer = BytesMut::new();
+    Property::encode(&mut buffer, &[Property::ServerKeepAlive(10)]).unwrap();
+    assert_eq!(&buffer[..], [0x03, 0x13, 0x00, 0x0A]);
+  }
+
+  #[test]
+  fn utf8_pair_test() {
+    let pair = Utf8Pair::new("name", "value").unwrap();
+    assert_eq!((pair.name(), pair.value()), ("name", "value"));
+    assert_eq!(<(String, String)>::from(pair), ("name".to_owned(), "value".to_owned()));
+
+    assert!(matches!(Utf8Pair::new("name", "a\0"), Err(EncodeError::NullCharacter)));
+    assert!(matches!(Utf8Pair::new(&"a".repeat(65536), "value"), Err(EncodeError::StringTooLong(65536))));
+
+    // a pair with U+0000 in the name
+    let mut buffer = BytesMut::from(&[0x06, 0x26, 0x00, 0x01, 0x00, 0x00, 0x00][..]);
+    assert!(matches!(Property::decode(&mut buffer), Err(DecodeError::NullCharacter)));
+  }
+}
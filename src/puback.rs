@@ -1,16 +1,19 @@
-use bytes::{BytesMut, Buf, BufMut};
+use bytes::{BytesMut, BufMut};
 
 use crate::{
   error::{EncodeError, DecodeError},
-  types::DecodedPacket,
-  property::Property,
-  reason_code::ReasonCode
+  types::{DecodedPacket, PacketType},
+  properties::AckProperties,
+  reason_code::ReasonCode,
+  utils::get_u16
 };
 
-#[derive(Clone, Debug, PartialEq)]pub struct PubackPacket {
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PubackPacket {
   pub packet_id: u16,
   pub reason_code: ReasonCode,
-  pub properties: Vec<Property>
+  pub properties: AckProperties
 }
 
 impl super::types::Encode for PubackPacket {
@@ -18,8 +21,8 @@ impl super::types::Encode for PubackPacket {
     buffer.put_u16(self.packet_id);
     buffer.put_u8(self.reason_code as u8);
   
-    if self.properties.len() > 0 {
-      Property::encode(buffer, &self.properties)?;
+    if !self.properties.is_empty() {
+      self.properties.encode(buffer)?;
     }
     
     Ok(())
@@ -28,18 +31,21 @@ impl super::types::Encode for PubackPacket {
 
 impl PubackPacket {
   pub fn decode(buffer: &mut BytesMut, remaining_length: usize) -> Result<DecodedPacket, DecodeError> {
-    let packet_id = buffer.get_u16();
-    let reason_code = ReasonCode::decode(buffer)?;
+    let packet_id = get_u16(buffer)?;
+    let reason_code = match remaining_length {
+      2 => ReasonCode::Success,
+      _ => ReasonCode::decode(buffer, PacketType::PUBACK)?
+    };
 
-    let properties = match remaining_length - 3 {
-      x if x < 4 => Vec::new(),
-      _ => Property::decode(buffer)?
+    let properties = match remaining_length {
+      0..=3 => AckProperties::default(),
+      _ => AckProperties::decode(buffer)?
     };
 
     let packet = PubackPacket {
-      packet_id: packet_id,
-      reason_code: reason_code,
-      properties: properties
+      packet_id,
+      reason_code,
+      properties
     };
 
     Ok(DecodedPacket::Puback(packet))
@@ -60,16 +66,28 @@ mod tests {
     let packet = PubackPacket {
       packet_id: 35,
       reason_code: ReasonCode::Success,
-      properties: vec![]
+      properties: AckProperties::default()
     };
 
     let packet2 = packet.clone();
     let mut buffer = BytesMut::new();
     packet.encode(&mut buffer).unwrap();
 
-    let remaining_length = buffer.remaining();
+    let remaining_length = buffer.len();
     let packet = PubackPacket::decode(&mut buffer, remaining_length).unwrap();
 
     assert_eq!(DecodedPacket::Puback(packet2), packet);
   }
+
+  #[test]
+  fn decode_without_reason_code_test() {
+    let mut buffer = BytesMut::from(&[0x00, 0x23][..]);
+    let packet = PubackPacket::decode(&mut buffer, 2).unwrap();
+
+    assert_eq!(DecodedPacket::Puback(PubackPacket {
+      packet_id: 35,
+      reason_code: ReasonCode::Success,
+      properties: AckProperties::default()
+    }), packet);
+  }
 }
\ No newline at end of file
@@ -2,37 +2,39 @@ use bytes::{BytesMut, Buf, BufMut};
 
 use crate::{
   error::{EncodeError, DecodeError},
-  types::DecodedPacket,
-  property::Property,
-  utils::{decode_utf8, decode_utf8_with_length, encode_utf8, get_remaining_length}
+  types::{DecodedPacket, QoS},
+  properties::PublishProperties,
+  topic,
+  utils::{decode_utf8, decode_utf8_with_length, encode_utf8, get_remaining_length, get_u16}
 };
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PublishPacket {
   pub topic: String,
   pub packet_id: Option<u16>,
   pub payload: String,
   pub config: PublishConfig,
-  pub properties: Vec<Property>
+  pub properties: PublishProperties
 }
 
 #[derive(Default, Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PublishConfig {
   pub dup: bool,
-  pub qos: u8,
+  pub qos: QoS,
   pub retain: bool
 }
 
 impl super::types::Encode for PublishPacket {
   fn encode(&self, buffer: &mut BytesMut) -> Result<(), EncodeError> {
-    encode_utf8(buffer, &self.topic);
+    encode_utf8(buffer, &self.topic)?;
     
-    match self.packet_id {
-      Some(identifier) => buffer.put_u16(identifier),
-      None => {}
+    if let Some(identifier) = self.packet_id {
+      buffer.put_u16(identifier);
     }
 
-    Property::encode(buffer, &self.properties)?;
+    self.properties.encode(buffer)?;
 
     buffer.put_slice(self.payload.as_bytes());
 
@@ -46,13 +48,19 @@ impl PublishPacket {
     let topic = decode_utf8(buffer)?;
 
     let packet_id = match publish_config.qos {
-      0 => None,
-      _ => Some(buffer.get_u16())
+      QoS::AtMostOnce => None,
+      _ => Some(get_u16(buffer)?)
     };
 
-    let properties = Property::decode(buffer)?;
+    let properties = PublishProperties::decode(buffer)?;
 
-    let payload = decode_utf8_with_length(buffer, get_remaining_length(&buffer, starting_length, remaining_length))?;
+    // an empty topic is only allowed when a topic alias stands in for it
+    let aliased = topic.is_empty() && properties.topic_alias.is_some();
+    if !aliased && !topic::is_valid_name(&topic) {
+      return Err(DecodeError::InvalidTopicName(topic));
+    }
+
+    let payload = decode_utf8_with_length(buffer, get_remaining_length(buffer, starting_length, remaining_length))?;
 
     let packet = PublishPacket {
       topic,
@@ -76,7 +84,7 @@ mod tests {
   fn codec_test() {
     let publish_config = PublishConfig {
       dup: false,
-      qos: 1,
+      qos: QoS::AtLeastOnce,
       retain: true
     };
 
@@ -85,7 +93,7 @@ mod tests {
       packet_id: Some(1234),
       payload: "hello".to_owned(),
       config: publish_config.clone(),
-      properties: vec![]
+      properties: PublishProperties::default()
     };
 
     let packet2 = packet.clone();
@@ -97,4 +105,15 @@ mod tests {
 
     assert_eq!(DecodedPacket::Publish(packet2), packet);
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn decode_property_not_allowed_test() {
+    // topic "a", packet id 1 and a Server Keep Alive, which only a CONNACK may carry
+    let mut buffer = BytesMut::from(&[0x00, 0x01, b'a', 0x00, 0x01, 0x03, 0x13, 0x00, 0x0a][..]);
+    let config = PublishConfig { dup: false, qos: QoS::AtLeastOnce, retain: false };
+    match PublishPacket::decode(&mut buffer, config, 9) {
+      Err(DecodeError::PropertyNotAllowed(0x13)) => {},
+      result => panic!("unexpected result {:?}", result)
+    }
+  }
+}
@@ -0,0 +1,81 @@
+use bytes::{BytesMut, BufMut};
+
+use crate::{
+  error::{EncodeError, DecodeError},
+  types::{DecodedPacket, PacketType},
+  properties::AckProperties,
+  reason_code::ReasonCode,
+  utils::get_u16
+};
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PubrelPacket {
+  pub packet_id: u16,
+  pub reason_code: ReasonCode,
+  pub properties: AckProperties
+}
+
+impl super::types::Encode for PubrelPacket {
+  fn encode(&self, buffer: &mut BytesMut) -> Result<(), EncodeError> {
+    buffer.put_u16(self.packet_id);
+    buffer.put_u8(self.reason_code as u8);
+  
+    if !self.properties.is_empty() {
+      self.properties.encode(buffer)?;
+    }
+    
+    Ok(())
+  }
+}
+
+impl PubrelPacket {
+  pub fn decode(buffer: &mut BytesMut, remaining_length: usize) -> Result<DecodedPacket, DecodeError> {
+    let packet_id = get_u16(buffer)?;
+    let reason_code = match remaining_length {
+      2 => ReasonCode::Success,
+      _ => ReasonCode::decode(buffer, PacketType::PUBREL)?
+    };
+
+    let properties = match remaining_length {
+      0..=3 => AckProperties::default(),
+      _ => AckProperties::decode(buffer)?
+    };
+
+    let packet = PubrelPacket {
+      packet_id,
+      reason_code,
+      properties
+    };
+
+    Ok(DecodedPacket::Pubrel(packet))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use bytes::BytesMut;
+  use crate::{
+    types::{Encode, DecodedPacket},
+    reason_code::ReasonCode
+  };
+  use super::*;
+
+  #[test]
+  fn codec_test() {
+    let packet = PubrelPacket {
+      packet_id: 35,
+      reason_code: ReasonCode::Success,
+      properties: AckProperties::default()
+    };
+
+    let packet2 = packet.clone();
+    let mut buffer = BytesMut::new();
+    packet.encode(&mut buffer).unwrap();
+
+    let remaining_length = buffer.len();
+    let packet = PubrelPacket::decode(&mut buffer, remaining_length).unwrap();
+
+    assert_eq!(DecodedPacket::Pubrel(packet2), packet);
+  }
+}
\ No newline at end of file
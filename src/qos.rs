@@ -0,0 +1,86 @@
+use std::convert::TryFrom;
+
+use crate::error::DecodeError;
+
+// ordered so that the lower of two QoS is their min
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(try_from = "u8", into = "u8"))]
+pub enum QoS {
+  #[default]
+  AtMostOnce = 0,
+  AtLeastOnce = 1,
+  ExactlyOnce = 2
+}
+
+impl TryFrom<u8> for QoS {
+  type Error = DecodeError;
+
+  fn try_from(value: u8) -> Result<Self, Self::Error> {
+    match value {
+      0 => Ok(QoS::AtMostOnce),
+      1 => Ok(QoS::AtLeastOnce),
+      2 => Ok(QoS::ExactlyOnce),
+      value => Err(DecodeError::InvalidQoS(value))
+    }
+  }
+}
+
+impl From<QoS> for u8 {
+  fn from(qos: QoS) -> Self {
+    qos as u8
+  }
+}
+
+// whether retained messages are sent when a subscription is made
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(try_from = "u8", into = "u8"))]
+pub enum RetainHandling {
+  #[default]
+  SendOnSubscribe = 0,
+  // only if the subscription did not exist before
+  SendOnNewSubscribe = 1,
+  DoNotSend = 2
+}
+
+impl TryFrom<u8> for RetainHandling {
+  type Error = DecodeError;
+
+  fn try_from(value: u8) -> Result<Self, Self::Error> {
+    match value {
+      0 => Ok(RetainHandling::SendOnSubscribe),
+      1 => Ok(RetainHandling::SendOnNewSubscribe),
+      2 => Ok(RetainHandling::DoNotSend),
+      value => Err(DecodeError::InvalidRetainHandling(value))
+    }
+  }
+}
+
+impl From<RetainHandling> for u8 {
+  fn from(retain_handling: RetainHandling) -> Self {
+    retain_handling as u8
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn qos_test() {
+    for value in 0..=2 {
+      assert_eq!(u8::from(QoS::try_from(value).unwrap()), value);
+    }
+    for value in 3..=255 {
+      assert!(matches!(QoS::try_from(value), Err(DecodeError::InvalidQoS(v)) if v == value));
+    }
+    assert_eq!(QoS::ExactlyOnce.min(QoS::AtLeastOnce), QoS::AtLeastOnce);
+  }
+
+  #[test]
+  fn retain_handling_test() {
+    for value in 0..=2 {
+      assert_eq!(u8::from(RetainHandling::try_from(value).unwrap()), value);
+    }
+    assert!(matches!(RetainHandling::try_from(3), Err(DecodeError::InvalidRetainHandling(3))));
+  }
+}
@@ -1,7 +1,14 @@
-use bytes::{BytesMut, Buf};
-use super::error::DecodeError;
+use std::{fmt, convert::TryFrom};
+
+use bytes::BytesMut;
+use crate::{
+  error::{DecodeError, ConversionError},
+  types::PacketType,
+  utils::get_u8
+};
 
 #[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ReasonCode {
   Success = 0x00,
   GrantedQoS1 = 0x01,
@@ -49,53 +56,202 @@ pub enum ReasonCode {
 }
 
 impl ReasonCode {
-  pub fn decode(buffer: &mut BytesMut) -> Result<Self, DecodeError> {
-    let reason_code = match buffer.get_u8() {
-      0x00 => ReasonCode::Success,
-      0x01 => ReasonCode::GrantedQoS1,
-      0x02 => ReasonCode::GrantedQoS2,
-      0x04 => ReasonCode::DisconnectWithWill,
-      0x10 => ReasonCode::NoMatchingSubscribers,
-      0x11 => ReasonCode::NoSubscriptionExisted,
-      0x18 => ReasonCode::ContinueAuthentication,
-      0x19 => ReasonCode::ReAuthenticate,
-      0x80 => ReasonCode::UnspecifiedError,
-      0x81 => ReasonCode::MalformedPacket,
-      0x82 => ReasonCode::ProtocolError,
-      0x83 => ReasonCode::ImplementationSpecificError,
-      0x84 => ReasonCode::UnsupportedProtocolVersion,
-      0x85 => ReasonCode::ClientIdentifierNotValid,
-      0x86 => ReasonCode::BadUserNameOrPassword,
-      0x87 => ReasonCode::NotAuthorized,
-      0x88 => ReasonCode::ServerUnavailable,
-      0x89 => ReasonCode::ServerBusy,
-      0x8A => ReasonCode::Banned,
-      0x8B => ReasonCode::ServerShuttingDown,
-      0x8C => ReasonCode::BadAuthenticationMethod,
-      0x8D => ReasonCode::KeepAliveMethod,
-      0x8E => ReasonCode::SessionTakenOver,
-      0x8F => ReasonCode::TopicFilterInvalid,
-      0x90 => ReasonCode::TopicNameInvalid,
-      0x91 => ReasonCode::PacketIdentifierInUse,
-      0x92 => ReasonCode::PacketIdentifierNotFound,
-      0x93 => ReasonCode::ReceiveMaximumExceeded,
-      0x94 => ReasonCode::TopicAliasInvalid,
-      0x95 => ReasonCode::PacketTooLarge,
-      0x96 => ReasonCode::MessageRateTooHigh,
-      0x97 => ReasonCode::QuotaExceeded,
-      0x98 => ReasonCode::AdministrativeAction,
-      0x99 => ReasonCode::PayloadFormatInvalid,
-      0x9A => ReasonCode::RetainNotSupported,
-      0x9B => ReasonCode::QoSNotSupported,
-      0x9C => ReasonCode::UseAnotherServer,
-      0x9D => ReasonCode::ServerMoved,
-      0x9E => ReasonCode::SharedSubscriptionsNotSupported,
-      0x9F => ReasonCode::ConnectionRateExceeded,
-      0xA0 => ReasonCode::MaximumConnectTime,
-      0xA1 => ReasonCode::SubscriptionIdentifiersNotSupported,
-      0xA2 => ReasonCode::WildcardSubsdcriptionNotSupported,
-      _ => return Err(DecodeError::FormatError)
-    };
+  // in the order of their values
+  pub const ALL: [ReasonCode; 43] = [
+    ReasonCode::Success, ReasonCode::GrantedQoS1, ReasonCode::GrantedQoS2, ReasonCode::DisconnectWithWill,
+    ReasonCode::NoMatchingSubscribers, ReasonCode::NoSubscriptionExisted, ReasonCode::ContinueAuthentication,
+    ReasonCode::ReAuthenticate, ReasonCode::UnspecifiedError, ReasonCode::MalformedPacket, ReasonCode::ProtocolError,
+    ReasonCode::ImplementationSpecificError, ReasonCode::UnsupportedProtocolVersion,
+    ReasonCode::ClientIdentifierNotValid, ReasonCode::BadUserNameOrPassword, ReasonCode::NotAuthorized,
+    ReasonCode::ServerUnavailable, ReasonCode::ServerBusy, ReasonCode::Banned, ReasonCode::ServerShuttingDown,
+    ReasonCode::BadAuthenticationMethod, ReasonCode::KeepAliveMethod, ReasonCode::SessionTakenOver,
+    ReasonCode::TopicFilterInvalid, ReasonCode::TopicNameInvalid, ReasonCode::PacketIdentifierInUse,
+    ReasonCode::PacketIdentifierNotFound, ReasonCode::ReceiveMaximumExceeded, ReasonCode::TopicAliasInvalid,
+    ReasonCode::PacketTooLarge, ReasonCode::MessageRateTooHigh, ReasonCode::QuotaExceeded,
+    ReasonCode::AdministrativeAction, ReasonCode::PayloadFormatInvalid, ReasonCode::RetainNotSupported,
+    ReasonCode::QoSNotSupported, ReasonCode::UseAnotherServer, ReasonCode::ServerMoved,
+    ReasonCode::SharedSubscriptionsNotSupported, ReasonCode::ConnectionRateExceeded, ReasonCode::MaximumConnectTime,
+    ReasonCode::SubscriptionIdentifiersNotSupported, ReasonCode::WildcardSubsdcriptionNotSupported
+  ];
+
+  // fails on a code that is unknown or not allowed on the packet
+  pub fn decode(buffer: &mut BytesMut, packet_type: PacketType) -> Result<Self, DecodeError> {
+    let code = get_u8(buffer)?;
+    let reason_code = ReasonCode::try_from(code).map_err(|_| DecodeError::UnknownReasonCode(code))?;
+
+    if !reason_code.is_allowed(packet_type) {
+      return Err(DecodeError::ReasonCodeNotAllowed { packet_type, reason_code });
+    }
     Ok(reason_code)
   }
+
+  pub fn is_error(&self) -> bool {
+    *self as u8 >= 0x80
+  }
+
+  pub fn is_allowed(&self, packet_type: PacketType) -> bool {
+    self.packet_types().contains(&packet_type)
+  }
+
+  // the packets that may carry this code, from the table in section 2.4 of the specification
+  pub fn packet_types(&self) -> &'static [PacketType] {
+    use PacketType::*;
+
+    match self {
+      ReasonCode::Success => &[CONNACK, PUBACK, PUBREC, PUBREL, PUBCOMP, SUBACK, UNSUBACK, DISCONNECT, AUTH],
+      ReasonCode::GrantedQoS1 | ReasonCode::GrantedQoS2 => &[SUBACK],
+      ReasonCode::DisconnectWithWill => &[DISCONNECT],
+      ReasonCode::NoMatchingSubscribers => &[PUBACK, PUBREC],
+      ReasonCode::NoSubscriptionExisted => &[UNSUBACK],
+      ReasonCode::ContinueAuthentication | ReasonCode::ReAuthenticate => &[AUTH],
+      ReasonCode::UnspecifiedError | ReasonCode::ImplementationSpecificError | ReasonCode::NotAuthorized =>
+        &[CONNACK, PUBACK, PUBREC, SUBACK, UNSUBACK, DISCONNECT],
+      ReasonCode::MalformedPacket | ReasonCode::ProtocolError | ReasonCode::ServerBusy | ReasonCode::BadAuthenticationMethod |
+      ReasonCode::PacketTooLarge | ReasonCode::RetainNotSupported | ReasonCode::QoSNotSupported |
+      ReasonCode::UseAnotherServer | ReasonCode::ServerMoved | ReasonCode::ConnectionRateExceeded => &[CONNACK, DISCONNECT],
+      ReasonCode::UnsupportedProtocolVersion | ReasonCode::ClientIdentifierNotValid | ReasonCode::BadUserNameOrPassword |
+      ReasonCode::ServerUnavailable | ReasonCode::Banned => &[CONNACK],
+      ReasonCode::ServerShuttingDown | ReasonCode::KeepAliveMethod | ReasonCode::SessionTakenOver |
+      ReasonCode::ReceiveMaximumExceeded | ReasonCode::TopicAliasInvalid | ReasonCode::MessageRateTooHigh |
+      ReasonCode::AdministrativeAction | ReasonCode::MaximumConnectTime => &[DISCONNECT],
+      ReasonCode::TopicFilterInvalid => &[SUBACK, UNSUBACK, DISCONNECT],
+      ReasonCode::TopicNameInvalid | ReasonCode::PayloadFormatInvalid => &[CONNACK, PUBACK, PUBREC, DISCONNECT],
+      ReasonCode::PacketIdentifierInUse => &[PUBACK, PUBREC, SUBACK, UNSUBACK],
+      ReasonCode::PacketIdentifierNotFound => &[PUBREL, PUBCOMP],
+      ReasonCode::QuotaExceeded => &[CONNACK, PUBACK, PUBREC, SUBACK, DISCONNECT],
+      ReasonCode::SharedSubscriptionsNotSupported | ReasonCode::SubscriptionIdentifiersNotSupported |
+      ReasonCode::WildcardSubsdcriptionNotSupported => &[SUBACK, DISCONNECT]
+    }
+  }
+
+  // the name the specification gives the code on this packet; 0x00 has a different name on
+  // DISCONNECT and SUBACK
+  pub fn name(&self, packet_type: PacketType) -> &'static str {
+    match (self, packet_type) {
+      (ReasonCode::Success, PacketType::DISCONNECT) => "Normal disconnection",
+      (ReasonCode::Success, PacketType::SUBACK) => "Granted QoS 0",
+      _ => self.spec_name()
+    }
+  }
+
+  fn spec_name(&self) -> &'static str {
+    match self {
+      ReasonCode::Success => "Success",
+      ReasonCode::GrantedQoS1 => "Granted QoS 1",
+      ReasonCode::GrantedQoS2 => "Granted QoS 2",
+      ReasonCode::DisconnectWithWill => "Disconnect with Will Message",
+      ReasonCode::NoMatchingSubscribers => "No matching subscribers",
+      ReasonCode::NoSubscriptionExisted => "No subscription existed",
+      ReasonCode::ContinueAuthentication => "Continue authentication",
+      ReasonCode::ReAuthenticate => "Re-authenticate",
+      ReasonCode::UnspecifiedError => "Unspecified error",
+      ReasonCode::MalformedPacket => "Malformed Packet",
+      ReasonCode::ProtocolError => "Protocol Error",
+      ReasonCode::ImplementationSpecificError => "Implementation specific error",
+      ReasonCode::UnsupportedProtocolVersion => "Unsupported Protocol Version",
+      ReasonCode::ClientIdentifierNotValid => "Client Identifier not valid",
+      ReasonCode::BadUserNameOrPassword => "Bad User Name or Password",
+      ReasonCode::NotAuthorized => "Not authorized",
+      ReasonCode::ServerUnavailable => "Server unavailable",
+      ReasonCode::ServerBusy => "Server busy",
+      ReasonCode::Banned => "Banned",
+      ReasonCode::ServerShuttingDown => "Server shutting down",
+      ReasonCode::BadAuthenticationMethod => "Bad authentication method",
+      ReasonCode::KeepAliveMethod => "Keep Alive timeout",
+      ReasonCode::SessionTakenOver => "Session taken over",
+      ReasonCode::TopicFilterInvalid => "Topic Filter invalid",
+      ReasonCode::TopicNameInvalid => "Topic Name invalid",
+      ReasonCode::PacketIdentifierInUse => "Packet Identifier in use",
+      ReasonCode::PacketIdentifierNotFound => "Packet Identifier not found",
+      ReasonCode::ReceiveMaximumExceeded => "Receive Maximum exceeded",
+      ReasonCode::TopicAliasInvalid => "Topic Alias invalid",
+      ReasonCode::PacketTooLarge => "Packet too large",
+      ReasonCode::MessageRateTooHigh => "Message rate too high",
+      ReasonCode::QuotaExceeded => "Quota exceeded",
+      ReasonCode::AdministrativeAction => "Administrative action",
+      ReasonCode::PayloadFormatInvalid => "Payload format invalid",
+      ReasonCode::RetainNotSupported => "Retain not supported",
+      ReasonCode::QoSNotSupported => "QoS not supported",
+      ReasonCode::UseAnotherServer => "Use another server",
+      ReasonCode::ServerMoved => "Server moved",
+      ReasonCode::SharedSubscriptionsNotSupported => "Shared Subscriptions not supported",
+      ReasonCode::ConnectionRateExceeded => "Connection rate exceeded",
+      ReasonCode::MaximumConnectTime => "Maximum connect time",
+      ReasonCode::SubscriptionIdentifiersNotSupported => "Subscription Identifiers not supported",
+      ReasonCode::WildcardSubsdcriptionNotSupported => "Wildcard Subscriptions not supported"
+    }
+  }
+}
+
+impl TryFrom<u8> for ReasonCode {
+  type Error = ConversionError;
+
+  fn try_from(value: u8) -> Result<Self, Self::Error> {
+    match ReasonCode::ALL.iter().find(|reason_code| **reason_code as u8 == value) {
+      Some(reason_code) => Ok(*reason_code),
+      None => Err(ConversionError { type_name: "reason code", value })
+    }
+  }
+}
+
+impl From<ReasonCode> for u8 {
+  fn from(reason_code: ReasonCode) -> Self {
+    reason_code as u8
+  }
+}
+
+impl fmt::Display for ReasonCode {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{} ({:#04x})", self.spec_name(), *self as u8)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn decode_test() {
+    let mut buffer = BytesMut::from(&[0x10][..]);
+    assert_eq!(ReasonCode::decode(&mut buffer, PacketType::PUBACK).unwrap(), ReasonCode::NoMatchingSubscribers);
+
+    let mut buffer = BytesMut::from(&[0x8A][..]);
+    match ReasonCode::decode(&mut buffer, PacketType::PUBACK) {
+      Err(DecodeError::ReasonCodeNotAllowed { packet_type: PacketType::PUBACK, reason_code: ReasonCode::Banned }) => {},
+      result => panic!("unexpected result {:?}", result)
+    }
+
+    let mut buffer = BytesMut::from(&[0x03][..]);
+    assert!(matches!(ReasonCode::decode(&mut buffer, PacketType::SUBACK), Err(DecodeError::UnknownReasonCode(0x03))));
+  }
+
+  #[test]
+  fn conversion_test() {
+    for reason_code in ReasonCode::ALL.iter() {
+      assert_eq!(ReasonCode::try_from(u8::from(*reason_code)).unwrap(), *reason_code);
+    }
+    assert!(ReasonCode::ALL.windows(2).all(|pair| (pair[0] as u8) < (pair[1] as u8)));
+
+    let error = ReasonCode::try_from(0x03).unwrap_err();
+    assert_eq!(error, ConversionError { type_name: "reason code", value: 0x03 });
+    assert_eq!(error.to_string(), "0x03 is not a valid reason code");
+    assert_eq!((0..=255).filter_map(|value| ReasonCode::try_from(value).ok()).count(), ReasonCode::ALL.len());
+  }
+
+  #[test]
+  fn is_error_test() {
+    assert!(!ReasonCode::GrantedQoS2.is_error());
+    assert!(!ReasonCode::NoMatchingSubscribers.is_error());
+    assert!(ReasonCode::UnspecifiedError.is_error());
+    assert!(ReasonCode::Banned.is_error());
+  }
+
+  #[test]
+  fn name_test() {
+    assert_eq!(ReasonCode::Success.name(PacketType::CONNACK), "Success");
+    assert_eq!(ReasonCode::Success.name(PacketType::DISCONNECT), "Normal disconnection");
+    assert_eq!(ReasonCode::Success.name(PacketType::SUBACK), "Granted QoS 0");
+    assert_eq!(ReasonCode::KeepAliveMethod.name(PacketType::DISCONNECT), "Keep Alive timeout");
+    assert_eq!(ReasonCode::NotAuthorized.to_string(), "Not authorized (0x87)");
+  }
 }
\ No newline at end of file
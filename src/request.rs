@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::StreamExt;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+  types::*,
+  client::{Client, Messages},
+  error::ClientError
+};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+type Waiting = Arc<Mutex<HashMap<Vec<u8>, oneshot::Sender<PublishPacket>>>>;
+
+// sends requests carrying a Response Topic and Correlation Data and resolves them with the
+// matching reply; for the server to provide Response Information the CONNECT has to include
+// Request Response Information set to 1
+#[derive(Clone)]
+pub struct Requester {
+  client: Client,
+  response_topic: String,
+  waiting: Waiting,
+  next_correlation_id: Arc<AtomicU64>,
+  timeout: Duration
+}
+
+impl Requester {
+  // subscribes to the reply topic and takes over the message stream; messages that are not
+  // replies are passed on through the returned stream
+  pub async fn new(client: Client, messages: Messages, fallback_topic: &str) -> Result<(Requester, Messages), ClientError> {
+    let response_topic = response_topic(client.connack(), fallback_topic);
+
+    let reason_codes = client.subscribe(SubscribePacket {
+      packet_id: 0,
+      subscriptions: vec![SubscriptionConfig {
+        topic: response_topic.clone(),
+        retain_handling: RetainHandling::DoNotSend,
+        rap: false,
+        nl: true,
+        qos: QoS::AtLeastOnce
+      }],
+      properties: Default::default()
+    }).await?;
+
+    if let Some(reason_code) = reason_codes.into_iter().find(|reason_code| reason_code.is_error()) {
+      return Err(ClientError::ProtocolError(reason_code));
+    }
+
+    let waiting = Waiting::default();
+    let (sender, receiver) = mpsc::unbounded_channel();
+    tokio::spawn(Self::dispatch(messages, sender, response_topic.clone(), waiting.clone()));
+
+    Ok((Requester {
+      client,
+      response_topic,
+      waiting,
+      next_correlation_id: Arc::new(AtomicU64::new(1)),
+      timeout: DEFAULT_TIMEOUT
+    }, Messages::new(receiver)))
+  }
+
+  pub fn with_timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = timeout;
+    self
+  }
+
+  pub fn response_topic(&self) -> &str {
+    &self.response_topic
+  }
+
+  // publishes the request and waits for the reply with the same Correlation Data;
+  // any Response Topic or Correlation Data already on the packet is replaced
+  pub async fn request(&self, mut packet: PublishPacket) -> Result<PublishPacket, ClientError> {
+    let correlation_data = self.next_correlation_id.fetch_add(1, Ordering::Relaxed).to_be_bytes().to_vec();
+
+    packet.properties.response_topic = Some(self.response_topic.clone());
+    packet.properties.correlation_data = Some(correlation_data.clone());
+
+    let (reply, receiver) = oneshot::channel();
+    self.waiting.lock().unwrap().insert(correlation_data.clone(), reply);
+
+    let result = match self.client.publish(packet).await {
+      Ok(reason_code) if reason_code.is_error() => Err(ClientError::ProtocolError(reason_code)),
+      Ok(_) => match tokio::time::timeout(self.timeout, receiver).await {
+        Ok(Ok(reply)) => Ok(reply),
+        Ok(Err(_)) => Err(ClientError::Disconnected),
+        Err(_) => Err(ClientError::Timeout)
+      },
+      Err(error) => Err(error)
+    };
+
+    self.waiting.lock().unwrap().remove(&correlation_data);
+    result
+  }
+
+  async fn dispatch(mut messages: Messages, sender: mpsc::UnboundedSender<PublishPacket>, response_topic: String,
+    waiting: Waiting) {
+
+    while let Some(packet) = messages.next().await {
+      let reply = match (packet.topic == response_topic, packet.properties.correlation_data.as_deref()) {
+        (true, Some(correlation_data)) => waiting.lock().unwrap().remove(correlation_data),
+        _ => None
+      };
+
+      match reply {
+        Some(reply) => {
+          let _ = reply.send(packet);
+        },
+        None => {
+          let _ = sender.send(packet);
+        }
+      }
+    }
+  }
+}
+
+// the topic replies are sent to: below the Response Information from the CONNACK if the
+// server provided one, otherwise the fallback
+pub fn response_topic(connack: &ConnackPacket, fallback_topic: &str) -> String {
+  match &connack.properties.response_information {
+    Some(information) => format!("{}/replies", information.trim_end_matches('/')),
+    None => fallback_topic.to_owned()
+  }
+}
+
+// the reply to a request, sent to its Response Topic with the same Correlation Data;
+// None if the request did not ask for a response
+pub fn reply_to(request: &PublishPacket, payload: String) -> Option<PublishPacket> {
+  let topic = request.properties.response_topic.clone()?;
+
+  Some(PublishPacket {
+    topic,
+    packet_id: None,
+    payload,
+    config: PublishConfig {
+      dup: false,
+      qos: request.config.qos,
+      retain: false
+    },
+    properties: PublishProperties {
+      correlation_data: request.properties.correlation_data.clone(),
+      ..Default::default()
+    }
+  })
+}
+
+#[cfg(all(test, feature = "broker"))]
+mod tests {
+  use crate::broker::Broker;
+  use crate::broker::tests::{connect_with, publish_packet};
+  use super::*;
+
+  // asks the broker for response information, so requests are answered under its prefix
+  async fn connect(broker: &Broker, client_id: &str) -> (Client, Messages) {
+    connect_with(broker, ConnectPacket::builder().client_id(client_id).request_response_information(true).build().unwrap()).await
+  }
+
+  #[test]
+  fn response_topic_test() {
+    let mut connack = ConnackPacket {
+      session_present: false,
+      reason_code: ReasonCode::Success,
+      properties: Default::default()
+    };
+    assert_eq!(response_topic(&connack, "fallback"), "fallback");
+
+    connack.properties.response_information = Some("responses/client/".to_owned());
+    assert_eq!(response_topic(&connack, "fallback"), "responses/client/replies");
+  }
+
+  #[test]
+  fn reply_to_test() {
+    let mut request = publish_packet("service", "ping", 1, false);
+    assert_eq!(reply_to(&request, "pong".to_owned()), None);
+
+    request.properties.response_topic = Some("replies".to_owned());
+    request.properties.correlation_data = Some(vec![1, 2]);
+    let reply = reply_to(&request, "pong".to_owned()).unwrap();
+    assert_eq!(reply.topic, "replies");
+    assert_eq!(reply.payload, "pong");
+    assert_eq!(reply.properties.correlation_data, Some(vec![1, 2]));
+    assert_eq!(reply.properties.response_topic, None);
+  }
+
+  #[tokio::test]
+  async fn request_test() {
+    let broker = Broker::start().await.unwrap();
+
+    let (responder, mut requests) = connect(&broker, "responder").await;
+    responder.subscribe(SubscribePacket {
+      packet_id: 0,
+      subscriptions: vec![SubscriptionConfig {
+        topic: "service".to_owned(),
+        retain_handling: RetainHandling::SendOnSubscribe,
+        rap: false,
+        nl: false,
+        qos: QoS::AtLeastOnce
+      }],
+      properties: Default::default()
+    }).await.unwrap();
+    tokio::spawn(async move {
+      while let Some(request) = requests.next().await {
+        let reply = reply_to(&request, format!("re: {}", request.payload)).unwrap();
+        responder.publish(reply).await.unwrap();
+      }
+    });
+
+    let (client, messages) = connect(&broker, "requester").await;
+    let (requester, _) = Requester::new(client, messages, "unused").await.unwrap();
+    assert_eq!(requester.response_topic(), "responses/requester/replies");
+
+    let (first, second) = tokio::join!(
+      requester.request(publish_packet("service", "one", 1, false)),
+      requester.request(publish_packet("service", "two", 1, false))
+    );
+    assert_eq!(first.unwrap().payload, "re: one");
+    assert_eq!(second.unwrap().payload, "re: two");
+
+    broker.stop().await;
+  }
+
+  #[tokio::test]
+  async fn timeout_test() {
+    let broker = Broker::start().await.unwrap();
+    let (client, messages) = connect(&broker, "requester").await;
+    let (requester, _) = Requester::new(client, messages, "unused").await.unwrap();
+
+    let requester = requester.with_timeout(Duration::from_millis(50));
+    match requester.request(publish_packet("nobody", "hello", 1, false)).await {
+      Err(ClientError::Timeout) => {},
+      result => panic!("unexpected result {:?}", result)
+    }
+    assert!(requester.waiting.lock().unwrap().is_empty());
+
+    broker.stop().await;
+  }
+}
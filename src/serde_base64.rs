@@ -0,0 +1,92 @@
+use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+// binary data is written as a base64 string so logged packets stay readable
+pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+  serializer.serialize_str(&base64::encode(bytes))
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+  let encoded = String::deserialize(deserializer)?;
+  base64::decode(&encoded).map_err(D::Error::custom)
+}
+
+pub mod option {
+  use super::*;
+
+  pub fn serialize<S: Serializer>(bytes: &Option<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error> {
+    match bytes {
+      Some(bytes) => serializer.serialize_some(&base64::encode(bytes)),
+      None => serializer.serialize_none()
+    }
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+      Some(encoded) => base64::decode(&encoded).map(Some).map_err(D::Error::custom),
+      None => Ok(None)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serde::{Serialize, de::DeserializeOwned};
+  use crate::types::*;
+
+  fn round_trip<T: Serialize + DeserializeOwned + PartialEq + std::fmt::Debug>(value: T) {
+    let json = serde_json::to_string(&value).unwrap();
+    assert_eq!(serde_json::from_str::<T>(&json).unwrap(), value);
+  }
+
+  #[test]
+  fn packets_test() {
+    let will = WillConfig::builder("status", "offline").qos(QoS::AtLeastOnce).correlation_data(&[1, 2]).build().unwrap();
+    let packets = vec![
+      DecodedPacket::Connect(ConnectPacket::builder().client_id("client").will(will).authentication_data(&[0xff]).build().unwrap()),
+      DecodedPacket::Connack(ConnackPacket::builder().reason_code(ReasonCode::NotAuthorized).build().unwrap()),
+      DecodedPacket::Publish(PublishPacket::builder("a/b").payload("hello").qos(QoS::AtLeastOnce).packet_id(1).subscription_identifier(3)
+        .user_property("k", "v").build().unwrap()),
+      DecodedPacket::Puback(PubackPacket::builder(1).build().unwrap()),
+      DecodedPacket::Pubrec(PubrecPacket::builder(1).reason_string("ok").build().unwrap()),
+      DecodedPacket::Pubrel(PubrelPacket::builder(1).build().unwrap()),
+      DecodedPacket::Pubcomp(PubcompPacket::builder(1).build().unwrap()),
+      DecodedPacket::Subscribe(SubscribePacket::builder().packet_id(2).topic("a/+", QoS::ExactlyOnce).build().unwrap()),
+      DecodedPacket::Suback(SubackPacket::builder(2).reason_code(ReasonCode::GrantedQoS2).build().unwrap()),
+      DecodedPacket::Unsubscribe(UnsubscribePacket::builder().packet_id(3).topic("a/+").build().unwrap()),
+      DecodedPacket::Unsuback(UnsubackPacket::builder(3).reason_code(ReasonCode::Success).build().unwrap()),
+      DecodedPacket::PingReq(PingReqPacket {}),
+      DecodedPacket::PingResp(PingRespPacket {}),
+      DecodedPacket::Disconnect(DisconnectPacket::builder().session_expiry_interval(10).build().unwrap())
+    ];
+
+    for packet in packets {
+      round_trip(packet);
+    }
+  }
+
+  #[test]
+  fn binary_test() {
+    let properties = PublishProperties { correlation_data: Some(vec![1, 2, 3]), ..Default::default() };
+    let json = serde_json::to_value(&properties).unwrap();
+    assert_eq!(json["correlation_data"], "AQID");
+    round_trip(properties);
+
+    let property = Property::AuthenticationData(vec![0xff]);
+    assert_eq!(serde_json::to_string(&property).unwrap(), r#"{"AuthenticationData":"/w=="}"#);
+    round_trip(property);
+
+    assert!(serde_json::from_str::<Property>(r#"{"CorrelationData":"not base64!"}"#).is_err());
+  }
+
+  #[test]
+  fn other_types_test() {
+    round_trip(PacketType::SUBSCRIBE);
+    round_trip(ReasonCode::QuotaExceeded);
+    round_trip(PublishConfig { dup: true, qos: QoS::ExactlyOnce, retain: true });
+    round_trip(SubscriptionConfig { topic: "a/#".to_owned(), retain_handling: RetainHandling::SendOnNewSubscribe, rap: true, nl: false, qos: QoS::AtLeastOnce });
+
+    // missing properties are left unset
+    let properties: ConnackProperties = serde_json::from_str(r#"{"server_keep_alive":5}"#).unwrap();
+    assert_eq!(properties, ConnackProperties { server_keep_alive: Some(5), ..Default::default() });
+  }
+}
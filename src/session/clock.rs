@@ -0,0 +1,99 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+pub trait Clock {
+  fn now(&self) -> Instant;
+
+  // wall clock time, for deadlines that have to outlive the process
+  fn system_time(&self) -> SystemTime;
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+  fn now(&self) -> Instant {
+    Instant::now()
+  }
+
+  fn system_time(&self) -> SystemTime {
+    SystemTime::now()
+  }
+}
+
+// follows tokio's clock, so paused and advanced test time is respected
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TokioClock;
+
+impl Clock for TokioClock {
+  fn now(&self) -> Instant {
+    tokio::time::Instant::now().into_std()
+  }
+
+  fn system_time(&self) -> SystemTime {
+    let now = self.now();
+    let real = Instant::now();
+    match now >= real {
+      true => SystemTime::now() + (now - real),
+      false => SystemTime::now() - (real - now)
+    }
+  }
+}
+
+// a clock that only moves when told to, shared between clones
+#[derive(Clone, Debug)]
+pub struct ManualClock {
+  start: Instant,
+  system_start: SystemTime,
+  now: Arc<Mutex<Instant>>
+}
+
+impl Default for ManualClock {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl ManualClock {
+  pub fn new() -> Self {
+    let start = Instant::now();
+    Self {
+      start,
+      system_start: SystemTime::now(),
+      now: Arc::new(Mutex::new(start))
+    }
+  }
+
+  pub fn advance(&self, duration: Duration) {
+    let mut now = self.now.lock().unwrap();
+    *now += duration;
+  }
+}
+
+impl Clock for ManualClock {
+  fn now(&self) -> Instant {
+    *self.now.lock().unwrap()
+  }
+
+  fn system_time(&self) -> SystemTime {
+    self.system_start + (self.now() - self.start)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn manual_clock_test() {
+    let clock = ManualClock::new();
+    let shared = clock.clone();
+    let start = clock.now();
+
+    let system_start = clock.system_time();
+
+    shared.advance(Duration::from_secs(5));
+    assert_eq!(clock.now() - start, Duration::from_secs(5));
+    assert_eq!(clock.system_time().duration_since(system_start).unwrap(), Duration::from_secs(5));
+  }
+}
@@ -0,0 +1,97 @@
+use std::time::{Duration, SystemTime};
+
+use crate::types::PublishPacket;
+use super::clock::Clock;
+
+// a message kept by the server, stamped with the time its Message Expiry Interval runs out
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExpiringMessage {
+  pub packet: PublishPacket,
+  pub expires_at: Option<SystemTime>
+}
+
+impl ExpiringMessage {
+  pub fn new<C: Clock>(packet: PublishPacket, clock: &C) -> Self {
+    let expires_at = packet.properties.message_expiry_interval
+      .map(|seconds| clock.system_time() + Duration::from_secs(seconds as u64));
+    Self { packet, expires_at }
+  }
+
+  pub fn is_expired<C: Clock>(&self, clock: &C) -> bool {
+    match self.expires_at {
+      Some(expires_at) => expires_at <= clock.system_time(),
+      None => false
+    }
+  }
+
+  // the packet to send now, with the Message Expiry Interval reduced by the time the message
+  // waited in the server; None once it has expired
+  pub fn deliver<C: Clock>(&self, clock: &C) -> Option<PublishPacket> {
+    let expires_at = match self.expires_at {
+      Some(expires_at) => expires_at,
+      None => return Some(self.packet.clone())
+    };
+
+    let remaining = match expires_at.duration_since(clock.system_time()) {
+      Ok(remaining) if remaining > Duration::ZERO => remaining,
+      _ => return None
+    };
+
+    // a partly elapsed second still counts, so a live message is never sent with an interval of 0
+    let seconds = remaining.as_secs() as u32 + (remaining.subsec_nanos() > 0) as u32;
+    let mut packet = self.packet.clone();
+    packet.properties.message_expiry_interval = Some(seconds);
+    Some(packet)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::types::{PublishConfig, PublishProperties};
+  use crate::session::ManualClock;
+  use super::*;
+
+  fn publish(properties: PublishProperties) -> PublishPacket {
+    PublishPacket {
+      topic: "a".to_owned(),
+      packet_id: None,
+      payload: "hello".to_owned(),
+      config: PublishConfig::default(),
+      properties
+    }
+  }
+
+  #[test]
+  fn no_expiry_test() {
+    let clock = ManualClock::new();
+    let message = ExpiringMessage::new(publish(PublishProperties::default()), &clock);
+    clock.advance(Duration::from_secs(u32::MAX as u64));
+    assert!(!message.is_expired(&clock));
+    assert_eq!(message.deliver(&clock), Some(publish(PublishProperties::default())));
+  }
+
+  #[test]
+  fn remaining_lifetime_test() {
+    let clock = ManualClock::new();
+    let properties = PublishProperties {
+      content_type: Some("text/plain".to_owned()),
+      message_expiry_interval: Some(10),
+      ..Default::default()
+    };
+    let message = ExpiringMessage::new(publish(properties), &clock);
+
+    clock.advance(Duration::from_millis(3500));
+    assert_eq!(message.deliver(&clock).unwrap().properties, PublishProperties {
+      content_type: Some("text/plain".to_owned()),
+      message_expiry_interval: Some(7),
+      ..Default::default()
+    });
+
+    clock.advance(Duration::from_millis(6000));
+    assert_eq!(message.deliver(&clock).unwrap().properties.message_expiry_interval, Some(1));
+
+    clock.advance(Duration::from_millis(500));
+    assert!(message.is_expired(&clock));
+    assert_eq!(message.deliver(&clock), None);
+  }
+}
@@ -0,0 +1,333 @@
+use std::collections::{HashSet, VecDeque};
+
+use crate::types::{DecodedPacket, ReasonCode, PublishPacket, ConnectPacket, ConnackPacket, QoS};
+use super::clock::Clock;
+use super::expiry::ExpiringMessage;
+
+const DEFAULT_RECEIVE_MAXIMUM: u16 = 65535;
+
+pub struct FlowControl {
+  send_maximum: u16,
+  // QoS 1 and 2 messages sent and not yet acknowledged
+  in_flight: u16,
+  pending: VecDeque<ExpiringMessage>,
+  receive_maximum: u16,
+  received: HashSet<u16>
+}
+
+// what an acknowledgement lets through
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Released {
+  // the next held back message, its Message Expiry Interval reduced by the time it waited
+  pub next: Option<PublishPacket>,
+  // held back messages that expired before quota was available, never to be sent
+  pub expired: Vec<PublishPacket>
+}
+
+impl Default for FlowControl {
+  fn default() -> Self {
+    Self::new(DEFAULT_RECEIVE_MAXIMUM)
+  }
+}
+
+impl FlowControl {
+  // receive_maximum is the limit we advertised to the peer
+  pub fn new(receive_maximum: u16) -> Self {
+    Self {
+      send_maximum: DEFAULT_RECEIVE_MAXIMUM,
+      in_flight: 0,
+      pending: VecDeque::new(),
+      receive_maximum: Self::normalize(receive_maximum),
+      received: HashSet::new()
+    }
+  }
+
+  pub fn apply_connect(&mut self, packet: &ConnectPacket) {
+    self.set_send_maximum(Self::find_receive_maximum(packet.properties.receive_maximum));
+  }
+
+  pub fn apply_connack(&mut self, packet: &ConnackPacket) {
+    self.set_send_maximum(Self::find_receive_maximum(packet.properties.receive_maximum));
+  }
+
+  // a lower maximum holds back new messages until enough of those in flight are acknowledged
+  pub fn set_send_maximum(&mut self, send_maximum: u16) {
+    self.send_maximum = Self::normalize(send_maximum);
+  }
+
+  // returns the packet if it may be sent now, otherwise holds it back until quota is available
+  pub fn send(&mut self, message: ExpiringMessage) -> Option<PublishPacket> {
+    if message.packet.config.qos == QoS::AtMostOnce {
+      return Some(message.packet);
+    }
+
+    if self.in_flight >= self.send_maximum || !self.pending.is_empty() {
+      self.pending.push_back(message);
+      return None;
+    }
+
+    self.in_flight += 1;
+    Some(message.packet)
+  }
+
+  // returns a held back message that may now be sent, if any, dropping the expired ones before it
+  pub fn acknowledge<C: Clock>(&mut self, packet: &DecodedPacket, clock: &C) -> Released {
+    let mut released = Released::default();
+    match packet {
+      DecodedPacket::Puback(_) | DecodedPacket::Pubcomp(_) => {},
+      DecodedPacket::Pubrec(packet) if packet.reason_code.is_error() => {},
+      _ => return released
+    }
+
+    self.in_flight = self.in_flight.saturating_sub(1);
+
+    while self.in_flight < self.send_maximum && released.next.is_none() {
+      let message = match self.pending.pop_front() {
+        Some(message) => message,
+        None => break
+      };
+      match message.deliver(clock) {
+        Some(packet) => {
+          self.in_flight += 1;
+          released.next = Some(packet);
+        },
+        None => released.expired.push(message.packet)
+      }
+    }
+    released
+  }
+
+  // puts back a message that was let through but could not be sent, e.g. for want of a packet
+  // id; it goes out first once the next acknowledgement frees a slot
+  pub fn hold(&mut self, message: ExpiringMessage) {
+    self.in_flight = self.in_flight.saturating_sub(1);
+    self.pending.push_front(message);
+  }
+
+  // the held back messages, to keep with the session when the connection ends
+  pub fn take_pending(&mut self) -> Vec<ExpiringMessage> {
+    self.pending.drain(..).collect()
+  }
+
+  pub fn receive(&mut self, packet: &PublishPacket) -> Result<(), ReasonCode> {
+    let packet_id = match (packet.config.qos, packet.packet_id) {
+      (QoS::AtMostOnce, _) | (_, None) => return Ok(()),
+      (_, Some(packet_id)) => packet_id
+    };
+
+    if self.received.contains(&packet_id) {
+      return Ok(());
+    }
+
+    if self.received.len() >= self.receive_maximum as usize {
+      return Err(ReasonCode::ReceiveMaximumExceeded);
+    }
+
+    self.received.insert(packet_id);
+    Ok(())
+  }
+
+  // called with the PUBACK, PUBREC or PUBCOMP we send back to free the inbound slot
+  pub fn respond(&mut self, packet: &DecodedPacket) {
+    match packet {
+      DecodedPacket::Puback(packet) => self.received.remove(&packet.packet_id),
+      DecodedPacket::Pubcomp(packet) => self.received.remove(&packet.packet_id),
+      DecodedPacket::Pubrec(packet) if packet.reason_code.is_error() => self.received.remove(&packet.packet_id),
+      _ => false
+    };
+  }
+
+  pub fn send_quota(&self) -> u16 {
+    self.send_maximum.saturating_sub(self.in_flight)
+  }
+
+  pub fn pending(&self) -> usize {
+    self.pending.len()
+  }
+
+  fn find_receive_maximum(receive_maximum: Option<u16>) -> u16 {
+    receive_maximum.unwrap_or(DEFAULT_RECEIVE_MAXIMUM)
+  }
+
+  fn normalize(receive_maximum: u16) -> u16 {
+    match receive_maximum {
+      0 => DEFAULT_RECEIVE_MAXIMUM,
+      value => value
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+  use crate::types::{PublishProperties, PubackPacket, PubrecPacket, PubcompPacket, ConnectProperties, ConnackProperties};
+  use crate::session::{publish, ManualClock};
+  use super::*;
+
+  fn message(packet: PublishPacket) -> ExpiringMessage {
+    ExpiringMessage::new(packet, &ManualClock::new())
+  }
+
+  fn released(next: Option<PublishPacket>) -> Released {
+    Released { next, expired: vec![] }
+  }
+
+  fn puback(packet_id: u16) -> DecodedPacket {
+    DecodedPacket::Puback(PubackPacket { packet_id, reason_code: ReasonCode::Success, properties: Default::default() })
+  }
+
+  fn connack(receive_maximum: u16) -> ConnackPacket {
+    ConnackPacket {
+      session_present: false,
+      reason_code: ReasonCode::Success,
+      properties: ConnackProperties { receive_maximum: Some(receive_maximum), ..Default::default() }
+    }
+  }
+
+  #[test]
+  fn apply_connack_test() {
+    let mut flow_control = FlowControl::default();
+    assert_eq!(flow_control.send_quota(), 65535);
+
+    flow_control.apply_connack(&connack(2));
+    assert_eq!(flow_control.send_quota(), 2);
+  }
+
+  #[test]
+  fn apply_connect_test() {
+    let mut flow_control = FlowControl::default();
+    flow_control.apply_connect(&ConnectPacket {
+      client_id: "test".to_owned(),
+      clean_start: true,
+      will_config: None,
+      keep_alive: 0,
+      username: None,
+      password: None,
+      properties: ConnectProperties { receive_maximum: Some(10), ..Default::default() }
+    });
+    assert_eq!(flow_control.send_quota(), 10);
+  }
+
+  #[test]
+  fn send_holds_back_test() {
+    let clock = ManualClock::new();
+    let mut flow_control = FlowControl::default();
+    flow_control.apply_connack(&connack(1));
+
+    assert_eq!(flow_control.send(message(publish(Some(1), 1))), Some(publish(Some(1), 1)));
+    assert_eq!(flow_control.send(message(publish(Some(2), 2))), None);
+    assert_eq!(flow_control.send(message(publish(None, 0))), Some(publish(None, 0)));
+    assert_eq!(flow_control.pending(), 1);
+
+    assert_eq!(flow_control.acknowledge(&puback(1), &clock), released(Some(publish(Some(2), 2))));
+    assert_eq!(flow_control.send_quota(), 0);
+
+    let pubcomp = DecodedPacket::Pubcomp(PubcompPacket { packet_id: 2, reason_code: ReasonCode::Success, properties: Default::default() });
+    assert_eq!(flow_control.acknowledge(&pubcomp, &clock), released(None));
+    assert_eq!(flow_control.send_quota(), 1);
+  }
+
+  #[test]
+  fn acknowledge_pubrec_test() {
+    let clock = ManualClock::new();
+    let mut flow_control = FlowControl::default();
+    flow_control.apply_connack(&connack(1));
+    flow_control.send(message(publish(Some(1), 2)));
+
+    let pubrec = DecodedPacket::Pubrec(PubrecPacket { packet_id: 1, reason_code: ReasonCode::Success, properties: Default::default() });
+    flow_control.acknowledge(&pubrec, &clock);
+    assert_eq!(flow_control.send_quota(), 0);
+
+    let pubrec = DecodedPacket::Pubrec(PubrecPacket { packet_id: 1, reason_code: ReasonCode::QuotaExceeded, properties: Default::default() });
+    flow_control.acknowledge(&pubrec, &clock);
+    assert_eq!(flow_control.send_quota(), 1);
+  }
+
+  #[test]
+  fn lower_send_maximum_test() {
+    let mut flow_control = FlowControl::default();
+    flow_control.send(message(publish(Some(1), 1)));
+    flow_control.send(message(publish(Some(2), 1)));
+
+    flow_control.set_send_maximum(1);
+    assert_eq!(flow_control.send_quota(), 0);
+    assert_eq!(flow_control.send(message(publish(Some(3), 1))), None);
+  }
+
+  #[test]
+  fn lower_send_maximum_in_flight_test() {
+    let clock = ManualClock::new();
+    let mut flow_control = FlowControl::default();
+    flow_control.set_send_maximum(10);
+    for packet_id in 1..=5 {
+      assert!(flow_control.send(message(publish(Some(packet_id), 1))).is_some());
+    }
+
+    // nothing more goes out until fewer than two messages are in flight
+    flow_control.set_send_maximum(2);
+    assert_eq!(flow_control.send(message(publish(Some(6), 1))), None);
+    for packet_id in 1..=3 {
+      assert_eq!(flow_control.acknowledge(&puback(packet_id), &clock), released(None));
+    }
+    assert_eq!(flow_control.acknowledge(&puback(4), &clock), released(Some(publish(Some(6), 1))));
+    assert_eq!(flow_control.send_quota(), 0);
+  }
+
+  #[test]
+  fn hold_test() {
+    let clock = ManualClock::new();
+    let mut flow_control = FlowControl::default();
+    flow_control.apply_connack(&connack(2));
+    flow_control.send(message(publish(Some(1), 1)));
+    let packet = flow_control.send(message(publish(None, 1))).unwrap();
+
+    // the message that could not be sent goes out ahead of those held back after it
+    flow_control.hold(message(packet));
+    assert_eq!(flow_control.send_quota(), 1);
+    flow_control.send(message(publish(None, 2)));
+    assert_eq!(flow_control.acknowledge(&puback(1), &clock), released(Some(publish(None, 1))));
+
+    let pending = flow_control.take_pending();
+    assert_eq!(pending.into_iter().map(|message| message.packet).collect::<Vec<_>>(), vec![publish(None, 2)]);
+    assert_eq!(flow_control.pending(), 0);
+  }
+
+  #[test]
+  fn held_back_expiry_test() {
+    let clock = ManualClock::new();
+    let mut flow_control = FlowControl::default();
+    flow_control.apply_connack(&connack(1));
+    let expiring = |packet_id, seconds| {
+      let mut packet = publish(Some(packet_id), 1);
+      packet.properties = PublishProperties { message_expiry_interval: Some(seconds), ..Default::default() };
+      ExpiringMessage::new(packet, &clock)
+    };
+
+    flow_control.send(message(publish(Some(1), 1)));
+    let short = expiring(2, 5);
+    flow_control.send(short.clone());
+    flow_control.send(expiring(3, 20));
+
+    // the first held back message expired while waiting, the second goes out with what is left of its interval
+    clock.advance(Duration::from_secs(10));
+    let released = flow_control.acknowledge(&puback(1), &clock);
+    assert_eq!(released.expired, vec![short.packet]);
+    let next = released.next.unwrap();
+    assert_eq!((next.packet_id, next.properties.message_expiry_interval), (Some(3), Some(10)));
+    assert_eq!((flow_control.send_quota(), flow_control.pending()), (0, 0));
+  }
+
+  #[test]
+  fn receive_maximum_test() {
+    let mut flow_control = FlowControl::new(2);
+    flow_control.receive(&publish(None, 0)).unwrap();
+    flow_control.receive(&publish(Some(1), 1)).unwrap();
+    flow_control.receive(&publish(Some(2), 2)).unwrap();
+    // a retransmission does not take another slot
+    flow_control.receive(&publish(Some(2), 2)).unwrap();
+    assert_eq!(flow_control.receive(&publish(Some(3), 1)), Err(ReasonCode::ReceiveMaximumExceeded));
+
+    flow_control.respond(&puback(1));
+    flow_control.receive(&publish(Some(3), 1)).unwrap();
+  }
+}
@@ -0,0 +1,403 @@
+use std::collections::HashSet;
+
+use crate::types::{
+  DecodedPacket, ReasonCode, PublishPacket, PubackPacket, PubrecPacket, PubrelPacket, PubcompPacket, QoS
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum OutgoingState {
+  // waiting for PUBACK (QoS 1) or PUBREC (QoS 2)
+  Publish(PublishPacket),
+  // PUBREL sent, waiting for PUBCOMP
+  Release(u16)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Received {
+  pub message: Option<PublishPacket>,
+  pub response: Option<DecodedPacket>
+}
+
+#[derive(Default)]
+pub struct Inflight {
+  outgoing: Vec<(u16, OutgoingState)>,
+  incoming: HashSet<u16>
+}
+
+impl Inflight {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn send(&mut self, packet: PublishPacket) -> Result<(), ReasonCode> {
+    if packet.config.qos == QoS::AtMostOnce {
+      return Ok(());
+    }
+
+    let packet_id = match packet.packet_id {
+      Some(packet_id) if packet_id != 0 => packet_id,
+      _ => return Err(ReasonCode::ProtocolError)
+    };
+
+    if self.position(packet_id).is_some() {
+      return Err(ReasonCode::PacketIdentifierInUse);
+    }
+
+    self.outgoing.push((packet_id, OutgoingState::Publish(packet)));
+    Ok(())
+  }
+
+  pub fn handle_puback(&mut self, packet: &PubackPacket) -> Result<PublishPacket, ReasonCode> {
+    match self.position(packet.packet_id) {
+      Some(index) => match &self.outgoing[index].1 {
+        OutgoingState::Publish(publish) if publish.config.qos == QoS::AtLeastOnce => {
+          let publish = publish.clone();
+          self.outgoing.remove(index);
+          Ok(publish)
+        },
+        _ => Err(ReasonCode::ProtocolError)
+      },
+      None => Err(ReasonCode::PacketIdentifierNotFound)
+    }
+  }
+
+  // returns the PUBREL to send, or None if the receiver rejected the message
+  pub fn handle_pubrec(&mut self, packet: &PubrecPacket) -> Result<Option<PubrelPacket>, ReasonCode> {
+    let index = match self.position(packet.packet_id) {
+      Some(index) => index,
+      None => return Err(ReasonCode::PacketIdentifierNotFound)
+    };
+
+    match &self.outgoing[index].1 {
+      OutgoingState::Publish(publish) if publish.config.qos == QoS::ExactlyOnce => {},
+      OutgoingState::Release(_) => {},
+      _ => return Err(ReasonCode::ProtocolError)
+    }
+
+    if packet.reason_code.is_error() {
+      self.outgoing.remove(index);
+      return Ok(None);
+    }
+
+    self.outgoing[index].1 = OutgoingState::Release(packet.packet_id);
+    Ok(Some(PubrelPacket {
+      packet_id: packet.packet_id,
+      reason_code: ReasonCode::Success,
+      properties: Default::default()
+    }))
+  }
+
+  pub fn handle_pubcomp(&mut self, packet: &PubcompPacket) -> Result<(), ReasonCode> {
+    match self.position(packet.packet_id) {
+      Some(index) => match self.outgoing[index].1 {
+        OutgoingState::Release(_) => {
+          self.outgoing.remove(index);
+          Ok(())
+        },
+        _ => Err(ReasonCode::ProtocolError)
+      },
+      None => Err(ReasonCode::PacketIdentifierNotFound)
+    }
+  }
+
+  // QoS 2 messages are only handed to the application once until the matching PUBREL arrives
+  pub fn receive(&mut self, packet: PublishPacket) -> Received {
+    match (packet.config.qos, packet.packet_id) {
+      (QoS::AtLeastOnce, Some(packet_id)) => Received {
+        message: Some(packet),
+        response: Some(DecodedPacket::Puback(PubackPacket {
+          packet_id,
+          reason_code: ReasonCode::Success,
+          properties: Default::default()
+        }))
+      },
+      (QoS::ExactlyOnce, Some(packet_id)) => Received {
+        message: match self.incoming.insert(packet_id) {
+          true => Some(packet),
+          false => None
+        },
+        response: Some(DecodedPacket::Pubrec(PubrecPacket {
+          packet_id,
+          reason_code: ReasonCode::Success,
+          properties: Default::default()
+        }))
+      },
+      _ => Received {
+        message: Some(packet),
+        response: None
+      }
+    }
+  }
+
+  pub fn handle_pubrel(&mut self, packet: &PubrelPacket) -> PubcompPacket {
+    let reason_code = match self.incoming.remove(&packet.packet_id) {
+      true => ReasonCode::Success,
+      false => ReasonCode::PacketIdentifierNotFound
+    };
+
+    PubcompPacket {
+      packet_id: packet.packet_id,
+      reason_code,
+      properties: Default::default()
+    }
+  }
+
+  // turns the acknowledgement from receive into a refusal, for a message that could not be
+  // handled; a refused QoS 2 message is accepted again if the sender publishes it anew
+  pub fn refuse(&mut self, response: &mut DecodedPacket, reason_code: ReasonCode) {
+    match response {
+      DecodedPacket::Puback(packet) => packet.reason_code = reason_code,
+      DecodedPacket::Pubrec(packet) => {
+        self.incoming.remove(&packet.packet_id);
+        packet.reason_code = reason_code;
+      },
+      _ => {}
+    }
+  }
+
+  // forgets a PUBLISH that was never sent, e.g. one that expired while held back by flow control
+  pub fn cancel(&mut self, packet_id: u16) -> Option<PublishPacket> {
+    let index = self.outgoing.iter().position(|(id, state)| *id == packet_id && matches!(state, OutgoingState::Publish(_)))?;
+    match self.outgoing.remove(index).1 {
+      OutgoingState::Publish(publish) => Some(publish),
+      OutgoingState::Release(_) => None
+    }
+  }
+
+  // packets to resend after a reconnect, in their original order
+  pub fn retransmit(&mut self) -> Vec<DecodedPacket> {
+    self.outgoing.iter_mut().map(|(packet_id, state)| match state {
+      OutgoingState::Publish(publish) => {
+        publish.config.dup = true;
+        DecodedPacket::Publish(publish.clone())
+      },
+      OutgoingState::Release(_) => DecodedPacket::Pubrel(PubrelPacket {
+        packet_id: *packet_id,
+        reason_code: ReasonCode::Success,
+        properties: Default::default()
+      })
+    }).collect()
+  }
+
+  // puts back the state of a resumed session
+  pub fn restore(&mut self, state: OutgoingState) -> Result<(), ReasonCode> {
+    match state {
+      OutgoingState::Publish(packet) => self.send(packet),
+      OutgoingState::Release(packet_id) => {
+        if self.position(packet_id).is_some() {
+          return Err(ReasonCode::PacketIdentifierInUse);
+        }
+        self.outgoing.push((packet_id, OutgoingState::Release(packet_id)));
+        Ok(())
+      }
+    }
+  }
+
+  pub fn restore_received(&mut self, packet_id: u16) {
+    self.incoming.insert(packet_id);
+  }
+
+  pub fn outgoing(&self) -> impl Iterator<Item = &OutgoingState> {
+    self.outgoing.iter().map(|(_, state)| state)
+  }
+
+  // ids of QoS 2 messages received and waiting for PUBREL
+  pub fn received(&self) -> impl Iterator<Item = u16> + '_ {
+    self.incoming.iter().copied()
+  }
+
+  pub fn len(&self) -> usize {
+    self.outgoing.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.outgoing.is_empty()
+  }
+
+  fn position(&self, packet_id: u16) -> Option<usize> {
+    self.outgoing.iter().position(|(id, _)| *id == packet_id)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::session::publish;
+  use super::*;
+
+  fn puback(packet_id: u16) -> PubackPacket {
+    PubackPacket { packet_id, reason_code: ReasonCode::Success, properties: Default::default() }
+  }
+
+  fn pubrec(packet_id: u16, reason_code: ReasonCode) -> PubrecPacket {
+    PubrecPacket { packet_id, reason_code, properties: Default::default() }
+  }
+
+  fn pubrel(packet_id: u16) -> PubrelPacket {
+    PubrelPacket { packet_id, reason_code: ReasonCode::Success, properties: Default::default() }
+  }
+
+  fn pubcomp(packet_id: u16) -> PubcompPacket {
+    PubcompPacket { packet_id, reason_code: ReasonCode::Success, properties: Default::default() }
+  }
+
+  #[test]
+  fn send_qos0_test() {
+    let mut inflight = Inflight::new();
+    inflight.send(publish(None, 0)).unwrap();
+    assert!(inflight.is_empty());
+  }
+
+  #[test]
+  fn send_invalid_test() {
+    let mut inflight = Inflight::new();
+    assert_eq!(inflight.send(publish(None, 1)), Err(ReasonCode::ProtocolError));
+    let mut packet = publish(None, 1);
+    packet.packet_id = Some(0);
+    assert_eq!(inflight.send(packet), Err(ReasonCode::ProtocolError));
+    inflight.send(publish(Some(1), 1)).unwrap();
+    assert_eq!(inflight.send(publish(Some(1), 2)), Err(ReasonCode::PacketIdentifierInUse));
+  }
+
+  #[test]
+  fn qos1_flow_test() {
+    let mut inflight = Inflight::new();
+    inflight.send(publish(Some(1), 1)).unwrap();
+    assert_eq!(inflight.len(), 1);
+
+    assert_eq!(inflight.handle_puback(&puback(1)).unwrap(), publish(Some(1), 1));
+    assert!(inflight.is_empty());
+    assert_eq!(inflight.handle_puback(&puback(1)), Err(ReasonCode::PacketIdentifierNotFound));
+  }
+
+  #[test]
+  fn qos2_flow_test() {
+    let mut inflight = Inflight::new();
+    inflight.send(publish(Some(2), 2)).unwrap();
+
+    assert_eq!(inflight.handle_puback(&puback(2)), Err(ReasonCode::ProtocolError));
+    assert_eq!(inflight.handle_pubcomp(&pubcomp(2)), Err(ReasonCode::ProtocolError));
+
+    assert_eq!(inflight.handle_pubrec(&pubrec(2, ReasonCode::Success)).unwrap(), Some(pubrel(2)));
+    assert_eq!(inflight.outgoing().next(), Some(&OutgoingState::Release(2)));
+
+    // a duplicate PUBREC is answered with the same PUBREL
+    assert_eq!(inflight.handle_pubrec(&pubrec(2, ReasonCode::Success)).unwrap(), Some(pubrel(2)));
+
+    inflight.handle_pubcomp(&pubcomp(2)).unwrap();
+    assert!(inflight.is_empty());
+  }
+
+  #[test]
+  fn qos2_rejected_test() {
+    let mut inflight = Inflight::new();
+    inflight.send(publish(Some(3), 2)).unwrap();
+
+    assert_eq!(inflight.handle_pubrec(&pubrec(3, ReasonCode::QuotaExceeded)).unwrap(), None);
+    assert!(inflight.is_empty());
+  }
+
+  #[test]
+  fn retransmit_test() {
+    let mut inflight = Inflight::new();
+    inflight.send(publish(Some(1), 1)).unwrap();
+    inflight.send(publish(Some(2), 2)).unwrap();
+    inflight.send(publish(Some(3), 2)).unwrap();
+    inflight.handle_pubrec(&pubrec(2, ReasonCode::Success)).unwrap();
+
+    let mut expected1 = publish(Some(1), 1);
+    expected1.config.dup = true;
+    let mut expected3 = publish(Some(3), 2);
+    expected3.config.dup = true;
+
+    assert_eq!(inflight.retransmit(), vec![
+      DecodedPacket::Publish(expected1),
+      DecodedPacket::Pubrel(pubrel(2)),
+      DecodedPacket::Publish(expected3)
+    ]);
+  }
+
+  #[test]
+  fn cancel_test() {
+    let mut inflight = Inflight::new();
+    inflight.send(publish(Some(1), 1)).unwrap();
+    inflight.send(publish(Some(2), 2)).unwrap();
+    inflight.handle_pubrec(&pubrec(2, ReasonCode::Success)).unwrap();
+
+    assert_eq!(inflight.cancel(1), Some(publish(Some(1), 1)));
+    assert_eq!(inflight.cancel(1), None);
+    // a PUBREL has already been sent for the second message
+    assert_eq!(inflight.cancel(2), None);
+    assert_eq!(inflight.len(), 1);
+  }
+
+  #[test]
+  fn restore_test() {
+    let mut inflight = Inflight::new();
+    inflight.restore(OutgoingState::Publish(publish(Some(1), 1))).unwrap();
+    inflight.restore(OutgoingState::Release(2)).unwrap();
+    assert_eq!(inflight.restore(OutgoingState::Release(1)), Err(ReasonCode::PacketIdentifierInUse));
+    inflight.restore_received(3);
+
+    inflight.handle_pubcomp(&pubcomp(2)).unwrap();
+    assert_eq!(inflight.received().collect::<Vec<_>>(), vec![3]);
+    assert_eq!(inflight.receive(publish(Some(3), 2)).message, None);
+  }
+
+  #[test]
+  fn receive_qos0_test() {
+    let mut inflight = Inflight::new();
+    let received = inflight.receive(publish(None, 0));
+    assert_eq!(received, Received { message: Some(publish(None, 0)), response: None });
+  }
+
+  #[test]
+  fn receive_qos1_test() {
+    let mut inflight = Inflight::new();
+    let received = inflight.receive(publish(Some(5), 1));
+    assert_eq!(received.message, Some(publish(Some(5), 1)));
+    assert_eq!(received.response, Some(DecodedPacket::Puback(puback(5))));
+  }
+
+  #[test]
+  fn receive_qos2_deduplicate_test() {
+    let mut inflight = Inflight::new();
+    let received = inflight.receive(publish(Some(6), 2));
+    assert_eq!(received.message, Some(publish(Some(6), 2)));
+    assert_eq!(received.response, Some(DecodedPacket::Pubrec(pubrec(6, ReasonCode::Success))));
+
+    let mut duplicate = publish(Some(6), 2);
+    duplicate.config.dup = true;
+    let received = inflight.receive(duplicate);
+    assert_eq!(received.message, None);
+    assert_eq!(received.response, Some(DecodedPacket::Pubrec(pubrec(6, ReasonCode::Success))));
+
+    assert_eq!(inflight.handle_pubrel(&pubrel(6)), pubcomp(6));
+
+    let received = inflight.receive(publish(Some(6), 2));
+    assert_eq!(received.message, Some(publish(Some(6), 2)));
+  }
+
+  #[test]
+  fn refuse_test() {
+    let mut inflight = Inflight::new();
+    let mut response = inflight.receive(publish(Some(7), 1)).response.unwrap();
+    inflight.refuse(&mut response, ReasonCode::UnspecifiedError);
+    assert_eq!(response, DecodedPacket::Puback(PubackPacket {
+      packet_id: 7,
+      reason_code: ReasonCode::UnspecifiedError,
+      properties: Default::default()
+    }));
+
+    let mut response = inflight.receive(publish(Some(8), 2)).response.unwrap();
+    inflight.refuse(&mut response, ReasonCode::UnspecifiedError);
+    assert_eq!(response, DecodedPacket::Pubrec(pubrec(8, ReasonCode::UnspecifiedError)));
+    assert_eq!(inflight.received().count(), 0);
+    assert_eq!(inflight.receive(publish(Some(8), 2)).message, Some(publish(Some(8), 2)));
+  }
+
+  #[test]
+  fn unknown_pubrel_test() {
+    let mut inflight = Inflight::new();
+    assert_eq!(inflight.handle_pubrel(&pubrel(9)).reason_code, ReasonCode::PacketIdentifierNotFound);
+  }
+}
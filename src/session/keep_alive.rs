@@ -0,0 +1,193 @@
+use std::time::{Duration, Instant};
+
+use crate::types::{DecodedPacket, ConnackPacket, Role};
+use super::clock::{Clock, SystemClock};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum KeepAliveAction {
+  SendPingReq,
+  Close
+}
+
+pub struct KeepAlive<C: Clock = SystemClock> {
+  clock: C,
+  role: Role,
+  interval: Option<Duration>,
+  last_received: Instant,
+  last_sent: Instant,
+  ping_outstanding: bool
+}
+
+impl KeepAlive<SystemClock> {
+  pub fn new(role: Role, keep_alive: u16) -> Self {
+    Self::with_clock(role, keep_alive, SystemClock)
+  }
+}
+
+impl<C: Clock> KeepAlive<C> {
+  pub fn with_clock(role: Role, keep_alive: u16, clock: C) -> Self {
+    let now = clock.now();
+    Self {
+      clock,
+      role,
+      interval: Self::to_interval(keep_alive),
+      last_received: now,
+      last_sent: now,
+      ping_outstanding: false
+    }
+  }
+
+  // the server may replace the keep alive the client asked for
+  pub fn apply_connack(&mut self, packet: &ConnackPacket) {
+    if let Some(keep_alive) = packet.properties.server_keep_alive {
+      self.set_keep_alive(keep_alive);
+    }
+  }
+
+  pub fn set_keep_alive(&mut self, keep_alive: u16) {
+    self.interval = Self::to_interval(keep_alive);
+  }
+
+  pub fn keep_alive(&self) -> Option<Duration> {
+    self.interval
+  }
+
+  pub fn received(&mut self, packet: &DecodedPacket) {
+    self.last_received = self.clock.now();
+    if let DecodedPacket::PingResp(_) = packet {
+      self.ping_outstanding = false;
+    }
+  }
+
+  pub fn sent(&mut self) {
+    self.last_sent = self.clock.now();
+  }
+
+  pub fn poll(&mut self) -> Option<KeepAliveAction> {
+    let interval = self.interval?;
+    let now = self.clock.now();
+
+    if now >= self.last_received + Self::grace(interval) {
+      return Some(KeepAliveAction::Close);
+    }
+
+    if self.role == Role::Client && !self.ping_outstanding && now >= self.last_sent + interval {
+      self.ping_outstanding = true;
+      self.last_sent = now;
+      return Some(KeepAliveAction::SendPingReq);
+    }
+
+    None
+  }
+
+  // the next instant at which poll may return an action
+  pub fn next_deadline(&self) -> Option<Instant> {
+    let interval = self.interval?;
+    let close = self.last_received + Self::grace(interval);
+
+    match self.role {
+      Role::Client if !self.ping_outstanding => Some(close.min(self.last_sent + interval)),
+      _ => Some(close)
+    }
+  }
+
+  fn grace(interval: Duration) -> Duration {
+    interval * 3 / 2
+  }
+
+  fn to_interval(keep_alive: u16) -> Option<Duration> {
+    match keep_alive {
+      0 => None,
+      seconds => Some(Duration::from_secs(seconds as u64))
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::types::{ReasonCode, PingReqPacket, PingRespPacket, ConnackProperties};
+  use crate::session::ManualClock;
+  use super::*;
+
+  #[test]
+  fn client_ping_test() {
+    let clock = ManualClock::new();
+    let mut keep_alive = KeepAlive::with_clock(Role::Client, 10, clock.clone());
+    assert_eq!(keep_alive.poll(), None);
+
+    clock.advance(Duration::from_secs(10));
+    assert_eq!(keep_alive.poll(), Some(KeepAliveAction::SendPingReq));
+    assert_eq!(keep_alive.poll(), None);
+
+    clock.advance(Duration::from_secs(2));
+    keep_alive.received(&DecodedPacket::PingResp(PingRespPacket {}));
+    assert_eq!(keep_alive.next_deadline(), Some(clock.now() + Duration::from_secs(8)));
+  }
+
+  #[test]
+  fn client_sent_traffic_delays_ping_test() {
+    let clock = ManualClock::new();
+    let mut keep_alive = KeepAlive::with_clock(Role::Client, 10, clock.clone());
+
+    clock.advance(Duration::from_secs(6));
+    keep_alive.sent();
+    keep_alive.received(&DecodedPacket::PingResp(PingRespPacket {}));
+
+    clock.advance(Duration::from_secs(6));
+    assert_eq!(keep_alive.poll(), None);
+  }
+
+  #[test]
+  fn client_close_test() {
+    let clock = ManualClock::new();
+    let mut keep_alive = KeepAlive::with_clock(Role::Client, 10, clock.clone());
+
+    clock.advance(Duration::from_secs(10));
+    assert_eq!(keep_alive.poll(), Some(KeepAliveAction::SendPingReq));
+
+    clock.advance(Duration::from_secs(4));
+    assert_eq!(keep_alive.poll(), None);
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(keep_alive.poll(), Some(KeepAliveAction::Close));
+  }
+
+  #[test]
+  fn server_close_test() {
+    let clock = ManualClock::new();
+    let mut keep_alive = KeepAlive::with_clock(Role::Server, 10, clock.clone());
+
+    clock.advance(Duration::from_secs(14));
+    assert_eq!(keep_alive.poll(), None);
+    keep_alive.received(&DecodedPacket::PingReq(PingReqPacket {}));
+
+    clock.advance(Duration::from_secs(14));
+    assert_eq!(keep_alive.poll(), None);
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(keep_alive.poll(), Some(KeepAliveAction::Close));
+  }
+
+  #[test]
+  fn disabled_test() {
+    let clock = ManualClock::new();
+    let mut keep_alive = KeepAlive::with_clock(Role::Client, 0, clock.clone());
+
+    clock.advance(Duration::from_secs(100000));
+    assert_eq!(keep_alive.poll(), None);
+    assert_eq!(keep_alive.next_deadline(), None);
+  }
+
+  #[test]
+  fn server_keep_alive_test() {
+    let clock = ManualClock::new();
+    let mut keep_alive = KeepAlive::with_clock(Role::Client, 60, clock.clone());
+    keep_alive.apply_connack(&ConnackPacket {
+      session_present: false,
+      reason_code: ReasonCode::Success,
+      properties: ConnackProperties { server_keep_alive: Some(5), ..Default::default() }
+    });
+    assert_eq!(keep_alive.keep_alive(), Some(Duration::from_secs(5)));
+
+    clock.advance(Duration::from_secs(5));
+    assert_eq!(keep_alive.poll(), Some(KeepAliveAction::SendPingReq));
+  }
+}
@@ -0,0 +1,30 @@
+mod packet_id;
+mod inflight;
+mod flow_control;
+mod clock;
+mod keep_alive;
+mod retain;
+mod will;
+mod store;
+mod expiry;
+
+pub use packet_id::*;
+pub use inflight::*;
+pub use flow_control::*;
+pub use clock::*;
+pub use keep_alive::*;
+pub use retain::*;
+pub use will::*;
+pub use store::*;
+pub use expiry::*;
+
+// the message the session tests pass around, where only the packet id and QoS matter
+#[cfg(test)]
+fn publish(packet_id: Option<u16>, qos: u8) -> crate::types::PublishPacket {
+  use std::convert::TryFrom;
+  let builder = crate::types::PublishPacket::builder("test").payload("hello").qos(crate::types::QoS::try_from(qos).unwrap());
+  match packet_id {
+    Some(packet_id) => builder.packet_id(packet_id),
+    None => builder
+  }.build().unwrap()
+}
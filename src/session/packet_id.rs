@@ -0,0 +1,180 @@
+use std::collections::HashSet;
+
+use crate::types::{DecodedPacket, ReasonCode};
+
+pub struct PacketIdAllocator {
+  next: u16,
+  in_use: HashSet<u16>
+}
+
+impl Default for PacketIdAllocator {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl PacketIdAllocator {
+  pub fn new() -> Self {
+    Self {
+      next: 1,
+      in_use: HashSet::new()
+    }
+  }
+
+  // hands out the next free identifier, wrapping around and never returning 0
+  pub fn allocate(&mut self) -> Result<u16, ReasonCode> {
+    if self.in_use.len() == u16::MAX as usize {
+      return Err(ReasonCode::PacketIdentifierInUse);
+    }
+
+    while self.in_use.contains(&self.next) {
+      self.advance();
+    }
+
+    let packet_id = self.next;
+    self.in_use.insert(packet_id);
+    self.advance();
+    Ok(packet_id)
+  }
+
+  // marks an identifier chosen elsewhere (e.g. restored from a session) as in flight
+  pub fn reserve(&mut self, packet_id: u16) -> Result<(), ReasonCode> {
+    if packet_id == 0 {
+      return Err(ReasonCode::ProtocolError);
+    }
+    if !self.in_use.insert(packet_id) {
+      return Err(ReasonCode::PacketIdentifierInUse);
+    }
+    Ok(())
+  }
+
+  pub fn release(&mut self, packet_id: u16) -> Result<(), ReasonCode> {
+    match self.in_use.remove(&packet_id) {
+      true => Ok(()),
+      false => Err(ReasonCode::PacketIdentifierNotFound)
+    }
+  }
+
+  // releases the identifier of a packet that ends an exchange, returning the id that was freed
+  pub fn acknowledge(&mut self, packet: &DecodedPacket) -> Result<Option<u16>, ReasonCode> {
+    let packet_id = match packet {
+      DecodedPacket::Puback(packet) => packet.packet_id,
+      DecodedPacket::Pubcomp(packet) => packet.packet_id,
+      DecodedPacket::Suback(packet) => packet.packet_id,
+      DecodedPacket::Unsuback(packet) => packet.packet_id,
+      // a failed PUBREC ends the QoS 2 exchange without a PUBCOMP
+      DecodedPacket::Pubrec(packet) if packet.reason_code.is_error() => packet.packet_id,
+      _ => return Ok(None)
+    };
+    self.release(packet_id)?;
+    Ok(Some(packet_id))
+  }
+
+  pub fn is_in_use(&self, packet_id: u16) -> bool {
+    self.in_use.contains(&packet_id)
+  }
+
+  pub fn len(&self) -> usize {
+    self.in_use.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.in_use.is_empty()
+  }
+
+  fn advance(&mut self) {
+    self.next = match self.next {
+      u16::MAX => 1,
+      next => next + 1
+    };
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::types::{PubackPacket, PubrecPacket, PubcompPacket, PingReqPacket};
+  use super::*;
+
+  #[test]
+  fn allocate_test() {
+    let mut allocator = PacketIdAllocator::new();
+    assert_eq!(allocator.allocate().unwrap(), 1);
+    assert_eq!(allocator.allocate().unwrap(), 2);
+    assert!(allocator.is_in_use(1));
+    assert_eq!(allocator.len(), 2);
+  }
+
+  #[test]
+  fn allocate_skips_in_use_test() {
+    let mut allocator = PacketIdAllocator::new();
+    allocator.reserve(1).unwrap();
+    allocator.reserve(2).unwrap();
+    assert_eq!(allocator.allocate().unwrap(), 3);
+  }
+
+  #[test]
+  fn allocate_wraps_without_zero_test() {
+    let mut allocator = PacketIdAllocator::new();
+    allocator.next = u16::MAX;
+    assert_eq!(allocator.allocate().unwrap(), u16::MAX);
+    assert_eq!(allocator.allocate().unwrap(), 1);
+  }
+
+  #[test]
+  fn allocate_exhausted_test() {
+    let mut allocator = PacketIdAllocator::new();
+    for _ in 0..u16::MAX {
+      allocator.allocate().unwrap();
+    }
+    assert_eq!(allocator.allocate(), Err(ReasonCode::PacketIdentifierInUse));
+
+    allocator.release(42).unwrap();
+    assert_eq!(allocator.allocate().unwrap(), 42);
+  }
+
+  #[test]
+  fn reserve_test() {
+    let mut allocator = PacketIdAllocator::new();
+    assert_eq!(allocator.reserve(0), Err(ReasonCode::ProtocolError));
+    allocator.reserve(7).unwrap();
+    assert_eq!(allocator.reserve(7), Err(ReasonCode::PacketIdentifierInUse));
+  }
+
+  #[test]
+  fn release_test() {
+    let mut allocator = PacketIdAllocator::new();
+    let packet_id = allocator.allocate().unwrap();
+    allocator.release(packet_id).unwrap();
+    assert!(!allocator.is_in_use(packet_id));
+    assert_eq!(allocator.release(packet_id), Err(ReasonCode::PacketIdentifierNotFound));
+  }
+
+  #[test]
+  fn acknowledge_test() {
+    let mut allocator = PacketIdAllocator::new();
+    let packet_id = allocator.allocate().unwrap();
+
+    let pubrec = DecodedPacket::Pubrec(PubrecPacket { packet_id, reason_code: ReasonCode::Success, properties: Default::default() });
+    assert_eq!(allocator.acknowledge(&pubrec), Ok(None));
+    assert!(allocator.is_in_use(packet_id));
+
+    let pubcomp = DecodedPacket::Pubcomp(PubcompPacket { packet_id, reason_code: ReasonCode::Success, properties: Default::default() });
+    assert_eq!(allocator.acknowledge(&pubcomp), Ok(Some(packet_id)));
+    assert!(allocator.is_empty());
+
+    let puback = DecodedPacket::Puback(PubackPacket { packet_id, reason_code: ReasonCode::Success, properties: Default::default() });
+    assert_eq!(allocator.acknowledge(&puback), Err(ReasonCode::PacketIdentifierNotFound));
+
+    assert_eq!(allocator.acknowledge(&DecodedPacket::PingReq(PingReqPacket {})), Ok(None));
+  }
+
+  #[test]
+  fn acknowledge_failed_pubrec_test() {
+    let mut allocator = PacketIdAllocator::new();
+    let packet_id = allocator.allocate().unwrap();
+
+    let pubrec = DecodedPacket::Pubrec(PubrecPacket { packet_id, reason_code: ReasonCode::QuotaExceeded, properties: Default::default() });
+    assert_eq!(allocator.acknowledge(&pubrec), Ok(Some(packet_id)));
+    assert!(allocator.is_empty());
+  }
+}
@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+
+use crate::{
+  types::{PublishPacket, SubscriptionConfig, RetainHandling},
+  topic
+};
+use super::clock::{Clock, SystemClock};
+use super::expiry::ExpiringMessage;
+
+pub struct RetainedStore<C: Clock = SystemClock> {
+  clock: C,
+  messages: HashMap<String, ExpiringMessage>
+}
+
+impl Default for RetainedStore<SystemClock> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl RetainedStore<SystemClock> {
+  pub fn new() -> Self {
+    Self::with_clock(SystemClock)
+  }
+}
+
+impl<C: Clock> RetainedStore<C> {
+  pub fn with_clock(clock: C) -> Self {
+    Self {
+      clock,
+      messages: HashMap::new()
+    }
+  }
+
+  // stores a retained publish, an empty payload deletes the retained message for the topic
+  pub fn store(&mut self, packet: &PublishPacket) {
+    if packet.payload.is_empty() {
+      self.messages.remove(&packet.topic);
+      return;
+    }
+
+    let mut packet = packet.clone();
+    packet.packet_id = None;
+    packet.config.dup = false;
+    packet.config.retain = true;
+
+    self.messages.insert(packet.topic.clone(), ExpiringMessage::new(packet, &self.clock));
+  }
+
+  // retained messages are returned with the lifetime they have left
+  pub fn get(&mut self, topic: &str) -> Option<PublishPacket> {
+    self.purge_expired();
+    self.messages.get(topic).and_then(|message| message.deliver(&self.clock))
+  }
+
+  pub fn matching(&mut self, filter: &str) -> Vec<PublishPacket> {
+    self.purge_expired();
+    self.messages.values()
+      .filter(|message| topic::matches(filter, &message.packet.topic))
+      .filter_map(|message| message.deliver(&self.clock))
+      .collect()
+  }
+
+  // the retained messages to send for a new subscription according to its retain handling
+  pub fn on_subscribe(&mut self, subscription: &SubscriptionConfig, existed: bool) -> Vec<PublishPacket> {
+    match (subscription.retain_handling, existed) {
+      (RetainHandling::SendOnSubscribe, _) | (RetainHandling::SendOnNewSubscribe, false) => {},
+      _ => return Vec::new()
+    }
+
+    self.matching(&subscription.topic).into_iter().map(|mut packet| {
+      packet.config.qos = packet.config.qos.min(subscription.qos);
+      packet
+    }).collect()
+  }
+
+  pub fn purge_expired(&mut self) {
+    let clock = &self.clock;
+    self.messages.retain(|_, message| !message.is_expired(clock));
+  }
+
+  pub fn len(&self) -> usize {
+    self.messages.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.messages.is_empty()
+  }
+}
+
+// the copy of a live publish sent to a subscriber: QoS is capped by the subscription and the
+// retain flag is only kept when the subscription asked for Retain As Published
+pub fn forward(packet: &PublishPacket, subscription: &SubscriptionConfig) -> PublishPacket {
+  let mut packet = packet.clone();
+  packet.packet_id = None;
+  packet.config.dup = false;
+  packet.config.qos = packet.config.qos.min(subscription.qos);
+  packet.config.retain = packet.config.retain && subscription.rap;
+  packet
+}
+
+#[cfg(test)]
+mod tests {
+  use std::convert::TryFrom;
+  use std::time::Duration;
+  use crate::types::{PublishConfig, PublishProperties, QoS};
+  use crate::session::ManualClock;
+  use super::*;
+
+  fn publish(topic: &str, payload: &str, properties: PublishProperties) -> PublishPacket {
+    PublishPacket {
+      topic: topic.to_owned(),
+      packet_id: Some(1),
+      payload: payload.to_owned(),
+      config: PublishConfig { dup: true, qos: QoS::ExactlyOnce, retain: true },
+      properties
+    }
+  }
+
+  fn subscription(filter: &str, retain_handling: u8, rap: bool, qos: u8) -> SubscriptionConfig {
+    SubscriptionConfig {
+      topic: filter.to_owned(),
+      retain_handling: RetainHandling::try_from(retain_handling).unwrap(),
+      rap,
+      nl: false,
+      qos: QoS::try_from(qos).unwrap()
+    }
+  }
+
+  #[test]
+  fn store_test() {
+    let mut store = RetainedStore::new();
+    store.store(&publish("a/b", "hello", PublishProperties::default()));
+
+    let packet = store.get("a/b").unwrap();
+    assert_eq!(packet.packet_id, None);
+    assert!(!packet.config.dup);
+    assert_eq!(packet.payload, "hello");
+
+    store.store(&publish("a/b", "world", PublishProperties::default()));
+    assert_eq!(store.get("a/b").unwrap().payload, "world");
+    assert_eq!(store.len(), 1);
+  }
+
+  #[test]
+  fn empty_payload_deletes_test() {
+    let mut store = RetainedStore::new();
+    store.store(&publish("a/b", "hello", PublishProperties::default()));
+    store.store(&publish("a/b", "", PublishProperties::default()));
+    assert!(store.is_empty());
+  }
+
+  #[test]
+  fn matching_test() {
+    let mut store = RetainedStore::new();
+    store.store(&publish("a/b", "1", PublishProperties::default()));
+    store.store(&publish("a/c", "2", PublishProperties::default()));
+    store.store(&publish("b/c", "3", PublishProperties::default()));
+
+    let mut payloads: Vec<String> = store.matching("a/+").into_iter().map(|packet| packet.payload).collect();
+    payloads.sort();
+    assert_eq!(payloads, vec!["1", "2"]);
+  }
+
+  #[test]
+  fn expiry_test() {
+    let clock = ManualClock::new();
+    let mut store = RetainedStore::with_clock(clock.clone());
+    store.store(&publish("a", "1", PublishProperties { message_expiry_interval: Some(10), ..Default::default() }));
+    store.store(&publish("b", "2", PublishProperties::default()));
+
+    clock.advance(Duration::from_secs(9));
+    assert_eq!(store.get("a").unwrap().properties.message_expiry_interval, Some(1));
+
+    clock.advance(Duration::from_secs(1));
+    assert!(store.get("a").is_none());
+    assert!(store.get("b").is_some());
+  }
+
+  #[test]
+  fn retain_handling_test() {
+    let mut store = RetainedStore::new();
+    store.store(&publish("a", "1", PublishProperties::default()));
+
+    assert_eq!(store.on_subscribe(&subscription("a", 0, false, 1), false).len(), 1);
+    assert_eq!(store.on_subscribe(&subscription("a", 0, false, 1), true).len(), 1);
+    assert_eq!(store.on_subscribe(&subscription("a", 1, false, 1), false).len(), 1);
+    assert_eq!(store.on_subscribe(&subscription("a", 1, false, 1), true).len(), 0);
+    assert_eq!(store.on_subscribe(&subscription("a", 2, false, 1), false).len(), 0);
+
+    let packet = &store.on_subscribe(&subscription("a", 0, false, 1), false)[0];
+    assert_eq!(packet.config.qos, QoS::AtLeastOnce);
+    assert!(packet.config.retain);
+  }
+
+  #[test]
+  fn forward_test() {
+    let packet = publish("a", "1", PublishProperties::default());
+
+    let forwarded = forward(&packet, &subscription("a", 0, false, 1));
+    assert!(!forwarded.config.retain);
+    assert!(!forwarded.config.dup);
+    assert_eq!(forwarded.config.qos, QoS::AtLeastOnce);
+    assert_eq!(forwarded.packet_id, None);
+
+    let forwarded = forward(&packet, &subscription("a", 0, true, 2));
+    assert!(forwarded.config.retain);
+    assert_eq!(forwarded.config.qos, QoS::ExactlyOnce);
+  }
+}
@@ -0,0 +1,668 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::{Buf, BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{
+  codec::MQTTCodec,
+  types::{DecodedPacket, PublishPacket, PublishConfig, PublishProperties, PubrecPacket, PubrelPacket, ReasonCode, SubscribePacket, SubscriptionConfig, QoS},
+  utils::{decode_utf8, decode_utf8_with_length, encode_utf8, get_u8, get_u32}
+};
+use super::clock::{Clock, SystemClock};
+use super::inflight::OutgoingState;
+use super::expiry::ExpiringMessage;
+
+// a Session Expiry Interval of 0xFFFFFFFF means the session does not expire
+const NEVER_EXPIRES: u32 = u32::MAX;
+
+const FILE_EXTENSION: &str = "session";
+const QUEUE_EXTENSION: &str = "queue";
+const CORRUPT_EXTENSION: &str = "corrupt";
+
+// the state kept for a client between network connections
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StoredSession {
+  pub expiry_interval: u32,
+  pub subscriptions: Vec<SubscriptionConfig>,
+  // QoS 1 and 2 messages sent to the client and not completely acknowledged
+  pub inflight: Vec<OutgoingState>,
+  // ids of QoS 2 messages received from the client and waiting for PUBREL
+  pub received: Vec<u16>,
+  // messages that arrived while the client was offline
+  pub queued: Vec<ExpiringMessage>
+}
+
+pub trait SessionStore {
+  // stores the session of a client that disconnected, its expiry interval starts now
+  fn save(&mut self, client_id: &str, session: StoredSession) -> io::Result<()>;
+
+  // returns the session if it exists and has not expired
+  fn load(&mut self, client_id: &str) -> io::Result<Option<StoredSession>>;
+
+  fn remove(&mut self, client_id: &str) -> io::Result<()>;
+
+  // adds a message for an offline client without restarting the expiry interval,
+  // returns false if there is no live session to queue to
+  fn queue(&mut self, client_id: &str, message: ExpiringMessage) -> io::Result<bool>;
+
+  // ids of every session that has not expired
+  fn client_ids(&mut self) -> io::Result<Vec<String>>;
+
+  // removes expired sessions and returns their client ids
+  fn purge_expired(&mut self) -> io::Result<Vec<String>>;
+}
+
+fn expires_at<C: Clock>(clock: &C, expiry_interval: u32) -> Option<SystemTime> {
+  match expiry_interval {
+    NEVER_EXPIRES => None,
+    seconds => Some(clock.system_time() + Duration::from_secs(seconds as u64))
+  }
+}
+
+fn is_expired<C: Clock>(clock: &C, expires_at: Option<SystemTime>) -> bool {
+  match expires_at {
+    Some(expires_at) => expires_at <= clock.system_time(),
+    None => false
+  }
+}
+
+pub struct MemorySessionStore<C: Clock = SystemClock> {
+  clock: C,
+  sessions: HashMap<String, (StoredSession, Option<SystemTime>)>
+}
+
+impl Default for MemorySessionStore<SystemClock> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl MemorySessionStore<SystemClock> {
+  pub fn new() -> Self {
+    Self::with_clock(SystemClock)
+  }
+}
+
+impl<C: Clock> MemorySessionStore<C> {
+  pub fn with_clock(clock: C) -> Self {
+    Self {
+      clock,
+      sessions: HashMap::new()
+    }
+  }
+}
+
+impl<C: Clock> SessionStore for MemorySessionStore<C> {
+  fn save(&mut self, client_id: &str, session: StoredSession) -> io::Result<()> {
+    // a session with an expiry interval of 0 ends with the network connection
+    if session.expiry_interval == 0 {
+      self.sessions.remove(client_id);
+      return Ok(());
+    }
+
+    let expires_at = expires_at(&self.clock, session.expiry_interval);
+    self.sessions.insert(client_id.to_owned(), (session, expires_at));
+    Ok(())
+  }
+
+  fn load(&mut self, client_id: &str) -> io::Result<Option<StoredSession>> {
+    self.purge_expired()?;
+    Ok(self.sessions.get(client_id).map(|(session, _)| session.clone()))
+  }
+
+  fn remove(&mut self, client_id: &str) -> io::Result<()> {
+    self.sessions.remove(client_id);
+    Ok(())
+  }
+
+  fn queue(&mut self, client_id: &str, message: ExpiringMessage) -> io::Result<bool> {
+    self.purge_expired()?;
+    match self.sessions.get_mut(client_id) {
+      Some((session, _)) => {
+        session.queued.push(message);
+        Ok(true)
+      },
+      None => Ok(false)
+    }
+  }
+
+  fn client_ids(&mut self) -> io::Result<Vec<String>> {
+    self.purge_expired()?;
+    Ok(self.sessions.keys().cloned().collect())
+  }
+
+  fn purge_expired(&mut self) -> io::Result<Vec<String>> {
+    let expired: Vec<String> = self.sessions.iter()
+      .filter(|(_, (_, expires_at))| is_expired(&self.clock, *expires_at))
+      .map(|(client_id, _)| client_id.clone())
+      .collect();
+
+    for client_id in expired.iter() {
+      self.sessions.remove(client_id);
+    }
+    Ok(expired)
+  }
+}
+
+// keeps two files per session in a directory; the session file stores the session content as MQTT packets:
+// SUBSCRIBE for subscriptions, PUBLISH or PUBREL for in-flight messages, PUBREC for received ids,
+// and the queue file holds the messages queued while the client is offline, one appended record each
+pub struct FileSessionStore<C: Clock = SystemClock> {
+  clock: C,
+  directory: PathBuf
+}
+
+impl FileSessionStore<SystemClock> {
+  pub fn open<P: AsRef<Path>>(directory: P) -> io::Result<Self> {
+    Self::with_clock(directory, SystemClock)
+  }
+}
+
+impl<C: Clock> FileSessionStore<C> {
+  pub fn with_clock<P: AsRef<Path>>(directory: P, clock: C) -> io::Result<Self> {
+    fs::create_dir_all(directory.as_ref())?;
+    Ok(Self {
+      clock,
+      directory: directory.as_ref().to_owned()
+    })
+  }
+
+  // client ids are hex encoded so any id is a valid file name
+  fn path(&self, client_id: &str, extension: &str) -> PathBuf {
+    let name: String = client_id.bytes().map(|byte| format!("{:02x}", byte)).collect();
+    self.directory.join(format!("{}.{}", name, extension))
+  }
+
+  fn read(&self, client_id: &str) -> io::Result<Option<(StoredSession, Option<SystemTime>)>> {
+    let (mut session, expires_at) = match fs::read(self.path(client_id, FILE_EXTENSION)) {
+      Ok(content) => decode_session(&content)?,
+      Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+      Err(error) => return Err(error)
+    };
+
+    match fs::read(self.path(client_id, QUEUE_EXTENSION)) {
+      Ok(content) => session.queued = decode_queue(&content)?,
+      Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+      _ => {}
+    }
+    Ok(Some((session, expires_at)))
+  }
+
+  fn write(&self, client_id: &str, session: &StoredSession, expires_at: Option<SystemTime>) -> io::Result<()> {
+    let mut queue = BytesMut::new();
+    for message in session.queued.iter() {
+      encode_queued(&mut queue, message)?;
+    }
+    self.replace(self.path(client_id, QUEUE_EXTENSION), &queue)?;
+    self.replace(self.path(client_id, FILE_EXTENSION), &encode_session(session, expires_at)?)
+  }
+
+  fn replace(&self, path: PathBuf, content: &[u8]) -> io::Result<()> {
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, content)?;
+    fs::rename(temporary, path)
+  }
+
+  // reads the session, removing its files if it has expired
+  fn read_live(&self, client_id: &str) -> io::Result<Option<(StoredSession, Option<SystemTime>)>> {
+    match self.read(client_id)? {
+      Some((_, expires_at)) if is_expired(&self.clock, expires_at) => {
+        self.remove_files(client_id)?;
+        Ok(None)
+      },
+      session => Ok(session)
+    }
+  }
+
+  // only reads the expiry time at the start of the session file, removing the files if it has expired
+  fn is_live(&self, client_id: &str) -> io::Result<bool> {
+    let mut header = [0; 8];
+    match fs::File::open(self.path(client_id, FILE_EXTENSION)).and_then(|mut file| file.read_exact(&mut header)) {
+      Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(false),
+      result => result?
+    }
+
+    if is_expired(&self.clock, decode_time(&mut BytesMut::from(&header[..]))?) {
+      self.remove_files(client_id)?;
+      return Ok(false);
+    }
+    Ok(true)
+  }
+
+  fn remove_files(&self, client_id: &str) -> io::Result<()> {
+    for extension in [FILE_EXTENSION, QUEUE_EXTENSION] {
+      match fs::remove_file(self.path(client_id, extension)) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+        _ => {}
+      }
+    }
+    Ok(())
+  }
+
+  fn stored_ids(&self) -> io::Result<Vec<String>> {
+    let mut client_ids = Vec::new();
+    for entry in fs::read_dir(&self.directory)? {
+      let path = match entry {
+        Ok(entry) => entry.path(),
+        Err(_) => continue
+      };
+      if path.extension().and_then(|extension| extension.to_str()) != Some(FILE_EXTENSION) {
+        continue;
+      }
+
+      if let Some(client_id) = path.file_stem().and_then(|stem| stem.to_str()).and_then(decode_hex) {
+        client_ids.push(client_id);
+      }
+    }
+    Ok(client_ids)
+  }
+
+  // splits the stored sessions into live and expired client ids; a session file that cannot be
+  // read is skipped, and one with a corrupt header is renamed to keep it out of later listings
+  fn sort_ids(&self) -> io::Result<(Vec<String>, Vec<String>)> {
+    let mut live = Vec::new();
+    let mut expired = Vec::new();
+    for client_id in self.stored_ids()? {
+      match self.is_live(&client_id) {
+        Ok(true) => live.push(client_id),
+        Ok(false) => expired.push(client_id),
+        Err(error) if is_corrupt(&error) => {
+          let path = self.path(&client_id, FILE_EXTENSION);
+          let _ = fs::rename(&path, path.with_extension(CORRUPT_EXTENSION));
+        },
+        Err(_) => {}
+      }
+    }
+    Ok((live, expired))
+  }
+}
+
+fn is_corrupt(error: &io::Error) -> bool {
+  matches!(error.kind(), io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof)
+}
+
+impl<C: Clock> SessionStore for FileSessionStore<C> {
+  fn save(&mut self, client_id: &str, session: StoredSession) -> io::Result<()> {
+    if session.expiry_interval == 0 {
+      return self.remove_files(client_id);
+    }
+
+    self.write(client_id, &session, expires_at(&self.clock, session.expiry_interval))
+  }
+
+  fn load(&mut self, client_id: &str) -> io::Result<Option<StoredSession>> {
+    Ok(self.read_live(client_id)?.map(|(session, _)| session))
+  }
+
+  fn remove(&mut self, client_id: &str) -> io::Result<()> {
+    self.remove_files(client_id)
+  }
+
+  // appends to the queue file, so queueing costs the same however many messages are waiting
+  fn queue(&mut self, client_id: &str, message: ExpiringMessage) -> io::Result<bool> {
+    if !self.is_live(client_id)? {
+      return Ok(false);
+    }
+
+    let mut record = BytesMut::new();
+    encode_queued(&mut record, &message)?;
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(self.path(client_id, QUEUE_EXTENSION))?;
+    file.write_all(&record)?;
+    Ok(true)
+  }
+
+  fn client_ids(&mut self) -> io::Result<Vec<String>> {
+    Ok(self.sort_ids()?.0)
+  }
+
+  fn purge_expired(&mut self) -> io::Result<Vec<String>> {
+    Ok(self.sort_ids()?.1)
+  }
+}
+
+fn decode_hex(name: &str) -> Option<String> {
+  let bytes: Option<Vec<u8>> = name.as_bytes().chunks(2)
+    .map(|pair| match pair {
+      [high, low] => u8::from_str_radix(std::str::from_utf8(&[*high, *low]).ok()?, 16).ok(),
+      _ => None
+    })
+    .collect();
+  String::from_utf8(bytes?).ok()
+}
+
+fn invalid_data<E: std::fmt::Debug>(error: E) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", error))
+}
+
+// expiry times are milliseconds since the epoch, 0 for no expiry
+fn encode_time(buffer: &mut BytesMut, time: Option<SystemTime>) -> io::Result<()> {
+  let millis = match time {
+    Some(time) => time.duration_since(UNIX_EPOCH).map_err(invalid_data)?.as_millis() as u64,
+    None => 0
+  };
+  buffer.put_u64(millis);
+  Ok(())
+}
+
+fn decode_time(buffer: &mut BytesMut) -> io::Result<Option<SystemTime>> {
+  if buffer.remaining() < 8 {
+    return Err(io::ErrorKind::UnexpectedEof.into());
+  }
+
+  Ok(match buffer.get_u64() {
+    0 => None,
+    millis => Some(UNIX_EPOCH + Duration::from_millis(millis))
+  })
+}
+
+// the session expiry time and interval, followed by each section as a count and its packets
+fn encode_session(session: &StoredSession, expires_at: Option<SystemTime>) -> io::Result<BytesMut> {
+  let mut buffer = BytesMut::new();
+  encode_time(&mut buffer, expires_at)?;
+  buffer.put_u32(session.expiry_interval);
+
+  let subscriptions = session.subscriptions.iter().map(|subscription| DecodedPacket::Subscribe(SubscribePacket {
+    packet_id: 1,
+    subscriptions: vec![subscription.clone()],
+    properties: Default::default()
+  }));
+  let inflight = session.inflight.iter().map(|state| match state {
+    OutgoingState::Publish(packet) => DecodedPacket::Publish(packet.clone()),
+    OutgoingState::Release(packet_id) => DecodedPacket::Pubrel(PubrelPacket {
+      packet_id: *packet_id,
+      reason_code: ReasonCode::Success,
+      properties: Default::default()
+    })
+  });
+  let received = session.received.iter().map(|packet_id| DecodedPacket::Pubrec(PubrecPacket {
+    packet_id: *packet_id,
+    reason_code: ReasonCode::Success,
+    properties: Default::default()
+  }));
+
+  encode_section(&mut buffer, subscriptions.collect())?;
+  encode_section(&mut buffer, inflight.collect())?;
+  encode_section(&mut buffer, received.collect())?;
+  Ok(buffer)
+}
+
+// queued messages get their packet id on delivery, so they are not stored as PUBLISH packets but as
+// their expiry time, the PUBLISH header flags, the topic, the properties and the payload with a 4 byte length
+fn encode_queued(buffer: &mut BytesMut, message: &ExpiringMessage) -> io::Result<()> {
+  let packet = &message.packet;
+  encode_time(buffer, message.expires_at)?;
+  buffer.put_u8(((packet.config.dup as u8) << 3) | ((packet.config.qos as u8) << 1) | packet.config.retain as u8);
+  encode_utf8(buffer, &packet.topic).map_err(invalid_data)?;
+  packet.properties.encode(buffer).map_err(invalid_data)?;
+  buffer.put_u32(packet.payload.len() as u32);
+  buffer.put_slice(packet.payload.as_bytes());
+  Ok(())
+}
+
+fn decode_queue(content: &[u8]) -> io::Result<Vec<ExpiringMessage>> {
+  let mut buffer = BytesMut::from(content);
+  let mut queued = Vec::new();
+  while buffer.has_remaining() {
+    queued.push(decode_queued(&mut buffer)?);
+  }
+  Ok(queued)
+}
+
+fn decode_queued(buffer: &mut BytesMut) -> io::Result<ExpiringMessage> {
+  let expires_at = decode_time(buffer)?;
+  let flags = get_u8(buffer).map_err(invalid_data)?;
+  let config = PublishConfig {
+    dup: flags & 0b1000 != 0,
+    qos: QoS::try_from((flags >> 1) & 0b11).map_err(invalid_data)?,
+    retain: flags & 0b1 != 0
+  };
+  let topic = decode_utf8(buffer).map_err(invalid_data)?;
+  let properties = PublishProperties::decode(buffer).map_err(invalid_data)?;
+  let payload_length = get_u32(buffer).map_err(invalid_data)?;
+  let payload = decode_utf8_with_length(buffer, payload_length as usize).map_err(invalid_data)?;
+  Ok(ExpiringMessage {
+    packet: PublishPacket { topic, packet_id: None, payload, config, properties },
+    expires_at
+  })
+}
+
+fn encode_section(buffer: &mut BytesMut, packets: Vec<DecodedPacket>) -> io::Result<()> {
+  buffer.put_u32(packets.len() as u32);
+  for packet in packets {
+    MQTTCodec::strict().encode(packet, buffer).map_err(invalid_data)?;
+  }
+  Ok(())
+}
+
+fn decode_session(content: &[u8]) -> io::Result<(StoredSession, Option<SystemTime>)> {
+  let mut buffer = BytesMut::from(content);
+  let expires_at = decode_time(&mut buffer)?;
+  let mut session = StoredSession {
+    expiry_interval: decode_count(&mut buffer)?,
+    ..StoredSession::default()
+  };
+
+  for packet in decode_section(&mut buffer)? {
+    match packet {
+      DecodedPacket::Subscribe(packet) => session.subscriptions.extend(packet.subscriptions),
+      packet => return Err(invalid_data(packet))
+    }
+  }
+  for packet in decode_section(&mut buffer)? {
+    session.inflight.push(match packet {
+      DecodedPacket::Publish(packet) => OutgoingState::Publish(packet),
+      DecodedPacket::Pubrel(packet) => OutgoingState::Release(packet.packet_id),
+      packet => return Err(invalid_data(packet))
+    });
+  }
+  for packet in decode_section(&mut buffer)? {
+    match packet {
+      DecodedPacket::Pubrec(packet) => session.received.push(packet.packet_id),
+      packet => return Err(invalid_data(packet))
+    }
+  }
+  if buffer.has_remaining() {
+    return Err(invalid_data("bytes after the last section"));
+  }
+  Ok((session, expires_at))
+}
+
+fn decode_count(buffer: &mut BytesMut) -> io::Result<u32> {
+  match buffer.remaining() {
+    0..=3 => Err(io::ErrorKind::UnexpectedEof.into()),
+    _ => Ok(buffer.get_u32())
+  }
+}
+
+fn decode_packet(buffer: &mut BytesMut) -> io::Result<DecodedPacket> {
+  let mut codec = MQTTCodec::strict();
+  match codec.decode(buffer).map_err(invalid_data)? {
+    Some(packet) => Ok(packet),
+    None => Err(io::ErrorKind::UnexpectedEof.into())
+  }
+}
+
+fn decode_section(buffer: &mut BytesMut) -> io::Result<Vec<DecodedPacket>> {
+  (0..decode_count(buffer)?).map(|_| decode_packet(buffer)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::types::RetainHandling;
+  use crate::session::{publish, ManualClock};
+  use super::*;
+
+  fn session(expiry_interval: u32) -> StoredSession {
+    StoredSession {
+      expiry_interval,
+      subscriptions: vec![SubscriptionConfig {
+        topic: "a/+".to_owned(),
+        retain_handling: RetainHandling::SendOnNewSubscribe,
+        rap: true,
+        nl: false,
+        qos: QoS::ExactlyOnce
+      }],
+      inflight: vec![
+        OutgoingState::Publish(publish(Some(1), 1)),
+        OutgoingState::Release(2)
+      ],
+      received: vec![3],
+      queued: vec![
+        ExpiringMessage { packet: publish(None, 0), expires_at: None },
+        ExpiringMessage { packet: publish(None, 2), expires_at: Some(UNIX_EPOCH + Duration::from_millis(1_700_000_000_123)) }
+      ]
+    }
+  }
+
+  fn temporary_directory(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("mqtt-codec-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    directory
+  }
+
+  fn store_test<S: SessionStore>(mut store: S, clock: ManualClock) {
+    store.save("client", session(10)).unwrap();
+    assert_eq!(store.load("client").unwrap(), Some(session(10)));
+    assert_eq!(store.client_ids().unwrap(), vec!["client"]);
+
+    let message = ExpiringMessage::new(publish(None, 1), &clock);
+    assert!(store.queue("client", message.clone()).unwrap());
+    assert!(!store.queue("other", message).unwrap());
+    assert_eq!(store.load("client").unwrap().unwrap().queued.len(), 3);
+
+    // queueing does not restart the expiry interval
+    clock.advance(Duration::from_secs(9));
+    assert!(store.load("client").unwrap().is_some());
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(store.load("client").unwrap(), None);
+    assert!(store.client_ids().unwrap().is_empty());
+
+    store.save("client", session(0)).unwrap();
+    assert_eq!(store.load("client").unwrap(), None);
+
+    store.save("forever", session(NEVER_EXPIRES)).unwrap();
+    store.save("expiring", session(5)).unwrap();
+    clock.advance(Duration::from_secs(u32::MAX as u64));
+    assert_eq!(store.purge_expired().unwrap(), vec!["expiring"]);
+    assert!(store.load("forever").unwrap().is_some());
+
+    store.remove("forever").unwrap();
+    assert_eq!(store.load("forever").unwrap(), None);
+  }
+
+  #[test]
+  fn memory_store_test() {
+    let clock = ManualClock::new();
+    store_test(MemorySessionStore::with_clock(clock.clone()), clock);
+  }
+
+  #[test]
+  fn file_store_test() {
+    let directory = temporary_directory("store");
+    let clock = ManualClock::new();
+    store_test(FileSessionStore::with_clock(&directory, clock.clone()).unwrap(), clock);
+    fs::remove_dir_all(directory).unwrap();
+  }
+
+  #[test]
+  fn file_store_reopen_test() {
+    let directory = temporary_directory("reopen");
+    FileSessionStore::open(&directory).unwrap().save("client/ü", session(60)).unwrap();
+
+    let mut store = FileSessionStore::open(&directory).unwrap();
+    assert_eq!(store.client_ids().unwrap(), vec!["client/ü"]);
+    assert_eq!(store.load("client/ü").unwrap(), Some(session(60)));
+    fs::remove_dir_all(directory).unwrap();
+  }
+
+  #[test]
+  fn queued_record_test() {
+    let mut packet = publish(None, 2);
+    packet.config.retain = true;
+    packet.properties.content_type = Some("text/plain".to_owned());
+    packet.payload = "a".repeat(70000);
+    let message = ExpiringMessage { packet, expires_at: Some(UNIX_EPOCH + Duration::from_secs(60)) };
+
+    let mut buffer = BytesMut::new();
+    encode_queued(&mut buffer, &message).unwrap();
+    // the expiry time is followed by the QoS and RETAIN flags, with no packet id anywhere
+    assert_eq!(buffer[8], 0b0101);
+    assert_eq!(decode_queued(&mut buffer).unwrap(), message);
+    assert!(buffer.is_empty());
+  }
+
+  #[test]
+  fn decode_invalid_test() {
+    assert_eq!(decode_session(&[0; 4]).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    assert_eq!(decode_session(&[0; 12]).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+  }
+
+  #[test]
+  fn file_store_corrupt_test() {
+    let directory = temporary_directory("corrupt");
+    let mut store = FileSessionStore::open(&directory).unwrap();
+    store.save("client", session(60)).unwrap();
+    let path = store.path("client", FILE_EXTENSION);
+    let content = fs::read(&path).unwrap();
+
+    // a truncated file, whether cut between packets or in the middle of one
+    for length in 0..content.len() {
+      fs::write(&path, &content[..length]).unwrap();
+      assert!(store.load("client").is_err(), "{} bytes", length);
+    }
+
+    // trailing bytes, and a PUBACK cut short where the subscriptions should be
+    fs::write(&path, [&content[..], &[0x00]].concat()).unwrap();
+    assert_eq!(store.load("client").unwrap_err().kind(), io::ErrorKind::InvalidData);
+    let mut corrupt = content[..12].to_vec();
+    corrupt.extend_from_slice(&[0x00, 0x00, 0x00, 0x01, 0x40, 0x01, 0x00]);
+    fs::write(&path, corrupt).unwrap();
+    assert_eq!(store.load("client").unwrap_err().kind(), io::ErrorKind::InvalidData);
+    // listing the sessions only reads the expiry time at the start of each file
+    assert_eq!(store.client_ids().unwrap(), vec!["client"]);
+
+    // a bad file does not stop the other sessions from being listed: a cut short expiry time is
+    // moved aside, and a file that cannot be read is skipped
+    store.save("other", session(60)).unwrap();
+    fs::write(&path, &content[..4]).unwrap();
+    let unreadable = store.path("unreadable", FILE_EXTENSION);
+    fs::create_dir(&unreadable).unwrap();
+    assert_eq!(store.client_ids().unwrap(), vec!["other"]);
+    assert_eq!(store.purge_expired().unwrap(), Vec::<String>::new());
+    assert!(!path.exists());
+    assert_eq!(fs::read(path.with_extension(CORRUPT_EXTENSION)).unwrap(), &content[..4]);
+    assert!(unreadable.exists());
+    fs::remove_dir_all(directory).unwrap();
+  }
+
+  #[test]
+  fn file_store_queue_test() {
+    let directory = temporary_directory("queue");
+    let mut store = FileSessionStore::open(&directory).unwrap();
+    store.save("client", session(60)).unwrap();
+    let content = fs::read(store.path("client", FILE_EXTENSION)).unwrap();
+
+    // a queued message is appended without touching the session file
+    let message = ExpiringMessage { packet: publish(None, 1), expires_at: None };
+    assert!(store.queue("client", message.clone()).unwrap());
+    assert_eq!(fs::read(store.path("client", FILE_EXTENSION)).unwrap(), content);
+    let mut expected = session(60);
+    expected.queued.push(message);
+    assert_eq!(store.load("client").unwrap(), Some(expected));
+
+    // a record cut short at the end of the queue file
+    let queue = store.path("client", QUEUE_EXTENSION);
+    let mut file = fs::OpenOptions::new().append(true).open(&queue).unwrap();
+    file.write_all(&[0x00]).unwrap();
+    assert_eq!(store.load("client").unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+
+    store.remove("client").unwrap();
+    assert!(!queue.exists());
+    fs::remove_dir_all(directory).unwrap();
+  }
+}
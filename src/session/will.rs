@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::types::{
+  ConnectPacket, DisconnectPacket, PublishConfig, PublishPacket, ReasonCode, WillConfig
+};
+use super::clock::{Clock, SystemClock};
+use super::expiry::ExpiringMessage;
+
+struct PendingWill {
+  // its Message Expiry Interval runs from the disconnect, so the delay counts against it
+  message: ExpiringMessage,
+  publish_at: Instant
+}
+
+pub struct WillScheduler<C: Clock = SystemClock> {
+  clock: C,
+  registered: HashMap<String, WillConfig>,
+  pending: HashMap<String, PendingWill>
+}
+
+impl Default for WillScheduler<SystemClock> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl WillScheduler<SystemClock> {
+  pub fn new() -> Self {
+    Self::with_clock(SystemClock)
+  }
+}
+
+impl<C: Clock> WillScheduler<C> {
+  pub fn with_clock(clock: C) -> Self {
+    Self {
+      clock,
+      registered: HashMap::new(),
+      pending: HashMap::new()
+    }
+  }
+
+  // registers the will of a new connection; returns the will of an earlier connection that has
+  // to be published now: one without a delay when that connection is taken over [MQTT-3.1.4-3],
+  // or any will when the new connection starts clean and ends the session. A will waiting for its
+  // delay to pass is cancelled by a connection that resumes the session [MQTT-3.1.3-9]
+  pub fn connect(&mut self, client_id: &str, packet: &ConnectPacket) -> Vec<PublishPacket> {
+    let mut due = Vec::new();
+
+    if let Some(will) = self.registered.remove(client_id) {
+      if packet.clean_start || will.properties.will_delay_interval.unwrap_or(0) == 0 {
+        due.push(will_publish(&will));
+      }
+    }
+    if let Some(pending) = self.pending.remove(client_id) {
+      if packet.clean_start {
+        due.extend(pending.message.deliver(&self.clock));
+      }
+    }
+
+    if let Some(will) = &packet.will_config {
+      self.registered.insert(client_id.to_owned(), will.clone());
+    }
+    due
+  }
+
+  // called when a connection ends, with the DISCONNECT the client sent if there was one
+  pub fn disconnect(&mut self, client_id: &str, packet: Option<&DisconnectPacket>, session_expiry_interval: u32) {
+    let will = match self.registered.remove(client_id) {
+      Some(will) => will,
+      None => return
+    };
+
+    if let Some(packet) = packet {
+      if packet.reason_code != ReasonCode::DisconnectWithWill {
+        return;
+      }
+    }
+
+    // the will is published when the delay passes or the session ends, whichever comes first
+    let delay = will.properties.will_delay_interval.unwrap_or(0).min(session_expiry_interval);
+
+    self.pending.insert(client_id.to_owned(), PendingWill {
+      message: ExpiringMessage::new(will_publish(&will), &self.clock),
+      publish_at: self.clock.now() + Duration::from_secs(delay as u64)
+    });
+  }
+
+  pub fn cancel(&mut self, client_id: &str) {
+    self.registered.remove(client_id);
+    self.pending.remove(client_id);
+  }
+
+  // removes and returns every will whose delay has passed, dropping those that expired meanwhile
+  pub fn poll(&mut self) -> Vec<PublishPacket> {
+    let now = self.clock.now();
+    let due: Vec<String> = self.pending.iter()
+      .filter(|(_, pending)| pending.publish_at <= now)
+      .map(|(client_id, _)| client_id.clone())
+      .collect();
+
+    let (pending, clock) = (&mut self.pending, &self.clock);
+    due.into_iter()
+      .filter_map(|client_id| pending.remove(&client_id))
+      .filter_map(|pending| pending.message.deliver(clock))
+      .collect()
+  }
+
+  pub fn next_deadline(&self) -> Option<Instant> {
+    self.pending.values().map(|pending| pending.publish_at).min()
+  }
+
+  pub fn is_pending(&self, client_id: &str) -> bool {
+    self.pending.contains_key(client_id)
+  }
+}
+
+// the publish sent for a will; the Will Delay Interval is not forwarded
+pub fn will_publish(will: &WillConfig) -> PublishPacket {
+  PublishPacket {
+    topic: will.topic.clone(),
+    packet_id: None,
+    payload: will.payload.clone(),
+    config: PublishConfig {
+      dup: false,
+      qos: will.qos,
+      retain: will.retain
+    },
+    properties: will.properties.clone().into()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::types::{PublishProperties, WillProperties, QoS};
+  use crate::session::ManualClock;
+  use super::*;
+
+  fn connect_packet(clean_start: bool, will_delay: Option<u32>) -> ConnectPacket {
+    let properties = WillProperties {
+      will_delay_interval: will_delay,
+      content_type: Some("text/plain".to_owned()),
+      ..Default::default()
+    };
+
+    ConnectPacket {
+      client_id: "client".to_owned(),
+      clean_start,
+      will_config: Some(WillConfig {
+        topic: "status".to_owned(),
+        payload: "offline".to_owned(),
+        retain: true,
+        qos: QoS::AtLeastOnce,
+        properties
+      }),
+      keep_alive: 0,
+      username: None,
+      password: None,
+      properties: Default::default()
+    }
+  }
+
+  fn disconnect_packet(reason_code: ReasonCode) -> DisconnectPacket {
+    DisconnectPacket {
+      reason_code,
+      properties: Default::default()
+    }
+  }
+
+  #[test]
+  fn will_publish_test() {
+    let packet = will_publish(connect_packet(true, Some(5)).will_config.as_ref().unwrap());
+    assert_eq!(packet.topic, "status");
+    assert_eq!(packet.payload, "offline");
+    assert_eq!(packet.config, PublishConfig { dup: false, qos: QoS::AtLeastOnce, retain: true });
+    assert_eq!(packet.properties, PublishProperties {
+      content_type: Some("text/plain".to_owned()),
+      ..Default::default()
+    });
+  }
+
+  #[test]
+  fn connection_lost_test() {
+    let mut wills = WillScheduler::with_clock(ManualClock::new());
+    wills.connect("client", &connect_packet(true, None));
+    assert!(wills.poll().is_empty());
+
+    wills.disconnect("client", None, 0);
+    assert_eq!(wills.poll().len(), 1);
+    assert!(wills.poll().is_empty());
+  }
+
+  #[test]
+  fn normal_disconnect_test() {
+    let mut wills = WillScheduler::with_clock(ManualClock::new());
+    wills.connect("client", &connect_packet(true, None));
+    wills.disconnect("client", Some(&disconnect_packet(ReasonCode::Success)), 0);
+    assert!(!wills.is_pending("client"));
+  }
+
+  #[test]
+  fn disconnect_with_will_test() {
+    let mut wills = WillScheduler::with_clock(ManualClock::new());
+    wills.connect("client", &connect_packet(true, None));
+    wills.disconnect("client", Some(&disconnect_packet(ReasonCode::DisconnectWithWill)), 0);
+    assert_eq!(wills.poll().len(), 1);
+  }
+
+  #[test]
+  fn will_delay_test() {
+    let clock = ManualClock::new();
+    let mut wills = WillScheduler::with_clock(clock.clone());
+    wills.connect("client", &connect_packet(true, Some(10)));
+    wills.disconnect("client", None, 60);
+    assert_eq!(wills.next_deadline(), Some(clock.now() + Duration::from_secs(10)));
+
+    clock.advance(Duration::from_secs(9));
+    assert!(wills.poll().is_empty());
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(wills.poll().len(), 1);
+  }
+
+  #[test]
+  fn will_delay_capped_by_session_expiry_test() {
+    let clock = ManualClock::new();
+    let mut wills = WillScheduler::with_clock(clock.clone());
+    wills.connect("client", &connect_packet(true, Some(10)));
+    wills.disconnect("client", None, 3);
+
+    clock.advance(Duration::from_secs(3));
+    assert_eq!(wills.poll().len(), 1);
+  }
+
+  #[test]
+  fn reconnect_cancels_test() {
+    let clock = ManualClock::new();
+    let mut wills = WillScheduler::with_clock(clock.clone());
+    wills.connect("client", &connect_packet(true, Some(10)));
+    wills.disconnect("client", None, 60);
+
+    clock.advance(Duration::from_secs(5));
+    assert!(wills.connect("client", &connect_packet(false, Some(10))).is_empty());
+    assert!(!wills.is_pending("client"));
+
+    clock.advance(Duration::from_secs(10));
+    assert!(wills.poll().is_empty());
+  }
+
+  #[test]
+  fn will_delay_expiry_test() {
+    let clock = ManualClock::new();
+    let mut wills = WillScheduler::with_clock(clock.clone());
+    let mut packet = connect_packet(true, Some(10));
+    packet.will_config.as_mut().unwrap().properties.message_expiry_interval = Some(30);
+    wills.connect("client", &packet);
+    wills.disconnect("client", None, 60);
+
+    clock.advance(Duration::from_secs(10));
+    let due = wills.poll();
+    assert_eq!(due[0].properties.message_expiry_interval, Some(20));
+
+    // a will that expires before its delay passes is never published
+    packet.will_config.as_mut().unwrap().properties.message_expiry_interval = Some(5);
+    wills.connect("client", &packet);
+    wills.disconnect("client", None, 60);
+    clock.advance(Duration::from_secs(10));
+    assert!(wills.poll().is_empty());
+  }
+
+  #[test]
+  fn takeover_test() {
+    let mut wills = WillScheduler::with_clock(ManualClock::new());
+
+    // the connection taken over had no will delay, so its will goes out either way
+    wills.connect("client", &connect_packet(false, None));
+    assert_eq!(wills.connect("client", &connect_packet(false, None)).len(), 1);
+    assert_eq!(wills.connect("client", &connect_packet(true, Some(10))).len(), 1);
+
+    // with a delay, the session goes on unless the new connection starts clean
+    assert!(wills.connect("client", &connect_packet(false, Some(10))).is_empty());
+    assert_eq!(wills.connect("client", &connect_packet(true, None)).len(), 1);
+    assert!(!wills.is_pending("client"));
+  }
+
+  #[test]
+  fn clean_start_ends_session_test() {
+    let mut wills = WillScheduler::with_clock(ManualClock::new());
+    wills.connect("client", &connect_packet(true, Some(10)));
+    wills.disconnect("client", None, 60);
+
+    assert_eq!(wills.connect("client", &connect_packet(true, Some(10))).len(), 1);
+    assert!(!wills.is_pending("client"));
+  }
+}
@@ -2,24 +2,25 @@ use bytes::{BytesMut, Buf, BufMut};
 
 use crate::{
   error::{EncodeError, DecodeError},
-  types::DecodedPacket,
-  property::Property,
+  types::{DecodedPacket, PacketType},
+  properties::AckProperties,
   reason_code::ReasonCode,
-  utils::get_remaining_length
+  utils::{get_remaining_length, get_u16}
 };
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SubackPacket {
   pub packet_id: u16,
   pub reason_codes: Vec<ReasonCode>,
-  pub properties: Vec<Property>
+  pub properties: AckProperties
 }
 
 impl super::types::Encode for SubackPacket {
   fn encode(&self, buffer: &mut BytesMut) -> Result<(), EncodeError> {
     buffer.put_u16(self.packet_id);
     
-    Property::encode(buffer, &self.properties)?;
+    self.properties.encode(buffer)?;
 
     for reason_code in &self.reason_codes {
       buffer.put_u8(*reason_code as u8);
@@ -32,19 +33,19 @@ impl super::types::Encode for SubackPacket {
 impl SubackPacket {
   pub fn decode(buffer: &mut BytesMut, remaining_length: usize) -> Result<DecodedPacket, DecodeError> {
     let starting_length = buffer.remaining();
-    let packet_id = buffer.get_u16();
+    let packet_id = get_u16(buffer)?;
 
-    let properties = Property::decode(buffer)?;
+    let properties = AckProperties::decode(buffer)?;
 
     let mut reason_codes = Vec::new();
-    while get_remaining_length(&buffer, starting_length, remaining_length) > 0 {
-      reason_codes.push(ReasonCode::decode(buffer)?);
+    while get_remaining_length(buffer, starting_length, remaining_length) > 0 {
+      reason_codes.push(ReasonCode::decode(buffer, PacketType::SUBACK)?);
     }
 
     let packet = SubackPacket {
-      packet_id: packet_id,
-      reason_codes: reason_codes,
-      properties: properties
+      packet_id,
+      reason_codes,
+      properties
     };
 
     Ok(DecodedPacket::Suback(packet))
@@ -65,7 +66,7 @@ mod tests {
     let packet = SubackPacket {
       packet_id: 32,
       reason_codes: vec![ReasonCode::Success],
-      properties: vec![]
+      properties: AckProperties::default()
     };
 
     let packet2 = packet.clone();
@@ -1,36 +1,40 @@
+use std::convert::TryFrom;
+
 use bytes::{BytesMut, Buf, BufMut};
 
 use crate::{
   error::{EncodeError, DecodeError},
-  types::DecodedPacket,
-  property::Property,
-  utils::{decode_utf8, encode_utf8, get_remaining_length}
+  types::{DecodedPacket, QoS, RetainHandling},
+  properties::SubscribeProperties,
+  utils::{decode_utf8, encode_utf8, get_remaining_length, get_u8, get_u16}
 };
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SubscribePacket {
   pub packet_id: u16,
   pub subscriptions: Vec<SubscriptionConfig>,
-  pub properties: Vec<Property>
+  pub properties: SubscribeProperties
 }
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SubscriptionConfig {
   pub topic: String,
-  pub retain_handling: u8,
+  pub retain_handling: RetainHandling,
   pub rap: bool,
   pub nl: bool,
-  pub qos: u8
+  pub qos: QoS
 }
 
 impl super::types::Encode for SubscribePacket {
   fn encode(&self, buffer: &mut BytesMut) -> Result<(), EncodeError> {
     buffer.put_u16(self.packet_id);
 
-    Property::encode(buffer, &self.properties)?;
+    self.properties.encode(buffer)?;
 
     for subscription in &self.subscriptions {
-      encode_utf8(buffer, &subscription.topic);
+      encode_utf8(buffer, &subscription.topic)?;
       Self::encode_subscription_options(subscription, buffer);
     }
 
@@ -41,7 +45,7 @@ impl super::types::Encode for SubscribePacket {
 impl SubscribePacket {
   fn encode_subscription_options(subscription: &SubscriptionConfig, buffer: &mut BytesMut) {
     let mut subscription_options = 0;
-    subscription_options += subscription.retain_handling << 4;
+    subscription_options += (subscription.retain_handling as u8) << 4;
     
     if subscription.rap {
       subscription_options += 0b1000;
@@ -51,34 +55,34 @@ impl SubscribePacket {
       subscription_options += 0b100
     }
 
-    subscription_options += subscription.qos;
+    subscription_options += subscription.qos as u8;
     buffer.put_u8(subscription_options);
   }
 
-  fn decode_subscription_options(subscription_options: u8) -> Result<(u8, bool, bool, u8), DecodeError> {
+  fn decode_subscription_options(subscription_options: u8) -> Result<(RetainHandling, bool, bool, QoS), DecodeError> {
     if (subscription_options & 0b11000000) >> 6 != 0 {
-      return Err(DecodeError::FormatError);
+      return Err(DecodeError::InvalidSubscriptionOptions(subscription_options));
     }
 
-    let retain_handling = (subscription_options & 0b110000) >> 4;
+    let retain_handling = RetainHandling::try_from((subscription_options & 0b110000) >> 4)?;
     let rap = ((subscription_options & 0b1000) >> 3) == 1;
     let nl = ((subscription_options & 0b100) >> 2) == 1;
-    let qos = subscription_options & 0b11;
+    let qos = QoS::try_from(subscription_options & 0b11)?;
 
     Ok((retain_handling, rap, nl, qos))
   }
 
   pub fn decode(buffer: &mut BytesMut, remaining_length: usize) -> Result<DecodedPacket, DecodeError> {
     let starting_length = buffer.remaining();
-    let packet_id = buffer.get_u16();
+    let packet_id = get_u16(buffer)?;
 
-    let properties = Property::decode(buffer)?;
+    let properties = SubscribeProperties::decode(buffer)?;
 
     let mut subscriptions = Vec::new();
 
-    while get_remaining_length(&buffer, starting_length, remaining_length) > 0 {
+    while get_remaining_length(buffer, starting_length, remaining_length) > 0 {
       let topic = decode_utf8(buffer)?;
-      let (retain_handling, rap, nl, qos) = SubscribePacket::decode_subscription_options(buffer.get_u8())?;
+      let (retain_handling, rap, nl, qos) = SubscribePacket::decode_subscription_options(get_u8(buffer)?)?;
       subscriptions.push(SubscriptionConfig{
         topic,
         retain_handling,
@@ -89,9 +93,9 @@ impl SubscribePacket {
     }
 
     let packet = SubscribePacket {
-      packet_id: packet_id,
-      subscriptions: subscriptions,
-      properties:properties
+      packet_id,
+      subscriptions,
+      properties
     };
 
     Ok(DecodedPacket::Subscribe(packet))
@@ -108,16 +112,16 @@ mod tests {
   fn codec_test() {
     let subscription = SubscriptionConfig {
       topic: "test".to_owned(),
-      retain_handling: 1,
+      retain_handling: RetainHandling::SendOnNewSubscribe,
       rap: false,
       nl: false,
-      qos: 1
+      qos: QoS::AtLeastOnce
     };
 
     let packet = SubscribePacket {
       packet_id: 32,
       subscriptions: vec![subscription],
-      properties: vec![]
+      properties: SubscribeProperties::default()
     };
 
     let packet2 = packet.clone();
@@ -129,4 +133,12 @@ mod tests {
 
     assert_eq!(DecodedPacket::Subscribe(packet2), packet);
   }
+
+  #[test]
+  fn decode_subscription_options_test() {
+    assert_eq!(SubscribePacket::decode_subscription_options(0b101110).unwrap(),
+      (RetainHandling::DoNotSend, true, true, QoS::ExactlyOnce));
+    assert!(matches!(SubscribePacket::decode_subscription_options(0b000011), Err(DecodeError::InvalidQoS(3))));
+    assert!(matches!(SubscribePacket::decode_subscription_options(0b110000), Err(DecodeError::InvalidRetainHandling(3))));
+  }
 }
\ No newline at end of file
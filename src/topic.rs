@@ -0,0 +1,82 @@
+pub fn is_valid_name(topic: &str) -> bool {
+  !topic.is_empty() && !topic.contains(['+', '#'])
+}
+
+pub fn is_valid_filter(filter: &str) -> bool {
+  if filter.is_empty() {
+    return false;
+  }
+
+  let levels: Vec<&str> = filter.split('/').collect();
+  levels.iter().enumerate().all(|(index, level)| match *level {
+    "+" => true,
+    "#" => index == levels.len() - 1,
+    level => !level.contains(['+', '#'])
+  })
+}
+
+// topics starting with '$' are not matched by filters starting with a wildcard
+pub fn matches(filter: &str, topic: &str) -> bool {
+  if topic.starts_with('$') && (filter.starts_with('+') || filter.starts_with('#')) {
+    return false;
+  }
+
+  let mut filter_levels = filter.split('/');
+  let mut topic_levels = topic.split('/');
+
+  loop {
+    match (filter_levels.next(), topic_levels.next()) {
+      (Some("#"), _) => return true,
+      (Some("+"), Some(_)) => {},
+      (Some(filter_level), Some(topic_level)) if filter_level == topic_level => {},
+      (None, None) => return true,
+      _ => return false
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn is_valid_name_test() {
+    assert!(is_valid_name("a/b"));
+    assert!(is_valid_name("/"));
+    assert!(!is_valid_name(""));
+    assert!(!is_valid_name("a/+"));
+    assert!(!is_valid_name("a/#"));
+  }
+
+  #[test]
+  fn is_valid_filter_test() {
+    assert!(is_valid_filter("a/b"));
+    assert!(is_valid_filter("#"));
+    assert!(is_valid_filter("+/b/#"));
+    assert!(!is_valid_filter(""));
+    assert!(!is_valid_filter("a/#/b"));
+    assert!(!is_valid_filter("a+/b"));
+    assert!(!is_valid_filter("a/b#"));
+  }
+
+  #[test]
+  fn matches_test() {
+    assert!(matches("a/b", "a/b"));
+    assert!(!matches("a/b", "a/c"));
+    assert!(matches("a/+", "a/b"));
+    assert!(!matches("a/+", "a/b/c"));
+    assert!(matches("a/+/c", "a/b/c"));
+    assert!(matches("a/#", "a"));
+    assert!(matches("a/#", "a/b/c"));
+    assert!(matches("#", "a/b"));
+    assert!(matches("+/+", "/b"));
+    assert!(!matches("a/b/c", "a/b"));
+  }
+
+  #[test]
+  fn matches_system_topic_test() {
+    assert!(!matches("#", "$SYS/uptime"));
+    assert!(!matches("+/uptime", "$SYS/uptime"));
+    assert!(matches("$SYS/#", "$SYS/uptime"));
+  }
+}
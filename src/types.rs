@@ -1,12 +1,17 @@
+use std::convert::TryFrom;
+
 use bytes::BytesMut;
 
-use crate::error::EncodeError;
+use crate::error::{EncodeError, ConversionError};
 
 // import all the types
 pub use crate::connect::{ConnectPacket, WillConfig};
 pub use crate::connack::ConnackPacket;
 pub use crate::publish::{PublishPacket, PublishConfig};
 pub use crate::puback::PubackPacket;
+pub use crate::pubrec::PubrecPacket;
+pub use crate::pubrel::PubrelPacket;
+pub use crate::pubcomp::PubcompPacket;
 pub use crate::subscribe::{SubscribePacket, SubscriptionConfig};
 pub use crate::suback::SubackPacket;
 pub use crate::unsubscribe::UnsubscribePacket;
@@ -15,19 +20,29 @@ pub use crate::pingreq::PingReqPacket;
 pub use crate::pingresp::PingRespPacket;
 pub use crate::disconnect::DisconnectPacket;
 
-pub use crate::property::Property;
+pub use crate::property::{Property, Utf8Pair};
+pub use crate::properties::{
+  ConnectProperties, WillProperties, ConnackProperties, PublishProperties, AckProperties, SubscribeProperties,
+  UnsubscribeProperties, DisconnectProperties
+};
 pub use crate::reason_code::ReasonCode;
+pub use crate::qos::{QoS, RetainHandling};
+pub use crate::limits::{Limits, Trimmed};
 
 pub trait Encode {
   fn encode(&self, buffer: &mut BytesMut) -> Result<(), EncodeError>;
 }
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DecodedPacket {
   Connect(ConnectPacket),
   Connack(ConnackPacket),
   Publish(PublishPacket),
   Puback(PubackPacket),
+  Pubrec(PubrecPacket),
+  Pubrel(PubrelPacket),
+  Pubcomp(PubcompPacket),
   Subscribe(SubscribePacket),
   Suback(SubackPacket),
   Unsubscribe(UnsubscribePacket),
@@ -44,6 +59,9 @@ impl DecodedPacket {
       DecodedPacket::Connack(item) => item.encode(buffer)?,
       DecodedPacket::Publish(item) => item.encode(buffer)?,
       DecodedPacket::Puback(item) => item.encode(buffer)?,
+      DecodedPacket::Pubrec(item) => item.encode(buffer)?,
+      DecodedPacket::Pubrel(item) => item.encode(buffer)?,
+      DecodedPacket::Pubcomp(item) => item.encode(buffer)?,
       DecodedPacket::Subscribe(item) => item.encode(buffer)?,
       DecodedPacket::Suback(item) => item.encode(buffer)?,
       DecodedPacket::Unsubscribe(item) => item.encode(buffer)?,
@@ -61,6 +79,9 @@ impl DecodedPacket {
       DecodedPacket::Connack(_) => PacketType::CONNACK,
       DecodedPacket::Publish(_) => PacketType::PUBLISH,
       DecodedPacket::Puback(_) => PacketType::PUBACK,
+      DecodedPacket::Pubrec(_) => PacketType::PUBREC,
+      DecodedPacket::Pubrel(_) => PacketType::PUBREL,
+      DecodedPacket::Pubcomp(_) => PacketType::PUBCOMP,
       DecodedPacket::Subscribe(_) => PacketType::SUBSCRIBE,
       DecodedPacket::Suback(_) => PacketType::SUBACK,
       DecodedPacket::Unsubscribe(_) => PacketType::UNSUBSCRIBE,
@@ -73,6 +94,22 @@ impl DecodedPacket {
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Role {
+  Client,
+  Server
+}
+
+impl std::fmt::Display for Role {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "{}", match self {
+      Role::Client => "client",
+      Role::Server => "server"
+    })
+  }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PacketType {
   RESERVED,
   CONNECT,
@@ -92,30 +129,46 @@ pub enum PacketType {
   AUTH
 }
 
-impl From<u8> for PacketType {
-  fn from(num: u8) -> Self {
-    match num {
-      0 => PacketType::RESERVED,
-      1 => PacketType::CONNECT,
-      2 => PacketType::CONNACK,
-      3 => PacketType::PUBLISH,
-      4 => PacketType::PUBACK,
-      5 => PacketType::PUBREC,
-      6 => PacketType::PUBREL,
-      7 => PacketType::PUBCOMP,
-      8 => PacketType::SUBSCRIBE,
-      9 => PacketType::SUBACK,
-      10 => PacketType::UNSUBSCRIBE,
-      11 => PacketType::UNSUBACK,
-      12 => PacketType::PINGREQ,
-      13 => PacketType::PINGRESP,
-      14 => PacketType::DISCONNECT,
-      15 => PacketType::AUTH,
-      _ => panic!("{} is out of range", num)
+impl PacketType {
+  // in the order of their values, so a value indexes its packet type
+  pub const ALL: [PacketType; 16] = [
+    PacketType::RESERVED, PacketType::CONNECT, PacketType::CONNACK, PacketType::PUBLISH, PacketType::PUBACK,
+    PacketType::PUBREC, PacketType::PUBREL, PacketType::PUBCOMP, PacketType::SUBSCRIBE, PacketType::SUBACK,
+    PacketType::UNSUBSCRIBE, PacketType::UNSUBACK, PacketType::PINGREQ, PacketType::PINGRESP, PacketType::DISCONNECT,
+    PacketType::AUTH
+  ];
+}
+
+impl PacketType {
+  // the direction of flow from section 2.1.2 of the specification
+  pub fn is_received_by(&self, role: Role) -> bool {
+    match self {
+      PacketType::PUBLISH | PacketType::PUBACK | PacketType::PUBREC | PacketType::PUBREL | PacketType::PUBCOMP |
+      PacketType::DISCONNECT | PacketType::AUTH => true,
+      PacketType::CONNECT | PacketType::SUBSCRIBE | PacketType::UNSUBSCRIBE | PacketType::PINGREQ => role == Role::Server,
+      PacketType::CONNACK | PacketType::SUBACK | PacketType::UNSUBACK | PacketType::PINGRESP => role == Role::Client,
+      PacketType::RESERVED => false
+    }
+  }
+}
+
+impl TryFrom<u8> for PacketType {
+  type Error = ConversionError;
+
+  fn try_from(value: u8) -> Result<Self, Self::Error> {
+    match PacketType::ALL.get(value as usize) {
+      Some(packet_type) => Ok(*packet_type),
+      None => Err(ConversionError { type_name: "packet type", value })
     }
   }
 }
 
+impl From<PacketType> for u8 {
+  fn from(packet_type: PacketType) -> Self {
+    packet_type as u8
+  }
+}
+
 impl std::fmt::Display for PacketType {
   fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
     write!(f, "{}", match self {
@@ -137,4 +190,17 @@ impl std::fmt::Display for PacketType {
       PacketType::AUTH => "AUTH"
     })
   }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn packet_type_conversion_test() {
+    for (value, packet_type) in PacketType::ALL.iter().enumerate() {
+      assert_eq!(PacketType::try_from(value as u8).unwrap(), *packet_type);
+      assert_eq!(u8::from(*packet_type), value as u8);
+    }
+    assert_eq!(PacketType::try_from(16).unwrap_err(), ConversionError { type_name: "packet type", value: 16 });
+  }
+}
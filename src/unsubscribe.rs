@@ -3,26 +3,27 @@ use bytes::{BytesMut, Buf, BufMut};
 use crate::{
   error::{EncodeError, DecodeError},
   types::DecodedPacket,
-  property::Property,
-  utils::{decode_utf8, encode_utf8, get_remaining_length}
+  properties::UnsubscribeProperties,
+  utils::{decode_utf8, encode_utf8, get_remaining_length, get_u16}
 };
 
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct UnsubscribePacket {
   pub packet_id: u16,
   pub topics: Vec<String>,
-  pub properties: Vec<Property>
+  pub properties: UnsubscribeProperties
 }
 
 impl super::types::Encode for UnsubscribePacket {
   fn encode(&self, buffer: &mut BytesMut) -> Result<(), EncodeError> {
     buffer.put_u16(self.packet_id);
 
-    Property::encode(buffer, &self.properties)?;
+    self.properties.encode(buffer)?;
 
     for topic in &self.topics {
-      encode_utf8(buffer, topic);
+      encode_utf8(buffer, topic)?;
     }
 
     Ok(())
@@ -32,21 +33,21 @@ impl super::types::Encode for UnsubscribePacket {
 impl UnsubscribePacket {
   pub fn decode(buffer: &mut BytesMut, remaining_length: usize) -> Result<DecodedPacket, DecodeError> {
     let starting_length = buffer.remaining();
-    let packet_id = buffer.get_u16();
+    let packet_id = get_u16(buffer)?;
 
-    let properties = Property::decode(buffer)?;
+    let properties = UnsubscribeProperties::decode(buffer)?;
 
     let mut topics = Vec::new();
 
-    while get_remaining_length(&buffer, starting_length, remaining_length) > 0 {
+    while get_remaining_length(buffer, starting_length, remaining_length) > 0 {
       let topic = decode_utf8(buffer)?;
       topics.push(topic);
     }
 
     let packet = UnsubscribePacket {
-      packet_id: packet_id,
-      topics: topics,
-      properties: properties
+      packet_id,
+      topics,
+      properties
     };
 
     Ok(DecodedPacket::Unsubscribe(packet))
@@ -64,7 +65,7 @@ mod tests {
     let packet = UnsubscribePacket {
       packet_id: 32,
       topics: vec!["test".to_owned(), "test1".to_owned()],
-      properties: vec![]
+      properties: UnsubscribeProperties::default()
     };
 
     let packet2 = packet.clone();
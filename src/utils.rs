@@ -1,25 +1,91 @@
 use bytes::{BytesMut, Buf, BufMut};
 
-pub fn decode_utf8(buffer: &mut BytesMut) -> Result<String, std::str::Utf8Error> {
-  let string_length = buffer.get_u16();
-  decode_utf8_with_length(buffer, string_length as usize)
+use crate::error::{EncodeError, DecodeError};
+
+// strings and binary data are prefixed with a two byte length
+pub const MAX_LENGTH: usize = 65535;
+
+// fails instead of panicking when fewer than length bytes are left in the packet
+pub fn check_remaining(buffer: &BytesMut, length: usize) -> Result<(), DecodeError> {
+  match buffer.remaining() < length {
+    true => Err(DecodeError::UnexpectedEnd),
+    false => Ok(())
+  }
+}
+
+pub fn get_u8(buffer: &mut BytesMut) -> Result<u8, DecodeError> {
+  check_remaining(buffer, 1)?;
+  Ok(buffer.get_u8())
+}
+
+pub fn get_u16(buffer: &mut BytesMut) -> Result<u16, DecodeError> {
+  check_remaining(buffer, 2)?;
+  Ok(buffer.get_u16())
+}
+
+pub fn get_u32(buffer: &mut BytesMut) -> Result<u32, DecodeError> {
+  check_remaining(buffer, 4)?;
+  Ok(buffer.get_u32())
+}
+
+pub fn decode_utf8(buffer: &mut BytesMut) -> Result<String, DecodeError> {
+  let string_length = get_u16(buffer)?;
+  let string = decode_utf8_with_length(buffer, string_length as usize)?;
+  if string.contains('\0') {
+    return Err(DecodeError::NullCharacter);
+  }
+  Ok(string)
 }
 
-pub fn decode_utf8_with_length(buffer: &mut BytesMut, string_length: usize) -> Result<String, std::str::Utf8Error> {
+// for payloads and other data that is not a UTF-8 encoded string, so U+0000 is allowed
+pub fn decode_utf8_with_length(buffer: &mut BytesMut, string_length: usize) -> Result<String, DecodeError> {
+  check_remaining(buffer, string_length)?;
   let mut read_buffer = vec![0; string_length];
   buffer.copy_to_slice(&mut read_buffer);
   let ret = std::str::from_utf8(&read_buffer)?.to_owned();
   Ok(ret)
 }
 
-pub fn encode_utf8(buffer: &mut BytesMut, string: &str) {
+pub fn encode_utf8(buffer: &mut BytesMut, string: &str) -> Result<(), EncodeError> {
+  check_utf8(string)?;
   buffer.put_u16(string.len() as u16);
   buffer.put_slice(string.as_bytes());
+  Ok(())
+}
+
+pub fn check_utf8(string: &str) -> Result<(), EncodeError> {
+  if string.len() > MAX_LENGTH {
+    return Err(EncodeError::StringTooLong(string.len()));
+  }
+  if string.contains('\0') {
+    return Err(EncodeError::NullCharacter);
+  }
+  Ok(())
+}
+
+// the specification discourages non-characters such as U+FFFF and lets the receiver treat them as malformed
+pub fn contains_non_character(string: &str) -> bool {
+  string.chars().any(|c| matches!(c as u32, 0xFDD0..=0xFDEF) || c as u32 & 0xFFFE == 0xFFFE)
+}
+
+pub fn decode_binary(buffer: &mut BytesMut) -> Result<Vec<u8>, DecodeError> {
+  let data_length = get_u16(buffer)? as usize;
+  check_remaining(buffer, data_length)?;
+  Ok(buffer.split_to(data_length).to_vec())
+}
+
+pub fn encode_binary(buffer: &mut BytesMut, data: &[u8]) -> Result<(), EncodeError> {
+  if data.len() > MAX_LENGTH {
+    return Err(EncodeError::BinaryTooLong(data.len()));
+  }
+  buffer.put_u16(data.len() as u16);
+  buffer.put_slice(data);
+  Ok(())
 }
 
 pub fn get_remaining_length(buffer: &BytesMut, starting_length: usize, remaining_length: usize) -> usize {
   let byte_written = starting_length - buffer.remaining();
-  return remaining_length - byte_written;
+  remaining_length.saturating_sub(byte_written)
 }
 
 #[cfg(test)]
@@ -44,10 +110,58 @@ mod tests {
   #[test]
   fn encode_utf8_test() {
     let mut buffer = BytesMut::new();
-    encode_utf8(&mut buffer, TEST_STRING);
+    encode_utf8(&mut buffer, TEST_STRING).unwrap();
     assert_eq!(&buffer[..], TEST_BYTES);
   }
 
+  #[test]
+  fn null_character_test() {
+    let mut buffer = BytesMut::from(&[0x00, 0x03, b'a', 0x00, b'b'][..]);
+    assert!(matches!(decode_utf8(&mut buffer), Err(DecodeError::NullCharacter)));
+    assert!(matches!(encode_utf8(&mut BytesMut::new(), "a\0b"), Err(EncodeError::NullCharacter)));
+
+    let mut buffer = BytesMut::from(&[b'a', 0x00, b'b'][..]);
+    assert_eq!(decode_utf8_with_length(&mut buffer, 3).unwrap(), "a\0b");
+  }
+
+  #[test]
+  fn too_long_test() {
+    let mut buffer = BytesMut::new();
+    assert!(encode_utf8(&mut buffer, &"a".repeat(MAX_LENGTH)).is_ok());
+    assert!(matches!(encode_utf8(&mut buffer, &"a".repeat(MAX_LENGTH + 1)), Err(EncodeError::StringTooLong(65536))));
+    assert!(matches!(encode_binary(&mut buffer, &[0; MAX_LENGTH + 1]), Err(EncodeError::BinaryTooLong(65536))));
+    assert_eq!(buffer.len(), MAX_LENGTH + 2);
+  }
+
+  #[test]
+  fn non_character_test() {
+    assert!(!contains_non_character(TEST_STRING));
+    assert!(!contains_non_character("\u{FFFD}\u{FDCF}"));
+    for c in ['\u{FDD0}', '\u{FDEF}', '\u{FFFE}', '\u{FFFF}', '\u{1FFFE}', '\u{10FFFF}'] {
+      assert!(contains_non_character(&format!("a{}", c)));
+    }
+  }
+
+  #[test]
+  fn binary_test() {
+    let mut buffer = BytesMut::new();
+    encode_binary(&mut buffer, &[0x00, 0xFF]).unwrap();
+    assert_eq!(&buffer[..], [0x00, 0x02, 0x00, 0xFF]);
+    assert_eq!(decode_binary(&mut buffer).unwrap(), vec![0x00, 0xFF]);
+  }
+
+  #[test]
+  fn truncated_test() {
+    let mut buffer = BytesMut::from(&[0x00, 0x03, 0x00, 0xFF][..]);
+    assert!(matches!(decode_binary(&mut buffer), Err(DecodeError::UnexpectedEnd)));
+
+    let mut buffer = BytesMut::from(&TEST_BYTES[..6]);
+    assert!(matches!(decode_utf8(&mut buffer), Err(DecodeError::UnexpectedEnd)));
+
+    let mut buffer = BytesMut::from(&[0x00][..]);
+    assert!(matches!(decode_utf8(&mut buffer), Err(DecodeError::UnexpectedEnd)));
+  }
+
   #[test]
   fn get_remaining_length_test() {
     let mut buffer = BytesMut::from(&TEST_BYTES[..]);
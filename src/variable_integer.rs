@@ -1,24 +1,49 @@
 use bytes::{BytesMut, Buf, BufMut};
 use crate::error::{EncodeError, DecodeError};
 
+pub const MAX_VALUE: u64 = 268435455;
+
+// accepts overlong encodings such as 0x80 0x00, which the specification forbids but some peers send
 pub fn decode(buffer: &mut BytesMut) -> Result<u64, DecodeError>  {
-  let mut multiplier = 1;
+  decode_with(buffer, false)
+}
+
+// the buffer is left untouched if the integer is incomplete, too long or, when minimal is set, overlong
+pub fn decode_with(buffer: &mut BytesMut, minimal: bool) -> Result<u64, DecodeError> {
+  let (value, length) = read(buffer)?;
+  if minimal && length > encoded_length(value) {
+    return Err(DecodeError::VariableIntegerNotMinimal);
+  }
+  buffer.advance(length);
+  Ok(value)
+}
+
+// the value and the number of bytes it takes, at most 4
+fn read(bytes: &[u8]) -> Result<(u64, usize), DecodeError> {
   let mut value = 0;
-  while {
-    let encoded_byte = buffer.get_u8();
-    value += ((encoded_byte & 0x7F) as u64) * multiplier;
-    if multiplier > 128 * 128 * 128 {
-      return Err(DecodeError::FormatError);
+  for (index, encoded_byte) in bytes.iter().take(4).enumerate() {
+    value += ((encoded_byte & 0x7F) as u64) << (7 * index);
+    if encoded_byte & 0x80 == 0 {
+      return Ok((value, index + 1));
     }
-    multiplier *= 128;
+  }
+  match bytes.len() {
+    0..=3 => Err(DecodeError::VariableIntegerIncomplete),
+    _ => Err(DecodeError::VariableIntegerTooLong)
+  }
+}
 
-    encoded_byte & 0x80 != 0
-  } {}
-  Ok(value)
+pub fn encoded_length(value: u64) -> usize {
+  match value {
+    0..=127 => 1,
+    128..=16383 => 2,
+    16384..=2097151 => 3,
+    _ => 4
+  }
 }
 
 pub fn encode(buffer: &mut BytesMut, mut val: u64) -> Result<(), EncodeError> {
-  if val > 268435455 {
+  if val > MAX_VALUE {
     return Err(EncodeError::VariableIntegerOutOfRangeError);
   }
   while {
@@ -64,6 +89,93 @@ mod tests {
     decode_util(&[0x80, 0x80, 0x80, 0x80, 0x01]).unwrap(); 
   }
 
+  // the largest value for each length and the smallest value of the next
+  const BOUNDARIES: [(u64, usize); 8] = [
+    (0, 1), (127, 1), (128, 2), (16383, 2), (16384, 3), (2097151, 3), (2097152, 4), (268435455, 4)
+  ];
+
+  // the same value padded with continuation bytes up to the given length
+  fn overlong(value: u64, length: usize) -> Vec<u8> {
+    let mut bytes = encode_util(value).unwrap().to_vec();
+    *bytes.last_mut().unwrap() |= 0x80;
+    bytes.resize(length, 0x80);
+    *bytes.last_mut().unwrap() = 0x00;
+    bytes
+  }
+
+  #[test]
+  fn boundary_test() {
+    for &(value, length) in BOUNDARIES.iter() {
+      for value in [value.saturating_sub(1), value, value + 1] {
+        if value > MAX_VALUE {
+          assert!(encode_util(value).is_err());
+          continue;
+        }
+        let mut buffer = encode_util(value).unwrap();
+        assert_eq!(buffer.len(), encoded_length(value));
+        buffer.put_u8(0xFF);
+        assert_eq!(decode_with(&mut buffer, true).unwrap(), value);
+        assert_eq!(&buffer[..], [0xFF]);
+      }
+      assert_eq!(encoded_length(value), length);
+    }
+  }
+
+  #[test]
+  fn two_bytes_test() {
+    for first in 0..=255u8 {
+      for second in 0..=255u8 {
+        let mut buffer = BytesMut::from(&[first, second][..]);
+        let result = decode_with(&mut buffer, true);
+        match (first & 0x80, second & 0x80) {
+          (0, _) => {
+            assert_eq!(result.unwrap(), first as u64);
+            assert_eq!(buffer.len(), 1);
+          },
+          (_, 0) if second == 0 => assert!(matches!(result, Err(DecodeError::VariableIntegerNotMinimal))),
+          (_, 0) => assert_eq!(result.unwrap(), (first & 0x7F) as u64 + ((second as u64) << 7)),
+          _ => assert!(matches!(result, Err(DecodeError::VariableIntegerIncomplete)))
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn overlong_test() {
+    for &(value, length) in BOUNDARIES.iter() {
+      for overlong_length in length + 1..=4 {
+        let bytes = overlong(value, overlong_length);
+        assert_eq!(decode_util(&bytes).unwrap(), value);
+
+        let mut buffer = BytesMut::from(&bytes[..]);
+        assert!(matches!(decode_with(&mut buffer, true), Err(DecodeError::VariableIntegerNotMinimal)));
+        assert_eq!(&buffer[..], &bytes[..]);
+      }
+    }
+    assert_eq!(overlong(0, 2), [0x80, 0x00]);
+    assert_eq!(overlong(127, 4), [0xFF, 0x80, 0x80, 0x00]);
+  }
+
+  #[test]
+  fn incomplete_test() {
+    for &(value, _) in BOUNDARIES.iter() {
+      let bytes = encode_util(value).unwrap();
+      for length in 0..bytes.len() {
+        let mut buffer = BytesMut::from(&bytes[..length]);
+        assert!(matches!(decode(&mut buffer), Err(DecodeError::VariableIntegerIncomplete)));
+        assert_eq!(buffer.len(), length);
+      }
+    }
+  }
+
+  #[test]
+  fn too_long_test() {
+    // the fourth byte must not have the continuation bit, whether or not a fifth byte follows
+    assert!(matches!(decode_util(&[0x80, 0x80, 0x80, 0x80]), Err(DecodeError::VariableIntegerTooLong)));
+    assert!(matches!(decode_util(&[0xFF, 0xFF, 0xFF, 0xFF, 0x7F]), Err(DecodeError::VariableIntegerTooLong)));
+    assert!(matches!(decode_util(&[0x80, 0x80, 0x80, 0x80, 0x00]), Err(DecodeError::VariableIntegerTooLong)));
+  }
+
   fn encode_util(value: u64) -> Result<BytesMut, EncodeError> {
     let mut buffer = BytesMut::new();
     encode(&mut buffer, value)?;
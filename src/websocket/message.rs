@@ -4,110 +4,425 @@ use bytes::{BytesMut, Buf, BufMut};
 use crate::{
   error::{DecodeError, EncodeError},
   types::DecodedPacket,
-  codec::MQTTCodec
+  codec::MQTTCodec,
+  header::packet_size,
+  variable_integer
 };
 
+const CONTINUATION: u8 = 0x0;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+// control frames carry at most 125 bytes of payload
+const MAXIMUM_CONTROL_PAYLOAD: usize = 125;
+
+// an MQTT packet or one of the control frames that may arrive between and inside data messages;
+// packets are by far the most common item, so they are not boxed
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum WebsocketMessage {
+  Packet(DecodedPacket),
+  Ping(Vec<u8>),
+  Pong(Vec<u8>),
+  // a close frame without a payload has no status code
+  Close(Option<CloseFrame>)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CloseFrame {
+  pub code: u16,
+  pub reason: String
+}
+
+impl CloseFrame {
+  // the status code for a peer that broke the websocket protocol
+  pub const PROTOCOL_ERROR: u16 = 1002;
+
+  // codes below 1000 are unused, and 1005, 1006 and 1015 only stand for a missing status code,
+  // an abnormal closure and a failed TLS handshake locally (RFC 6455 section 7.4.1)
+  pub fn is_valid_code(code: u16) -> bool {
+    !matches!(code, 0..=999 | 1005 | 1006 | 1015)
+  }
+}
+
+impl WebsocketMessage {
+  // the frame to answer with: a pong for a ping, and a close echoing the status code for a close,
+  // or carrying a protocol error if that status code is not valid
+  pub fn reply(&self) -> Option<WebsocketMessage> {
+    match self {
+      WebsocketMessage::Ping(payload) => Some(WebsocketMessage::Pong(payload.clone())),
+      WebsocketMessage::Close(frame) => Some(WebsocketMessage::Close(frame.as_ref().map(|frame| CloseFrame {
+        code: match CloseFrame::is_valid_code(frame.code) {
+          true => frame.code,
+          false => CloseFrame::PROTOCOL_ERROR
+        },
+        reason: String::new()
+      }))),
+      _ => None
+    }
+  }
+}
+
+impl From<DecodedPacket> for WebsocketMessage {
+  fn from(packet: DecodedPacket) -> Self {
+    WebsocketMessage::Packet(packet)
+  }
+}
+
 pub struct WebsocketCodec {
+  codec: MQTTCodec,
   mqtt_buffer: BytesMut
 }
 
+impl Default for WebsocketCodec {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 impl WebsocketCodec {
   pub fn new() -> Self {
+    Self::with_codec(MQTTCodec::new())
+  }
+
+  // wraps a configured codec, e.g. a strict one
+  pub fn with_codec(codec: MQTTCodec) -> Self {
     Self {
+      codec,
       mqtt_buffer: BytesMut::new()
     }
   }
 }
 
-impl Decoder for WebsocketCodec {
-  type Item = DecodedPacket;
-  type Error = DecodeError;
+impl WebsocketCodec {
+  // the Maximum Packet Size announced to the peer, or the largest packet MQTT can express
+  fn maximum_packet_size(&self) -> u64 {
+    match self.codec.receive_limits().and_then(|limits| limits.maximum_packet_size) {
+      Some(maximum) => maximum as u64,
+      None => packet_size(variable_integer::MAX_VALUE as usize) as u64
+    }
+  }
 
-  fn decode(&mut self, buffer: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+  // reads one complete frame, moving a data payload into the MQTT buffer and returning a control frame;
+  // None if the frame is incomplete
+  fn decode_frame(&mut self, buffer: &mut BytesMut) -> Result<Option<Option<WebsocketMessage>>, DecodeError> {
     let mut read_buffer = buffer.clone();
-    let mut fin = false;
-    
-    while read_buffer.remaining() > 0 {
-      while !fin {
-        if read_buffer.remaining() < 2 {
-          return Ok(None);
-        }
-    
-        let first_byte = read_buffer.get_u8();
-        fin = (first_byte & 0b10000000) == 0b10000000;
-        let opcode = first_byte & 0b1111;
-  
-        if first_byte & 0b01110000 != 0 || (opcode != 2 && opcode != 0) {
-          return Err(DecodeError::FormatError);
-        }
-  
-        let second_byte = read_buffer.get_u8();
-        let mask = (second_byte & 0b10000000) == 0b10000000;
-        let mut payload_length = (second_byte & 0b1111111) as usize;
-        
-        if payload_length == 126 {
-          payload_length = read_buffer.get_u16() as usize;
-        } else if payload_length > 126 {
-          payload_length = read_buffer.get_u64() as usize;
-        }
-  
-        let mask_key = match mask {
-          true => {
-            Some([read_buffer.get_u8(), read_buffer.get_u8(), read_buffer.get_u8(), read_buffer.get_u8()])
-          },
-          false => None
-        };
-
-    
-        if read_buffer.remaining() < payload_length {
+    if read_buffer.remaining() < 2 {
+      return Ok(None);
+    }
+
+    let first_byte = read_buffer.get_u8();
+    let fin = first_byte & 0b10000000 != 0;
+    let opcode = first_byte & 0b1111;
+
+    if first_byte & 0b01110000 != 0 {
+      return Err(DecodeError::InvalidWebsocketFrame(first_byte));
+    }
+    match opcode {
+      CONTINUATION | BINARY => {},
+      CLOSE | PING | PONG => if !fin {
+        return Err(DecodeError::InvalidWebsocketControlFrame(opcode));
+      },
+      _ => return Err(DecodeError::UnsupportedWebsocketOpcode(opcode))
+    }
+
+    let second_byte = read_buffer.get_u8();
+    let mask = (second_byte & 0b10000000) == 0b10000000;
+    let mut payload_length = (second_byte & 0b1111111) as u64;
+
+    if payload_length == 126 {
+      if read_buffer.remaining() < 2 {
+        return Ok(None);
+      }
+      payload_length = read_buffer.get_u16() as u64;
+    } else if payload_length > 126 {
+      if read_buffer.remaining() < 8 {
+        return Ok(None);
+      }
+      payload_length = read_buffer.get_u64();
+    }
+    if opcode & 0b1000 != 0 && payload_length > MAXIMUM_CONTROL_PAYLOAD as u64 {
+      return Err(DecodeError::InvalidWebsocketControlFrame(opcode));
+    }
+    // the frame is rejected before it is buffered, whatever length it declares
+    let maximum = self.maximum_packet_size();
+    if payload_length > maximum {
+      return Err(DecodeError::WebsocketFrameTooLarge { size: payload_length, maximum });
+    }
+    let payload_length = payload_length as usize;
+
+    let mask_key = match mask {
+      true => {
+        if read_buffer.remaining() < 4 {
           return Ok(None);
         }
-    
-        let mut message = vec![0; payload_length];
-        
-        if let Some(mask_key) = mask_key {
-          for i in 0..payload_length {
-            message[i] = read_buffer[i] ^ mask_key[i % 4]
-          }
-        }
+        Some([read_buffer.get_u8(), read_buffer.get_u8(), read_buffer.get_u8(), read_buffer.get_u8()])
+      },
+      false => None
+    };
+
+    if read_buffer.remaining() < payload_length {
+      return Ok(None);
+    }
 
-        read_buffer.advance(payload_length);
+    let mut message = read_buffer.split_to(payload_length);
+    if let Some(mask_key) = mask_key {
+      for (i, byte) in message.iter_mut().enumerate() {
+        *byte ^= mask_key[i % 4];
+      }
+    }
 
+    let control = match opcode {
+      CLOSE => Some(WebsocketMessage::Close(Self::decode_close(&message)?)),
+      PING => Some(WebsocketMessage::Ping(message.to_vec())),
+      PONG => Some(WebsocketMessage::Pong(message.to_vec())),
+      _ => {
         self.mqtt_buffer.reserve(payload_length);
-        self.mqtt_buffer.put_slice(&message);
+        self.mqtt_buffer.put(message);
+        None
       }
-      
-      let mut codec = MQTTCodec {};
-      buffer.advance(buffer.remaining() - read_buffer.remaining());
-      if let Ok(Some(packet)) = codec.decode(&mut self.mqtt_buffer) {
-        self.mqtt_buffer.clear();
-        return Ok(Some(packet));
+    };
+
+    buffer.advance(buffer.remaining() - read_buffer.remaining());
+    Ok(Some(control))
+  }
+
+  fn decode_close(payload: &[u8]) -> Result<Option<CloseFrame>, DecodeError> {
+    match payload.len() {
+      0 => Ok(None),
+      1 => Err(DecodeError::InvalidWebsocketControlFrame(CLOSE)),
+      _ => match u16::from_be_bytes([payload[0], payload[1]]) {
+        code if CloseFrame::is_valid_code(code) => Ok(Some(CloseFrame {
+          code,
+          reason: std::str::from_utf8(&payload[2..])?.to_owned()
+        })),
+        code => Err(DecodeError::InvalidWebsocketCloseCode(code))
       }
-      fin = false;
     }
-    Ok(None)
   }
-}
 
-impl Encoder<DecodedPacket> for WebsocketCodec {
-  type Error = EncodeError;
-  fn encode(&mut self, packet: DecodedPacket, buffer: &mut BytesMut) -> Result<(), Self::Error> {
-    let mut codec = MQTTCodec {};
-    let mut mqtt_buffer = BytesMut::new();
-    codec.encode(packet, &mut mqtt_buffer)?;
-    
-    buffer.put_u8(0b10000010);
-    let payload_length = mqtt_buffer.len();
+  fn encode_frame(opcode: u8, payload: &[u8], buffer: &mut BytesMut) {
+    buffer.put_u8(0b10000000 | opcode);
+    let payload_length = payload.len();
     if payload_length < 126 {
       buffer.put_u8(payload_length as u8);
     } else if payload_length < 65536 {
       buffer.put_u8(126);
       buffer.put_u16(payload_length as u16);
     } else {
+      buffer.put_u8(127);
       buffer.put_u64(payload_length as u64);
     }
 
-    buffer.put_slice(&mqtt_buffer[..]);
+    buffer.put_slice(payload);
+  }
+}
+
+impl Decoder for WebsocketCodec {
+  type Item = WebsocketMessage;
+  type Error = DecodeError;
+
+  // MQTT packets may span frames and a frame may hold several packets, so payloads are
+  // collected into one stream before decoding; control frames are returned as they arrive
+  fn decode(&mut self, buffer: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+    loop {
+      if let Some(packet) = self.codec.decode(&mut self.mqtt_buffer)? {
+        return Ok(Some(WebsocketMessage::Packet(packet)));
+      }
+
+      match self.decode_frame(buffer)? {
+        Some(Some(control)) => return Ok(Some(control)),
+        Some(None) => {},
+        None => return Ok(None)
+      }
+    }
+  }
+}
+
+impl Encoder<WebsocketMessage> for WebsocketCodec {
+  type Error = EncodeError;
+  fn encode(&mut self, message: WebsocketMessage, buffer: &mut BytesMut) -> Result<(), Self::Error> {
+    match message {
+      WebsocketMessage::Packet(packet) => {
+        let mut mqtt_buffer = BytesMut::new();
+        self.codec.encode(packet, &mut mqtt_buffer)?;
+        Self::encode_frame(BINARY, &mqtt_buffer, buffer);
+      },
+      WebsocketMessage::Ping(payload) | WebsocketMessage::Pong(payload) if payload.len() > MAXIMUM_CONTROL_PAYLOAD => {
+        return Err(EncodeError::ControlFrameTooLong(payload.len()));
+      },
+      WebsocketMessage::Ping(payload) => Self::encode_frame(PING, &payload, buffer),
+      WebsocketMessage::Pong(payload) => Self::encode_frame(PONG, &payload, buffer),
+      WebsocketMessage::Close(frame) => {
+        let mut payload = Vec::new();
+        if let Some(frame) = frame {
+          payload.extend_from_slice(&frame.code.to_be_bytes());
+          payload.extend_from_slice(frame.reason.as_bytes());
+        }
+        if payload.len() > MAXIMUM_CONTROL_PAYLOAD {
+          return Err(EncodeError::ControlFrameTooLong(payload.len()));
+        }
+        Self::encode_frame(CLOSE, &payload, buffer);
+      }
+    }
     Ok(())
   }
 }
+
+impl Encoder<DecodedPacket> for WebsocketCodec {
+  type Error = EncodeError;
+  fn encode(&mut self, packet: DecodedPacket, buffer: &mut BytesMut) -> Result<(), Self::Error> {
+    self.encode(WebsocketMessage::Packet(packet), buffer)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use bytes::BytesMut;
+  use crate::types::{DecodedPacket, PingReqPacket, PublishPacket, PublishConfig, ConnackPacket, ReasonCode, Role};
+  use super::*;
+
+  fn publish(payload_length: usize) -> DecodedPacket {
+    DecodedPacket::Publish(PublishPacket {
+      topic: "test".to_owned(),
+      packet_id: None,
+      payload: "a".repeat(payload_length),
+      config: PublishConfig::default(),
+      properties: Default::default()
+    })
+  }
+
+  #[test]
+  fn codec_test() {
+    let mut codec = WebsocketCodec::new();
+    for packet in [publish(10), publish(1000), publish(70000)].iter() {
+      let mut buffer = BytesMut::new();
+      Encoder::<DecodedPacket>::encode(&mut codec, packet.clone(), &mut buffer).unwrap();
+      assert_eq!(codec.decode(&mut buffer).unwrap(), Some(WebsocketMessage::Packet(packet.clone())));
+      assert!(buffer.is_empty());
+    }
+  }
+
+  #[test]
+  fn decode_masked_test() {
+    let mut codec = WebsocketCodec::new();
+    let mask_key = [0x12, 0x34, 0x56, 0x78];
+    let payload = [0xC0 ^ mask_key[0], mask_key[1]];
+    let mut buffer = BytesMut::from(&[0b10000010, 0b10000010][..]);
+    buffer.put_slice(&mask_key);
+    buffer.put_slice(&payload);
+
+    assert_eq!(codec.decode(&mut buffer).unwrap(), Some(WebsocketMessage::Packet(DecodedPacket::PingReq(PingReqPacket {}))));
+  }
+
+  #[test]
+  fn decode_split_packets_test() {
+    let mut codec = WebsocketCodec::new();
+    // one PINGREQ split over two frames followed by two PINGREQs in a single frame
+    let mut buffer = BytesMut::from(&[0b00000010, 0x01, 0xC0, 0b10000000, 0x01, 0x00][..]);
+    buffer.put_slice(&[0b10000010, 0x04, 0xC0, 0x00, 0xC0, 0x00]);
+
+    for _ in 0..3 {
+      assert_eq!(codec.decode(&mut buffer).unwrap(), Some(WebsocketMessage::Packet(DecodedPacket::PingReq(PingReqPacket {}))));
+    }
+    assert_eq!(codec.decode(&mut buffer).unwrap(), None);
+  }
+
+  #[test]
+  fn decode_incomplete_test() {
+    let mut codec = WebsocketCodec::new();
+    let mut buffer = BytesMut::from(&[0b10000010, 0x02, 0xC0][..]);
+    assert_eq!(codec.decode(&mut buffer).unwrap(), None);
+    buffer.put_u8(0x00);
+    assert_eq!(codec.decode(&mut buffer).unwrap(), Some(WebsocketMessage::Packet(DecodedPacket::PingReq(PingReqPacket {}))));
+  }
+
+  #[test]
+  fn control_frames_test() {
+    let mut codec = WebsocketCodec::new();
+    // a PINGREQ split over two frames with a ping and a pong between them
+    let mut buffer = BytesMut::from(&[0b00000010, 0x01, 0xC0, 0b10001001, 0x02, b'h', b'i', 0b10001010, 0x00][..]);
+    buffer.put_slice(&[0b10000000, 0x01, 0x00]);
+
+    let ping = codec.decode(&mut buffer).unwrap().unwrap();
+    assert_eq!(ping, WebsocketMessage::Ping(b"hi".to_vec()));
+    assert_eq!(ping.reply(), Some(WebsocketMessage::Pong(b"hi".to_vec())));
+    assert_eq!(codec.decode(&mut buffer).unwrap(), Some(WebsocketMessage::Pong(vec![])));
+    assert_eq!(codec.decode(&mut buffer).unwrap(), Some(WebsocketMessage::Packet(DecodedPacket::PingReq(PingReqPacket {}))));
+    assert!(buffer.is_empty());
+
+    let mut buffer = BytesMut::new();
+    codec.encode(ping.reply().unwrap(), &mut buffer).unwrap();
+    assert_eq!(&buffer[..], [0b10001010, 0x02, b'h', b'i']);
+
+    let error = codec.encode(WebsocketMessage::Ping(vec![0; 126]), &mut BytesMut::new()).unwrap_err();
+    assert!(matches!(error, EncodeError::ControlFrameTooLong(126)));
+    let close = WebsocketMessage::Close(Some(CloseFrame { code: 1000, reason: "a".repeat(124) }));
+    assert!(matches!(codec.encode(close, &mut BytesMut::new()), Err(EncodeError::ControlFrameTooLong(126))));
+  }
+
+  #[test]
+  fn close_test() {
+    let mut codec = WebsocketCodec::new();
+    let mut buffer = BytesMut::from(&[0b10001000, 0x06, 0x03, 0xE9, b'b', b'y', b'e', b'!'][..]);
+
+    let close = codec.decode(&mut buffer).unwrap().unwrap();
+    assert_eq!(close, WebsocketMessage::Close(Some(CloseFrame { code: 1001, reason: "bye!".to_owned() })));
+
+    // the reply echoes the status code without the reason
+    codec.encode(close.reply().unwrap(), &mut buffer).unwrap();
+    assert_eq!(&buffer[..], [0b10001000, 0x02, 0x03, 0xE9]);
+    assert_eq!(codec.decode(&mut buffer).unwrap(), Some(WebsocketMessage::Close(Some(CloseFrame { code: 1001, reason: String::new() }))));
+
+    let mut buffer = BytesMut::from(&[0b10001000, 0x00][..]);
+    assert_eq!(codec.decode(&mut buffer).unwrap(), Some(WebsocketMessage::Close(None)));
+    assert_eq!(WebsocketMessage::Close(None).reply(), Some(WebsocketMessage::Close(None)));
+  }
+
+  #[test]
+  fn invalid_close_test() {
+    // a close with status code 1000 and a reason that is not UTF-8
+    let mut buffer = BytesMut::from(&[0b10001000, 0x04, 0x03, 0xE8, 0xC3, 0x28][..]);
+    assert!(matches!(WebsocketCodec::new().decode(&mut buffer), Err(DecodeError::Utf8Error(_))));
+
+    // status codes that are never sent in a close frame
+    for code in [0u16, 999, 1005, 1006, 1015] {
+      let [high, low] = code.to_be_bytes();
+      let mut buffer = BytesMut::from(&[0b10001000, 0x02, high, low][..]);
+      assert!(matches!(WebsocketCodec::new().decode(&mut buffer), Err(DecodeError::InvalidWebsocketCloseCode(error)) if error == code));
+    }
+
+    // a close built with one of them is answered with a protocol error
+    let close = WebsocketMessage::Close(Some(CloseFrame { code: 1006, reason: String::new() }));
+    assert_eq!(close.reply(), Some(WebsocketMessage::Close(Some(CloseFrame { code: 1002, reason: String::new() }))));
+  }
+
+  #[test]
+  fn invalid_control_frame_test() {
+    // a fragmented ping, an overlong pong and a close with a 1 byte payload
+    for bytes in [&[0b00001001, 0x00][..], &[0b10001010, 0x7E, 0x00, 0x7E], &[0b10001000, 0x01, 0x03]] {
+      let mut codec = WebsocketCodec::new();
+      assert!(matches!(codec.decode(&mut BytesMut::from(bytes)), Err(DecodeError::InvalidWebsocketControlFrame(_))));
+    }
+    assert!(matches!(WebsocketCodec::new().decode(&mut BytesMut::from(&[0b10000001, 0x00][..])), Err(DecodeError::UnsupportedWebsocketOpcode(1))));
+  }
+
+  #[test]
+  fn frame_too_large_test() {
+    // a frame declaring the largest 64 bit length is rejected before its payload arrives
+    let mut buffer = BytesMut::from(&[0b10000010, 0x7F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF][..]);
+    let error = WebsocketCodec::new().decode(&mut buffer).unwrap_err();
+    assert!(matches!(error, DecodeError::WebsocketFrameTooLarge { size: u64::MAX, maximum: 268435460 }));
+    assert_eq!(error.reason_code(), ReasonCode::PacketTooLarge);
+
+    // once the server announces a Maximum Packet Size, a longer frame is rejected
+    let mut codec = WebsocketCodec::with_codec(MQTTCodec::new().with_role(Role::Server).with_limits());
+    let connack = ConnackPacket::builder().maximum_packet_size(16).build().unwrap();
+    Encoder::<DecodedPacket>::encode(&mut codec, DecodedPacket::Connack(connack), &mut BytesMut::new()).unwrap();
+    let mut buffer = BytesMut::from(&[0b10000010, 0x11][..]);
+    buffer.put_slice(&[0; 17]);
+    assert!(matches!(codec.decode(&mut buffer), Err(DecodeError::WebsocketFrameTooLarge { size: 17, maximum: 16 })));
+  }
+}
@@ -1,5 +1,7 @@
 mod upgrade;
 mod message;
+mod packets;
 
 pub use upgrade::*;
-pub use message::*;
\ No newline at end of file
+pub use message::*;
+pub use packets::*;
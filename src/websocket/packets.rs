@@ -0,0 +1,157 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::{ready, Sink, Stream};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::Framed;
+
+use crate::{
+  error::{DecodeError, EncodeError},
+  types::DecodedPacket
+};
+use super::{CloseFrame, WebsocketCodec, WebsocketMessage};
+
+// MQTT packets over a websocket with the control frames handled underneath: pings are answered
+// and a close is echoed before the stream ends, so only DecodedPacket items come through
+pub struct WebsocketPackets<T> {
+  framed: Framed<T, WebsocketCodec>,
+  // the answer to the last control frame, waiting for room in the write buffer
+  reply: Option<WebsocketMessage>,
+  flushing: bool,
+  closed: bool
+}
+
+impl<T: AsyncRead + AsyncWrite> WebsocketPackets<T> {
+  pub fn new(io: T) -> Self {
+    Self::with_codec(io, WebsocketCodec::new())
+  }
+
+  pub fn with_codec(io: T, codec: WebsocketCodec) -> Self {
+    Self::from_framed(Framed::new(io, codec))
+  }
+
+  pub fn from_framed(framed: Framed<T, WebsocketCodec>) -> Self {
+    Self {
+      framed,
+      reply: None,
+      flushing: false,
+      closed: false
+    }
+  }
+
+  pub fn into_inner(self) -> Framed<T, WebsocketCodec> {
+    self.framed
+  }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> WebsocketPackets<T> {
+  fn poll_reply(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), EncodeError>> {
+    if self.reply.is_some() {
+      ready!(Sink::<WebsocketMessage>::poll_ready(Pin::new(&mut self.framed), cx))?;
+      if let Some(reply) = self.reply.take() {
+        Pin::new(&mut self.framed).start_send(reply)?;
+        self.flushing = true;
+      }
+    }
+    if self.flushing {
+      ready!(Sink::<WebsocketMessage>::poll_flush(Pin::new(&mut self.framed), cx))?;
+      self.flushing = false;
+    }
+    Poll::Ready(Ok(()))
+  }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Stream for WebsocketPackets<T> {
+  type Item = Result<DecodedPacket, DecodeError>;
+
+  // the stream ends after a close frame, or when a reply cannot be written; a close with an invalid
+  // status code is reported and answered with a protocol error before the stream ends
+  fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    let this = self.get_mut();
+    loop {
+      if ready!(this.poll_reply(cx)).is_err() || this.closed {
+        return Poll::Ready(None);
+      }
+
+      match ready!(Pin::new(&mut this.framed).poll_next(cx)) {
+        Some(Ok(WebsocketMessage::Packet(packet))) => return Poll::Ready(Some(Ok(packet))),
+        Some(Ok(control)) => {
+          this.closed = matches!(control, WebsocketMessage::Close(_));
+          this.reply = control.reply();
+        },
+        Some(Err(DecodeError::InvalidWebsocketCloseCode(code))) => {
+          this.closed = true;
+          this.reply = Some(WebsocketMessage::Close(Some(CloseFrame { code: CloseFrame::PROTOCOL_ERROR, reason: String::new() })));
+          return Poll::Ready(Some(Err(DecodeError::InvalidWebsocketCloseCode(code))));
+        },
+        Some(Err(error)) => return Poll::Ready(Some(Err(error))),
+        None => return Poll::Ready(None)
+      }
+    }
+  }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Sink<DecodedPacket> for WebsocketPackets<T> {
+  type Error = EncodeError;
+
+  fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    Sink::<DecodedPacket>::poll_ready(Pin::new(&mut self.get_mut().framed), cx)
+  }
+
+  fn start_send(self: Pin<&mut Self>, packet: DecodedPacket) -> Result<(), Self::Error> {
+    Pin::new(&mut self.get_mut().framed).start_send(packet)
+  }
+
+  fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    Sink::<DecodedPacket>::poll_flush(Pin::new(&mut self.get_mut().framed), cx)
+  }
+
+  fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    Sink::<DecodedPacket>::poll_close(Pin::new(&mut self.get_mut().framed), cx)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use bytes::{BufMut, BytesMut};
+  use futures::{SinkExt, StreamExt};
+  use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+  use crate::types::{PingReqPacket, PingRespPacket};
+  use super::*;
+
+  #[tokio::test]
+  async fn control_frames_test() {
+    let (io, mut peer) = duplex(1024);
+    let mut packets = WebsocketPackets::new(io);
+
+    // a ping, a PINGREQ and a close with status code 1000
+    let mut frames = BytesMut::from(&[0b10001001, 0x02, b'h', b'i', 0b10000010, 0x02, 0xC0, 0x00][..]);
+    frames.put_slice(&[0b10001000, 0x02, 0x03, 0xE8]);
+    peer.write_all(&frames).await.unwrap();
+
+    assert_eq!(packets.next().await.unwrap().unwrap(), DecodedPacket::PingReq(PingReqPacket {}));
+    packets.send(DecodedPacket::PingResp(PingRespPacket {})).await.unwrap();
+    assert!(packets.next().await.is_none());
+
+    // the pong went out before the PINGRESP, and the close is echoed last
+    let expected = [0b10001010, 0x02, b'h', b'i', 0b10000010, 0x02, 0xD0, 0x00, 0b10001000, 0x02, 0x03, 0xE8];
+    let mut received = [0; 12];
+    peer.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, expected);
+  }
+
+  #[tokio::test]
+  async fn invalid_close_code_test() {
+    let (io, mut peer) = duplex(1024);
+    let mut packets = WebsocketPackets::new(io);
+
+    // a close with status code 1005, which only stands for a missing status code
+    peer.write_all(&[0b10001000, 0x02, 0x03, 0xED]).await.unwrap();
+    assert!(matches!(packets.next().await, Some(Err(DecodeError::InvalidWebsocketCloseCode(1005)))));
+    assert!(packets.next().await.is_none());
+
+    let mut received = [0; 4];
+    peer.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, [0b10001000, 0x02, 0x03, 0xEA]);
+  }
+}
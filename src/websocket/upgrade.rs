@@ -8,11 +8,10 @@ use crate::{
   utils::decode_utf8_with_length
 };
 
-fn convert_headers_to_hashmap<'a>(headers: &'a str) -> Result<HashMap<&'a str, &'a str>, DecodeError> {
+fn convert_headers_to_hashmap(headers: &str) -> Result<HashMap<&str, &str>, DecodeError> {
   let mut hashmap = HashMap::new();
 
-  let mut lines = headers.split("\r\n");
-  while let Some(line) = lines.next() {
+  for line in headers.split("\r\n") {
     let mut items = line.splitn(2, ": ");
     let key = match items.next() {
       Some(key) => key,
@@ -38,20 +37,20 @@ impl Decoder for WebsocketUpgradeCodec {
     let headers = convert_headers_to_hashmap(&headers)?;
 
     if headers.get("Upgrade") != Some(&"websocket") {
-      return Err(DecodeError::FormatError);
+      return Err(DecodeError::InvalidUpgradeHeader("Upgrade"));
     }
 
     if headers.get("Connection") != Some(&"Upgrade") {
-      return Err(DecodeError::FormatError);
+      return Err(DecodeError::InvalidUpgradeHeader("Connection"));
     }
 
     if headers.get("Sec-WebSocket-Protocol") != Some(&"mqtt") {
-      return Err(DecodeError::FormatError);
+      return Err(DecodeError::InvalidUpgradeHeader("Sec-WebSocket-Protocol"));
     }
 
     let websocket_key = match headers.get("Sec-WebSocket-Key") {
       Some(key) => key,
-      None => return Err(DecodeError::FormatError)
+      None => return Err(DecodeError::InvalidUpgradeHeader("Sec-WebSocket-Key"))
     };
 
     let mut hasher = Sha1::new();